@@ -0,0 +1,255 @@
+use crate::Position;
+
+// The editing verbs, kept apart from Document so something other than a Vec of
+// rows could sit behind the editor.
+//
+// A position is a row y and a grapheme x in it, where x at the row's length is
+// the end of the row, just before its newline. Positions past the end of a row
+// (or of the buffer) are pulled back to the nearest real one. Inserting text at a
+// position and then deleting from there to wherever insert_str says it ended
+// leaves the buffer as it was.
+pub trait TextBuffer {
+    fn row_count(&self) -> usize;
+
+    // graphemes in row y, 0 past the last row
+    fn row_len(&self, y: usize) -> usize;
+
+    // splits the text into rows at newlines, returns where the end of it ended up
+    fn insert_str(&mut self, at: &Position, text: &str) -> Position;
+
+    // removes everything from start up to end, joining their rows
+    // the two can be given in either order
+    fn delete_range(&mut self, start: &Position, end: &Position);
+
+    // deletes the grapheme at at, or at the end of a row, the newline,
+    // which joins the next row onto this one
+    fn delete(&mut self, at: &Position);
+
+    fn insert(&mut self, at: &Position, c: char) {
+        self.insert_str(at, c.encode_utf8(&mut [0; 4]));
+    }
+
+    // splits the row at at, returns the start of the new row
+    fn newline(&mut self, at: &Position) -> Position {
+        self.insert_str(at, "\n")
+    }
+
+    // puts row y + 1 on the end of row y
+    fn join(&mut self, y: usize) {
+        if y + 1 < self.row_count() {
+            let end = Position {
+                x: self.row_len(y),
+                y,
+            };
+            self.delete_range(&end, &Position { x: 0, y: y + 1 });
+        }
+    }
+
+    // overwrites the grapheme at at, or appends at the end of a row
+    // newlines are still inserted so that Enter splits the row as usual
+    fn replace(&mut self, at: &Position, c: char) {
+        if c != '\n' && at.y < self.row_count() && at.x < self.row_len(at.y) {
+            self.delete(at);
+        }
+        self.insert(at, c);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Document;
+    use proptest::prelude::*;
+
+    // xorshift, so a failing run can be repeated from its seed
+    pub(crate) struct Rng(u64);
+
+    impl Rng {
+        pub(crate) fn new(seed: u64) -> Self {
+            Self(seed.max(1))
+        }
+
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        // 0 up to but not including n
+        pub(crate) fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        pub(crate) fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+            &items[self.below(items.len())]
+        }
+    }
+
+    // the same verbs on a Vec of rows of chars, written out the long way
+    struct Model(Vec<Vec<char>>);
+
+    impl Model {
+        fn contents(&self) -> String {
+            let rows: Vec<String> = self.0.iter().map(|row| row.iter().collect()).collect();
+            rows.join("\n")
+        }
+
+        fn clamp(&self, at: &Position) -> Position {
+            Position {
+                x: at.x.min(self.row_len(at.y)),
+                y: at.y,
+            }
+        }
+
+        // where delete's grapheme ends
+        fn step(&self, at: &Position) -> Position {
+            if at.x < self.row_len(at.y) {
+                Position { x: at.x + 1, y: at.y }
+            } else if at.y + 1 >= self.0.len() {
+                Position {
+                    x: self.row_len(at.y),
+                    y: at.y,
+                }
+            } else {
+                Position { x: 0, y: at.y + 1 }
+            }
+        }
+    }
+
+    impl TextBuffer for Model {
+        fn row_count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn row_len(&self, y: usize) -> usize {
+            self.0.get(y).map_or(0, Vec::len)
+        }
+
+        fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+            if at.y >= self.0.len() {
+                return at.clone();
+            }
+            let at = self.clamp(at);
+            let tail = self.0[at.y].split_off(at.x);
+            let mut y = at.y;
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    y += 1;
+                    self.0.insert(y, Vec::new());
+                }
+                self.0[y].extend(line.chars());
+            }
+            let end = Position {
+                x: self.0[y].len(),
+                y,
+            };
+            self.0[y].extend(tail);
+            end
+        }
+
+        fn delete_range(&mut self, start: &Position, end: &Position) {
+            let (start, end) = if start <= end { (start, end) } else { (end, start) };
+            if start.y >= self.0.len() {
+                return;
+            }
+            let end = if end.y >= self.0.len() {
+                let y = self.0.len() - 1;
+                Position { x: self.0[y].len(), y }
+            } else {
+                self.clamp(end)
+            };
+            let start = self.clamp(start);
+            let tail = self.0[end.y].split_off(end.x);
+            self.0.drain(start.y + 1..=end.y);
+            self.0[start.y].truncate(start.x);
+            self.0[start.y].extend(tail);
+        }
+
+        fn delete(&mut self, at: &Position) {
+            let end = self.step(at);
+            self.delete_range(at, &end);
+        }
+    }
+
+    // Positions are drawn as any numbers and only fitted to the buffer when
+    // the edit is made, since what's in it by then depends on the edits before.
+    #[derive(Clone, Debug)]
+    enum Op {
+        // and maybe take it straight back out again
+        Insert(At, String, bool),
+        DeleteRange(At, At),
+        Delete(At),
+        Join(usize),
+    }
+
+    #[derive(Clone, Debug)]
+    struct At(usize, usize);
+
+    impl At {
+        // anywhere in the buffer, a little past the end of a row or of the buffer
+        fn on<B: TextBuffer>(&self, buffer: &B) -> Position {
+            let y = self.1 % (buffer.row_count() + 1);
+            Position {
+                x: self.0 % (buffer.row_len(y) + 3),
+                y,
+            }
+        }
+    }
+
+    fn at() -> impl Strategy<Value = At> {
+        (any::<usize>(), any::<usize>()).prop_map(|(x, y)| At(x, y))
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (at(), "[ab \t\u{e9}\u{4e2d}\n]{0,4}", any::<bool>()).prop_map(|(at, text, back)| Op::Insert(at, text, back)),
+            (at(), at()).prop_map(|(start, end)| Op::DeleteRange(start, end)),
+            at().prop_map(Op::Delete),
+            any::<usize>().prop_map(Op::Join),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn edits_on_a_document_match_the_same_edits_on_a_model(ops in prop::collection::vec(op(), 0..100)) {
+            let lines = vec!["one".to_string(), String::new(), "two\tthree".to_string()];
+            let mut document = Document::from_lines(&lines);
+            let mut model = Model(lines.iter().map(|line| line.chars().collect()).collect());
+            for op in ops {
+                match op {
+                    Op::Insert(at, text, back) => {
+                        let at = at.on(&model);
+                        let before = model.contents();
+                        let start = model.clamp(&at);
+                        let end = document.insert_str(&at, &text);
+                        prop_assert_eq!(&end, &model.insert_str(&at, &text));
+                        prop_assert_eq!(document.contents(), model.contents());
+                        if back {
+                            document.delete_range(&start, &end);
+                            model.delete_range(&start, &end);
+                            prop_assert_eq!(document.contents(), before);
+                        }
+                    }
+                    Op::DeleteRange(start, end) => {
+                        let (start, end) = (start.on(&model), end.on(&model));
+                        document.delete_range(&start, &end);
+                        model.delete_range(&start, &end);
+                    }
+                    Op::Delete(at) => {
+                        let at = at.on(&model);
+                        document.delete(&at);
+                        model.delete(&at);
+                    }
+                    Op::Join(y) => {
+                        let y = y % (model.row_count() + 1);
+                        document.join(y);
+                        model.join(y);
+                    }
+                }
+                prop_assert_eq!(document.contents(), model.contents());
+                prop_assert_eq!(document.row_count(), model.row_count());
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// A little calculator for Ctrl-X =: numbers, + - * /, unary minus and
+// parentheses, with the usual precedence. Errors are said in words, for the
+// message bar.
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = Parser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        at: 0,
+    };
+    let value = parser.sum()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected {}", c)),
+    }
+}
+
+// whole numbers without the .0, anything else as short as it'll go
+pub fn format(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    // terms joined by + and -
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.at += 1;
+            let rhs = self.product()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    // factors joined by * and /
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.at += 1;
+            let rhs = self.factor()?;
+            if op == '*' {
+                value *= rhs;
+            } else if rhs == 0.0 {
+                return Err("division by zero".to_string());
+            } else {
+                value /= rhs;
+            }
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some('-') => {
+                self.at += 1;
+                Ok(-self.factor()?)
+            }
+            Some('+') => {
+                self.at += 1;
+                self.factor()
+            }
+            Some('(') => {
+                self.at += 1;
+                let value = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err("missing )".to_string());
+                }
+                self.at += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.at;
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.at += 1;
+                }
+                let number: String = self.chars[start..self.at].iter().collect();
+                number.parse().map_err(|_| format!("bad number {}", number))
+            }
+            Some(c) => Err(format!("unexpected {}", c)),
+            None => Err("expression ends too soon".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_unary_minus_and_parentheses() {
+        assert_eq!(evaluate("1+2*3"), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(evaluate("-2 * -(3 - 5)"), Ok(-4.0));
+        assert_eq!(evaluate("10 - 4 - 3"), Ok(3.0));
+        assert_eq!(evaluate("1 / 4"), Ok(0.25));
+    }
+
+    #[test]
+    fn errors_say_what_went_wrong() {
+        assert_eq!(evaluate("1/0"), Err("division by zero".to_string()));
+        assert_eq!(evaluate("(1"), Err("missing )".to_string()));
+        assert_eq!(evaluate("1.2.3"), Err("bad number 1.2.3".to_string()));
+        assert_eq!(evaluate("2 x"), Err("unexpected x".to_string()));
+        assert_eq!(evaluate("1 +"), Err("expression ends too soon".to_string()));
+    }
+
+    #[test]
+    fn whole_numbers_are_formatted_without_a_fraction() {
+        assert_eq!(format(2.0), "2");
+        assert_eq!(format(-3.0), "-3");
+        assert_eq!(format(0.25), "0.25");
+    }
+}
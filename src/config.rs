@@ -0,0 +1,574 @@
+use crate::CursorShape;
+use log::{info, warn};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use termion::color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineNumberMode {
+    Off,
+    Absolute,
+    // distance from the cursor line, which shows 0
+    Relative,
+    // the cursor line shows its absolute number, the rest are relative
+    Hybrid,
+}
+
+impl LineNumberMode {
+    // the order Ctrl-N cycles through
+    pub fn next(self) -> Self {
+        match self {
+            LineNumberMode::Off => LineNumberMode::Absolute,
+            LineNumberMode::Absolute => LineNumberMode::Relative,
+            LineNumberMode::Relative => LineNumberMode::Hybrid,
+            LineNumberMode::Hybrid => LineNumberMode::Off,
+        }
+    }
+}
+
+impl FromStr for LineNumberMode {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(LineNumberMode::Off),
+            "absolute" => Ok(LineNumberMode::Absolute),
+            "relative" => Ok(LineNumberMode::Relative),
+            "hybrid" => Ok(LineNumberMode::Hybrid),
+            _ => Err(()),
+        }
+    }
+}
+
+// how Ctrl-V inserts a control character
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlCharStyle {
+    // the byte itself
+    Raw,
+    // ^ followed by a letter, e.g. ^[ for Esc
+    Caret,
+}
+
+impl FromStr for ControlCharStyle {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "raw" => Ok(ControlCharStyle::Raw),
+            "caret" => Ok(ControlCharStyle::Caret),
+            _ => Err(()),
+        }
+    }
+}
+
+// how a row longer than max_line_length is flagged
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LongLineStyle {
+    // the part past the limit gets a background
+    Overflow,
+    // the row's line number is drawn in the colour instead, or the overflow
+    // with line numbers off
+    Number,
+}
+
+impl FromStr for LongLineStyle {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "overflow" => Ok(LongLineStyle::Overflow),
+            "number" => Ok(LongLineStyle::Number),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchCase {
+    // case only matters once the query has an uppercase letter in it
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+impl SearchCase {
+    // the order Alt-C cycles through while searching
+    pub fn next(self) -> Self {
+        match self {
+            SearchCase::Smart => SearchCase::Sensitive,
+            SearchCase::Sensitive => SearchCase::Insensitive,
+            SearchCase::Insensitive => SearchCase::Smart,
+        }
+    }
+
+    pub fn ignores_case(self, query: &str) -> bool {
+        match self {
+            SearchCase::Smart => !query.chars().any(char::is_uppercase),
+            SearchCase::Sensitive => false,
+            SearchCase::Insensitive => true,
+        }
+    }
+}
+
+impl FromStr for SearchCase {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "smart" => Ok(SearchCase::Smart),
+            "sensitive" => Ok(SearchCase::Sensitive),
+            "insensitive" => Ok(SearchCase::Insensitive),
+            _ => Err(()),
+        }
+    }
+}
+
+// a colour written as #rrggbb
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub color::Rgb);
+
+impl FromStr for Color {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.strip_prefix('#').ok_or(())?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(());
+        }
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| ());
+        Ok(Color(color::Rgb(channel(0)?, channel(2)?, channel(4)?)))
+    }
+}
+
+// words like TODO to pick out in comments, split by spaces or commas, each
+// with its own colour if it's written as WORD:#rrggbb
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommentTags(pub Vec<(String, Option<Color>)>);
+
+impl FromStr for CommentTags {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut tags = Vec::new();
+        for tag in value.split([' ', ',']).filter(|tag| !tag.is_empty()) {
+            tags.push(match tag.split_once(':') {
+                Some((word, color)) => (word.to_string(), Some(color.parse()?)),
+                None => (tag.to_string(), None),
+            });
+        }
+        Ok(CommentTags(tags))
+    }
+}
+
+impl CommentTags {
+    pub fn color_of(&self, word: &str, default: Color) -> Option<Color> {
+        self.0
+            .iter()
+            .find(|(tag, _)| tag == word)
+            .map(|(_, color)| color.unwrap_or(default))
+    }
+}
+
+// Settings read from $XDG_CONFIG_HOME/milli/config (or ~/.config/milli/config)
+// The file is just `key = value` lines, with # for comments
+// anything missing or unparseable keeps its default
+// Lines after a [filetype.rust] header only apply to files of that syntax,
+// on top of everything else, until the next header.
+#[derive(Clone)]
+pub struct Config {
+    // width of a literal tab
+    pub tab_stop: usize,
+    // how far Tab and auto-indent go in with spaces, 0 means tab_stop
+    pub indent_width: usize,
+    // whether Tab inserts spaces instead of a literal tab
+    pub expand_tabs: bool,
+    // sniff tab_stop/expand_tabs from the file being opened
+    pub detect_indent: bool,
+    // carry on from the other end of the document when search runs out of matches
+    pub wrap_search: bool,
+    // smart, sensitive or insensitive, toggled with Alt-C while searching
+    pub search_case: SearchCase,
+    // the line number gutter, toggled at runtime with Ctrl-N
+    pub line_numbers: LineNumberMode,
+    // seconds without a keypress before a modified file is saved, 0 turns it off
+    pub auto_save_after: u64,
+    // copy the file to a .bak the first time it's changed
+    pub backup: bool,
+    // where backups go, empty means next to the file
+    pub backup_dir: String,
+    // leave the backup behind after quitting
+    pub keep_backup: bool,
+    // drawn at the start of rows past the end of the document, can be empty
+    pub empty_row_marker: String,
+    // the name and version a third of the way down an empty, unnamed buffer
+    pub show_welcome: bool,
+    // draws that marker faded, so it stands apart from the text
+    pub dim_empty_rows: bool,
+    // drawn at the left and right edges of rows with more text off that side,
+    // either can be empty to turn it off
+    pub precedes_marker: String,
+    pub extends_marker: String,
+    // default, block, underline or bar
+    pub insert_cursor: CursorShape,
+    pub overwrite_cursor: CursorShape,
+    // turning either bar off gives its row back to the text
+    pub status_bar: bool,
+    pub message_bar: bool,
+    // the status bar counts lines and columns from 0, like offsets, instead of 1
+    pub zero_based_position: bool,
+    // the most columns the file name gets in the status bar, a longer path loses
+    // directories from the front so the file's own name stays in view
+    pub status_name_width: usize,
+    // control characters in the bars, from file names or typed into a prompt, are
+    // shown as ^X, otherwise they're left out (tabs and newlines are spaces either way)
+    pub escape_bar_control_chars: bool,
+    pub control_chars: ControlCharStyle,
+    // the column Alt-Q rewraps paragraphs to
+    pub text_width: usize,
+    // shades the column just past text_width
+    pub ruler: bool,
+    // shades the cursor's column on every row, toggled with Ctrl-X c
+    pub cursor_column: bool,
+    pub cursor_column_color: Color,
+    // a faint block under the cursor on an empty row, for terminals whose
+    // cursor is easy to lose there
+    pub empty_row_cursor: bool,
+    pub empty_row_cursor_color: Color,
+    // undo steps kept per buffer, a burst of typing counts as one
+    pub max_undo: usize,
+    // typing into a block selection pads rows that stop short of it,
+    // otherwise those rows are left alone
+    pub block_pad_short_rows: bool,
+    // repeats of a movement key that pile up while drawing are handled before the next redraw
+    pub coalesce_keys: bool,
+    // lets the cursor move past the end of a row, typing there pads the row out with spaces
+    pub virtual_edit: bool,
+    // milliseconds to wait for the next key of a binding like Ctrl-X Ctrl-S, 0 waits forever
+    pub chord_timeout: u64,
+    // rows Ctrl-E, Ctrl-W and the mouse wheel move the view by, leaving the cursor be
+    pub scroll_step: usize,
+    // rows kept between the cursor and the top or bottom of the screen, where the file allows
+    pub scroll_off: usize,
+    // the terminal reports the mouse wheel, at the cost of selecting text with
+    // the mouse (most terminals still do that with Shift held)
+    pub mouse_wheel: bool,
+    // flags rows wider than max_line_length columns, toggled with Ctrl-X l
+    pub long_lines: bool,
+    pub max_line_length: usize,
+    // overflow or number
+    pub long_line_style: LongLineStyle,
+    pub long_line_color: Color,
+    // an overview of the whole file down the right edge, toggled with Ctrl-X m
+    pub minimap: bool,
+    // files with more bytes or rows than these open without highlighting, word
+    // matches or the minimap, until Ctrl-X f turns them back on, 0 for no limit
+    pub large_file_bytes: u64,
+    pub large_file_lines: usize,
+    // a row longer than this is taken to be minified, and isn't highlighted
+    // 0 highlights rows whatever their length
+    pub minified_line_length: usize,
+    // what Ctrl-X b pipes the buffer through to lay it out again, e.g. `jq .`
+    pub prettify_command: String,
+    // ring the terminal bell when something fails or isn't found,
+    // or with visual_bell as well, flash the screen instead
+    pub bell: bool,
+    pub visual_bell: bool,
+    // Ctrl-K also copies what it killed to the system clipboard, through the
+    // terminal (OSC 52), so it can be pasted into other programs
+    pub kill_to_clipboard: bool,
+    // Ctrl-X p keeps the colours and cursor moves in the snapshot, for
+    // cat-ing back to a terminal, instead of writing just the text
+    pub snapshot_ansi: bool,
+    // ask before saving over a file that's read-only or was changed by someone else
+    pub confirm_overwrite: bool,
+    // what a save that was refused permission can be retried through, given
+    // the file name with the buffer on stdin, empty to not offer it. sudo
+    // can't ask for a password while the editor has the terminal, so -n only
+    // works while sudo remembers you; `sudo -A tee` with an askpass helper
+    // asks in a window of its own
+    pub sudo_command: String,
+    // hold an advisory lock (flock) on the open file, and open files someone
+    // else holds one on read-only; off by default since not every platform
+    // or filesystem takes locks the same way
+    pub lock_files: bool,
+    // Ctrl-X t and Ctrl-X T convert blanks anywhere in a row, not just its indentation
+    pub retab_whole_line: bool,
+    // Enter starts the new row at the same indentation as the one it came from
+    pub auto_indent: bool,
+    // and one level further in after a block opener like {, with a closer right
+    // after the cursor getting a row of its own below
+    pub indent_after_opener: bool,
+    // Enter in a line comment starts the next row with the comment marker too,
+    // and Enter on a row that's only the marker takes it away again
+    pub continue_comments: bool,
+    // a pasted block is shifted in or out as a whole to sit at the cursor's indentation
+    pub reindent_on_paste: bool,
+    // joining rows (Backspace at the start of one, Delete at the end of one) leaves
+    // a single space between their words instead of whatever whitespace was there
+    pub join_collapse_whitespace: bool,
+    // opening the file that's already open reads it from disk again, as long as
+    // there's nothing unsaved, instead of carrying on in the buffer
+    pub reopen_open_file: bool,
+    // a sign next to every row edited since the last save, git or no git
+    pub touched_signs: bool,
+    pub touched_sign_color: Color,
+    // shades other occurrences of the word under the cursor, toggled with Ctrl-X h
+    pub highlight_word: bool,
+    pub word_highlight_color: Color,
+    // drawn with a background where they show up in a comment, which is
+    // comment_tag_color for any without a colour of their own
+    pub comment_tags: CommentTags,
+    pub comment_tag_color: Color,
+    // besides letters and digits, the characters that count as part of a word,
+    // e.g. _- to treat foo-bar as one; empty means letters and digits only
+    pub word_chars: String,
+    // the lines under each [filetype.name], by syntax name
+    filetypes: Vec<(String, Vec<(String, String)>)>,
+}
+
+// which part of the config file a line is in
+enum Section {
+    Global,
+    Filetype(usize),
+    Unknown,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_stop: 4,
+            indent_width: 0,
+            expand_tabs: true,
+            detect_indent: true,
+            wrap_search: true,
+            search_case: SearchCase::Smart,
+            line_numbers: LineNumberMode::Off,
+            auto_save_after: 0,
+            backup: false,
+            backup_dir: String::new(),
+            keep_backup: true,
+            empty_row_marker: "~".to_string(),
+            show_welcome: true,
+            dim_empty_rows: true,
+            precedes_marker: "<".to_string(),
+            extends_marker: ">".to_string(),
+            insert_cursor: CursorShape::Bar,
+            overwrite_cursor: CursorShape::Block,
+            status_bar: true,
+            message_bar: true,
+            zero_based_position: false,
+            status_name_width: 30,
+            escape_bar_control_chars: true,
+            control_chars: ControlCharStyle::Raw,
+            text_width: 80,
+            ruler: false,
+            cursor_column: false,
+            cursor_column_color: Color(color::Rgb(48, 52, 58)),
+            empty_row_cursor: false,
+            empty_row_cursor_color: Color(color::Rgb(80, 86, 96)),
+            max_undo: 1000,
+            block_pad_short_rows: true,
+            coalesce_keys: true,
+            virtual_edit: false,
+            chord_timeout: 1000,
+            scroll_step: 3,
+            scroll_off: 0,
+            mouse_wheel: false,
+            long_lines: false,
+            max_line_length: 100,
+            long_line_style: LongLineStyle::Overflow,
+            long_line_color: Color(color::Rgb(220, 50, 47)),
+            minimap: false,
+            large_file_bytes: 64 * 1024 * 1024,
+            large_file_lines: 1_000_000,
+            minified_line_length: 5000,
+            prettify_command: String::new(),
+            bell: false,
+            visual_bell: false,
+            kill_to_clipboard: false,
+            snapshot_ansi: false,
+            confirm_overwrite: true,
+            sudo_command: "sudo -n tee".to_string(),
+            lock_files: false,
+            retab_whole_line: false,
+            auto_indent: true,
+            indent_after_opener: true,
+            continue_comments: true,
+            reindent_on_paste: true,
+            join_collapse_whitespace: false,
+            reopen_open_file: false,
+            touched_signs: true,
+            touched_sign_color: Color(color::Rgb(108, 113, 196)),
+            highlight_word: true,
+            word_highlight_color: Color(color::Rgb(73, 80, 86)),
+            comment_tags: "TODO FIXME HACK NOTE".parse().unwrap_or(CommentTags(Vec::new())),
+            comment_tag_color: Color(color::Rgb(108, 71, 0)),
+            word_chars: "_".to_string(),
+            filetypes: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return config,
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return config,
+        };
+        info!("loading config from {}", path.display());
+        let mut section = Section::Global;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                section = match header.trim().strip_prefix("filetype.") {
+                    Some(name) => {
+                        config.filetypes.push((name.trim().to_string(), Vec::new()));
+                        Section::Filetype(config.filetypes.len() - 1)
+                    }
+                    None => {
+                        warn!("ignoring config section: {}", line);
+                        Section::Unknown
+                    }
+                };
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let (key, value) = (key.trim(), value.trim());
+                match section {
+                    Section::Global => config.set(key, value),
+                    Section::Filetype(index) => config.filetypes[index].1.push((key.to_string(), value.to_string())),
+                    Section::Unknown => (),
+                }
+            } else {
+                warn!("ignoring config line: {}", line);
+            }
+        }
+        config
+    }
+
+    // these settings with those for files of syntax laid over them,
+    // in the order they came in the file
+    pub fn for_filetype(&self, syntax: &str) -> Config {
+        let mut config = self.clone();
+        for (_, settings) in self.filetypes.iter().filter(|(name, _)| name == syntax) {
+            for (key, value) in settings {
+                config.set(key, value);
+            }
+        }
+        config
+    }
+
+    // the columns in one level of space indentation
+    pub fn indent_step(&self) -> usize {
+        if self.indent_width == 0 {
+            self.tab_stop
+        } else {
+            self.indent_width
+        }
+    }
+
+    fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("milli").join("config"))
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let ok = match key {
+            "tab_stop" => parse_into(value, &mut self.tab_stop),
+            "indent_width" => parse_into(value, &mut self.indent_width),
+            "expand_tabs" => parse_into(value, &mut self.expand_tabs),
+            "detect_indent" => parse_into(value, &mut self.detect_indent),
+            "wrap_search" => parse_into(value, &mut self.wrap_search),
+            "search_case" => parse_into(value, &mut self.search_case),
+            "line_numbers" => parse_into(value, &mut self.line_numbers),
+            "auto_save_after" => parse_into(value, &mut self.auto_save_after),
+            "backup" => parse_into(value, &mut self.backup),
+            "backup_dir" => parse_into(value, &mut self.backup_dir),
+            "keep_backup" => parse_into(value, &mut self.keep_backup),
+            "empty_row_marker" => parse_into(value, &mut self.empty_row_marker),
+            "show_welcome" => parse_into(value, &mut self.show_welcome),
+            "dim_empty_rows" => parse_into(value, &mut self.dim_empty_rows),
+            "precedes_marker" => parse_into(value, &mut self.precedes_marker),
+            "extends_marker" => parse_into(value, &mut self.extends_marker),
+            "insert_cursor" => parse_into(value, &mut self.insert_cursor),
+            "overwrite_cursor" => parse_into(value, &mut self.overwrite_cursor),
+            "status_bar" => parse_into(value, &mut self.status_bar),
+            "message_bar" => parse_into(value, &mut self.message_bar),
+            "zero_based_position" => parse_into(value, &mut self.zero_based_position),
+            "status_name_width" => parse_into(value, &mut self.status_name_width),
+            "escape_bar_control_chars" => parse_into(value, &mut self.escape_bar_control_chars),
+            "control_chars" => parse_into(value, &mut self.control_chars),
+            "text_width" => parse_into(value, &mut self.text_width),
+            "ruler" => parse_into(value, &mut self.ruler),
+            "cursor_column" => parse_into(value, &mut self.cursor_column),
+            "cursor_column_color" => parse_into(value, &mut self.cursor_column_color),
+            "empty_row_cursor" => parse_into(value, &mut self.empty_row_cursor),
+            "empty_row_cursor_color" => parse_into(value, &mut self.empty_row_cursor_color),
+            "max_undo" => parse_into(value, &mut self.max_undo),
+            "block_pad_short_rows" => parse_into(value, &mut self.block_pad_short_rows),
+            "coalesce_keys" => parse_into(value, &mut self.coalesce_keys),
+            "virtual_edit" => parse_into(value, &mut self.virtual_edit),
+            "chord_timeout" => parse_into(value, &mut self.chord_timeout),
+            "scroll_step" => parse_into(value, &mut self.scroll_step),
+            "scroll_off" => parse_into(value, &mut self.scroll_off),
+            "mouse_wheel" => parse_into(value, &mut self.mouse_wheel),
+            "long_lines" => parse_into(value, &mut self.long_lines),
+            "max_line_length" => parse_into(value, &mut self.max_line_length),
+            "long_line_style" => parse_into(value, &mut self.long_line_style),
+            "long_line_color" => parse_into(value, &mut self.long_line_color),
+            "minimap" => parse_into(value, &mut self.minimap),
+            "large_file_bytes" => parse_into(value, &mut self.large_file_bytes),
+            "large_file_lines" => parse_into(value, &mut self.large_file_lines),
+            "minified_line_length" => parse_into(value, &mut self.minified_line_length),
+            "prettify_command" => parse_into(value, &mut self.prettify_command),
+            "bell" => parse_into(value, &mut self.bell),
+            "visual_bell" => parse_into(value, &mut self.visual_bell),
+            "kill_to_clipboard" => parse_into(value, &mut self.kill_to_clipboard),
+            "snapshot_ansi" => parse_into(value, &mut self.snapshot_ansi),
+            "confirm_overwrite" => parse_into(value, &mut self.confirm_overwrite),
+            "sudo_command" => parse_into(value, &mut self.sudo_command),
+            "lock_files" => parse_into(value, &mut self.lock_files),
+            "retab_whole_line" => parse_into(value, &mut self.retab_whole_line),
+            "auto_indent" => parse_into(value, &mut self.auto_indent),
+            "indent_after_opener" => parse_into(value, &mut self.indent_after_opener),
+            "continue_comments" => parse_into(value, &mut self.continue_comments),
+            "reindent_on_paste" => parse_into(value, &mut self.reindent_on_paste),
+            "join_collapse_whitespace" => parse_into(value, &mut self.join_collapse_whitespace),
+            "reopen_open_file" => parse_into(value, &mut self.reopen_open_file),
+            "touched_signs" => parse_into(value, &mut self.touched_signs),
+            "touched_sign_color" => parse_into(value, &mut self.touched_sign_color),
+            "highlight_word" => parse_into(value, &mut self.highlight_word),
+            "word_highlight_color" => parse_into(value, &mut self.word_highlight_color),
+            "comment_tags" => parse_into(value, &mut self.comment_tags),
+            "comment_tag_color" => parse_into(value, &mut self.comment_tag_color),
+            "word_chars" => parse_into(value, &mut self.word_chars),
+            _ => false,
+        };
+        if !ok {
+            warn!("ignoring config setting: {} = {}", key, value);
+        }
+    }
+}
+
+fn parse_into<T: FromStr>(value: &str, field: &mut T) -> bool {
+    match value.parse() {
+        Ok(parsed) => {
+            *field = parsed;
+            true
+        }
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,161 @@
+// how a line of the new text differs from the old one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    // lines were taken out just after this one (or before it, at the very top)
+    Deleted,
+}
+
+// past this many cells in the table the middle is just called one big change,
+// rather than spending the memory to find out exactly what moved
+const MAX_TABLE: usize = 4_000_000;
+
+// A plain longest-common-subsequence diff by lines, after trimming whatever
+// both ends have in common. Gives each changed line of new, by index.
+pub fn line_changes(old: &[&str], new: &[&str]) -> Vec<(usize, LineChange)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    // counts for the run of changes since the last kept line, where y is
+    // the line of new that comes next
+    let mut changes = Vec::new();
+    let mut removed = 0;
+    let mut added = 0;
+    let mut y = prefix;
+    let mut flush = |y: usize, removed: &mut usize, added: &mut usize| {
+        hunk(&mut changes, y - *added, *removed, *added);
+        *removed = 0;
+        *added = 0;
+    };
+    for step in steps(old_middle, new_middle) {
+        match step {
+            Step::Keep => {
+                flush(y, &mut removed, &mut added);
+                y += 1;
+            }
+            Step::Remove => removed += 1,
+            Step::Add => {
+                added += 1;
+                y += 1;
+            }
+        }
+    }
+    flush(y, &mut removed, &mut added);
+    changes
+}
+
+// one run of removals and additions starting at line start of new:
+// as many lines as were replaced count as modified, the rest as added
+fn hunk(changes: &mut Vec<(usize, LineChange)>, start: usize, removed: usize, added: usize) {
+    if added == 0 {
+        if removed > 0 {
+            changes.push((start.saturating_sub(1), LineChange::Deleted));
+        }
+        return;
+    }
+    for offset in 0..added {
+        let change = if offset < removed {
+            LineChange::Modified
+        } else {
+            LineChange::Added
+        };
+        changes.push((start + offset, change));
+    }
+}
+
+enum Step {
+    Keep,
+    Remove,
+    Add,
+}
+
+fn steps(old: &[&str], new: &[&str]) -> Vec<Step> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1) * (m + 1) > MAX_TABLE {
+        let mut steps: Vec<Step> = (0..n).map(|_| Step::Remove).collect();
+        steps.extend((0..m).map(|_| Step::Add));
+        return steps;
+    }
+    // lengths[i][j] is the longest common run of old[i..] and new[j..]
+    let width = m + 1;
+    let mut lengths = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let mut steps = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            steps.push(Step::Keep);
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            steps.push(Step::Remove);
+            i += 1;
+        } else {
+            steps.push(Step::Add);
+            j += 1;
+        }
+    }
+    steps.extend((i..n).map(|_| Step::Remove));
+    steps.extend((j..m).map(|_| Step::Add));
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LineChange::*;
+
+    #[test]
+    fn added_and_modified_lines_are_marked_on_the_new_text() {
+        assert_eq!(line_changes(&["a", "c"], &["a", "b", "c"]), [(1, Added)]);
+        assert_eq!(line_changes(&["a", "b", "c"], &["a", "x", "c"]), [(1, Modified)]);
+        assert_eq!(line_changes(&[], &["a"]), [(0, Added)]);
+    }
+
+    #[test]
+    fn a_deletion_is_marked_on_the_line_before_it() {
+        assert_eq!(line_changes(&["a", "b", "c"], &["a", "c"]), [(0, Deleted)]);
+        // at either end there's only the one line next to it
+        assert_eq!(line_changes(&["a", "b", "c"], &["b", "c"]), [(0, Deleted)]);
+        assert_eq!(line_changes(&["a", "b", "c"], &["a", "b"]), [(1, Deleted)]);
+    }
+
+    #[test]
+    fn what_replaces_fewer_lines_is_modified_then_added() {
+        let old = ["a", "b", "c", "d", "e"];
+        let new = ["a", "x", "y", "z", "d", "f"];
+        assert_eq!(line_changes(&old, &new), [(1, Modified), (2, Modified), (3, Added), (5, Modified)]);
+    }
+
+    #[test]
+    fn a_middle_too_big_for_the_table_is_one_change() {
+        let old: Vec<String> = (0..2001).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..2001).map(|i| format!("new {}", i)).collect();
+        let mut old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut new: Vec<&str> = new.iter().map(String::as_str).collect();
+        new.push("added");
+        // a line the two have in common isn't looked for
+        old[1000] = "same";
+        new[1000] = "same";
+        let changes = line_changes(&old, &new);
+        assert_eq!(changes.len(), 2002);
+        assert!(changes[..2001].iter().enumerate().all(|(i, change)| *change == (i, Modified)));
+        assert_eq!(changes[2001], (2001, Added));
+    }
+}
@@ -1,36 +1,1349 @@
+use crate::history::{Change, History};
+use crate::HighlightState;
+use crate::Position;
 use crate::Row;
+use crate::SearchDirection;
+use crate::SyntaxDefinition;
+use crate::TextBuffer;
 use std::fs;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
+
+// files bigger than this show their loading progress
+const LOADING_THRESHOLD: u64 = 4 * 1024 * 1024;
+const LOADING_CHUNK_SIZE: usize = 256 * 1024;
+
+// how many rows are looked at when guessing the indentation of a file
+const INDENT_SAMPLE_ROWS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentStyle {
+    Tabs,
+    Spaces(usize),
+}
+
+impl Default for IndentStyle {
+    fn default() -> Self {
+        IndentStyle::Spaces(4)
+    }
+}
+
+// what goes between rows when the file is saved
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Unix,
+    Dos,
+    // old Mac OS, a lone \r
+    Mac,
+}
+
+impl LineEnding {
+    pub const ALL: [LineEnding; 3] = [LineEnding::Unix, LineEnding::Dos, LineEnding::Mac];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "\n",
+            LineEnding::Dos => "\r\n",
+            LineEnding::Mac => "\r",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+            LineEnding::Mac => "mac",
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|ending| ending.name() == name)
+    }
+
+    // going by the first line ending in the file
+    fn detect(contents: &str) -> Self {
+        match contents.find(['\n', '\r']).map(|index| &contents[index..]) {
+            Some(rest) if rest.starts_with("\r\n") => LineEnding::Dos,
+            Some(rest) if rest.starts_with('\r') => LineEnding::Mac,
+            _ => LineEnding::Unix,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
     pub file_name: Option<String>,
+    // what Tab inserts in this buffer
+    pub indent: IndentStyle,
+    // changed since it was last opened or saved
+    dirty: bool,
+    line_ending: LineEnding,
+    syntax: SyntaxDefinition,
+    // rows above this are known to be highlighted correctly
+    highlighted_until: usize,
+    // rows longer than this many graphemes aren't highlighted, 0 for no limit
+    highlight_limit: usize,
+    history: History,
+    // when the file was last written as far as we know, by us or before we opened it
+    disk_modified: Option<SystemTime>,
+    // a buffer for notes, until it's saved somewhere
+    scratch: bool,
+    // goes up with every change to the text, so others can tell when it moved on
+    revision: usize,
+    // which rows were edited since the last save, by row, anything past the end is false
+    touched: Vec<bool>,
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let file_contents = fs::read_to_string(filename)?;
+        Self::open_with_progress(filename, |_| {})
+    }
+
+    // a blank document for a file that doesn't exist yet, created on the first save
+    pub fn new_file(filename: &str) -> Self {
+        Self {
+            rows: vec![Row::default()],
+            file_name: Some(filename.to_string()),
+            syntax: SyntaxDefinition::for_file(filename),
+            ..Self::default()
+        }
+    }
+
+    pub fn scratch() -> Self {
+        Self {
+            rows: vec![Row::default()],
+            scratch: true,
+            ..Self::default()
+        }
+    }
+
+    // a document that isn't backed by a file, e.g. a directory listing
+    pub fn from_lines(lines: &[String]) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(&line[..])).collect(),
+            ..Self::default()
+        }
+    }
+
+    // files bigger than LOADING_THRESHOLD are read in chunks
+    // and progress gets the percentage read so far, each time it changes
+    pub fn open_with_progress<F>(filename: &str, mut progress: F) -> Result<Self, std::io::Error>
+    where
+        F: FnMut(u64),
+    {
+        let mut file = fs::File::open(filename)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let file_contents = if size > LOADING_THRESHOLD {
+            let mut bytes = Vec::with_capacity(size as usize);
+            let mut chunk = vec![0; LOADING_CHUNK_SIZE];
+            let mut last_percent = None;
+            loop {
+                let count = file.read(&mut chunk)?;
+                if count == 0 {
+                    break;
+                }
+                bytes.extend_from_slice(&chunk[..count]);
+                let percent = (bytes.len() as u64 * 100 / size).min(100);
+                if last_percent != Some(percent) {
+                    progress(percent);
+                    last_percent = Some(percent);
+                }
+            }
+            String::from_utf8(bytes)
+                .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?
+        } else {
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)?;
+            contents
+        };
 
         let mut rows: Vec<Row> = Vec::new();
-        for value in file_contents.lines() {
-            rows.push(Row::from(value));
+        // splitting on newlines rather than using lines() keeps the empty row
+        // after a trailing newline, so the cursor can get to the real end of the file
+        // and saving writes the file back the way it was
+        let line_ending = LineEnding::detect(&file_contents);
+        if line_ending == LineEnding::Mac {
+            rows.extend(file_contents.split('\r').map(Row::from));
+        } else {
+            for value in file_contents.split('\n') {
+                let value = value.strip_suffix('\r').unwrap_or(value);
+                rows.push(Row::from(value));
+            }
         }
 
+        let syntax = SyntaxDefinition::detect(filename, rows.first().map_or("", Row::as_str));
         Ok(Self {
             rows,
             file_name: Some(filename.to_string()),
+            indent: IndentStyle::default(),
+            dirty: false,
+            line_ending,
+            syntax,
+            highlighted_until: 0,
+            highlight_limit: 0,
+            history: History::default(),
+            disk_modified: metadata.modified().ok(),
+            scratch: false,
+            revision: 0,
+            touched: Vec::new(),
         })
     }
 
+    pub fn save(&mut self) -> Result<(), std::io::Error> {
+        if let Some(file_name) = &self.file_name {
+            self.write_rows(Path::new(file_name), 0..self.rows.len())?;
+            self.mark_saved()?;
+        }
+        Ok(())
+    }
+
+    // for when something else wrote the file out, e.g. sudo tee
+    pub fn mark_saved(&mut self) -> Result<(), std::io::Error> {
+        self.dirty = false;
+        self.touched.clear();
+        if let Some(file_name) = &self.file_name {
+            self.disk_modified = fs::metadata(file_name)?.modified().ok();
+        }
+        Ok(())
+    }
+
+    // the whole file, just as save would write it
+    pub fn contents(&self) -> String {
+        let mut bytes = Vec::new();
+        // writing to a Vec can't fail, and the rows are all strings already
+        let _ = self.write_rows_to(&mut bytes, 0..self.rows.len());
+        String::from_utf8(bytes).unwrap_or_default()
+    }
+
+    // Throws away what's changed since and reads the file again, as one
+    // change, so undo can still bring the changes back. Clean afterwards.
+    pub fn reload(&mut self) -> Result<(), std::io::Error> {
+        let file_name = match &self.file_name {
+            Some(file_name) => file_name.clone(),
+            None => return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no file to reload from")),
+        };
+        let fresh = Self::open(&file_name)?;
+        let text: Vec<&str> = fresh.rows.iter().map(Row::as_str).collect();
+        self.replace_all(&text.join("\n"));
+        self.dirty = false;
+        self.touched.clear();
+        self.line_ending = fresh.line_ending;
+        self.disk_modified = fresh.disk_modified;
+        Ok(())
+    }
+
+    // Writes some of the rows to path, leaving the document (and its own file)
+    // alone. The rows are written just as a save would write them, so rows
+    // from partway through end in a line ending like they did here.
+    pub fn write_rows(&self, path: &Path, rows: Range<usize>) -> Result<(), std::io::Error> {
+        self.write_rows_to(&mut fs::File::create(path)?, rows)
+    }
+
+    fn write_rows_to<W: Write>(&self, file: &mut W, rows: Range<usize>) -> Result<(), std::io::Error> {
+        let ending = self.line_ending.as_str().as_bytes();
+        let last = rows.end.min(self.rows.len());
+        for y in rows.start.min(last)..last {
+            if y > rows.start {
+                file.write_all(ending)?;
+            }
+            file.write_all(self.rows[y].as_str().as_bytes())?;
+        }
+        if last < self.rows.len() && rows.start < last {
+            file.write_all(ending)?;
+        }
+        Ok(())
+    }
+
+    // copies the file as it is on disk to a .bak, either next to it or in dir
+    // files in dir are named after their full path so they don't collide
+    pub fn write_backup(&self, dir: Option<&Path>) -> Result<Option<PathBuf>, std::io::Error> {
+        let file_name = match &self.file_name {
+            Some(file_name) => file_name,
+            None => return Ok(None),
+        };
+        let original = Path::new(file_name);
+        if !original.exists() {
+            return Ok(None);
+        }
+        let backup = match dir {
+            Some(dir) => {
+                let full_path = fs::canonicalize(original)?;
+                let flattened = full_path.to_string_lossy().replace('/', "%");
+                fs::create_dir_all(dir)?;
+                dir.join(format!("{}.bak", flattened))
+            }
+            None => PathBuf::from(format!("{}.bak", file_name)),
+        };
+        fs::copy(original, &backup)?;
+        Ok(Some(backup))
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    // the rows stay as they are, it's only the next save that changes
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if line_ending != self.line_ending {
+            self.line_ending = line_ending;
+            self.dirty = true;
+        }
+    }
+
+    pub fn syntax(&self) -> &SyntaxDefinition {
+        &self.syntax
+    }
+
+    // overrides the detected file type for this buffer
+    pub fn set_syntax(&mut self, syntax: SyntaxDefinition) {
+        self.syntax = syntax;
+        for row in &mut self.rows {
+            row.clear_highlighting();
+        }
+        self.highlighted_until = 0;
+    }
+
+    // makes sure every row before until is highlighted
+    // rows are cached, and a row is only done again if it changed or the row
+    // above it now ends differently, e.g. a string or block comment was opened
+    // or closed. so after an edit the rows below are redone until they end up
+    // in the same state as before.
+    // the rows above until have to be done too since each row starts in
+    // whatever state the previous one ended in
+    pub fn highlight(&mut self, until: usize) {
+        let until = until.min(self.rows.len());
+        let mut state = match self.highlighted_until.checked_sub(1) {
+            Some(previous) => self.rows[previous].ends_in(),
+            None => HighlightState::Normal,
+        };
+        let limit = self.highlight_limit;
+        for row in self.rows.iter_mut().take(until).skip(self.highlighted_until) {
+            if limit > 0 && row.len() > limit {
+                row.skip_highlighting(state);
+            } else if !row.is_highlighted(state) {
+                row.highlight(&self.syntax, state);
+            }
+            state = row.ends_in();
+        }
+        self.highlighted_until = self.highlighted_until.max(until);
+    }
+
+    // the row changed, so it and everything after it may need highlighting again
+    fn unhighlight_from(&mut self, y: usize) {
+        self.highlighted_until = self.highlighted_until.min(y);
+    }
+
+    // someone else wrote the file after we last read or saved it
+    pub fn changed_on_disk(&self) -> bool {
+        let on_disk = self
+            .file_name
+            .as_ref()
+            .and_then(|file_name| fs::metadata(file_name).ok())
+            .and_then(|metadata| metadata.modified().ok());
+        match (on_disk, self.disk_modified) {
+            (Some(on_disk), Some(known)) => on_disk > known,
+            _ => false,
+        }
+    }
+
+    // false for files that aren't there yet, those get created
+    pub fn read_only_on_disk(&self) -> bool {
+        self.file_name
+            .as_ref()
+            .and_then(|file_name| fs::metadata(file_name).ok())
+            .is_some_and(|metadata| metadata.permissions().readonly())
+    }
+
+    pub fn is_scratch(&self) -> bool {
+        self.scratch && self.file_name.is_none()
+    }
+
+    pub fn revision(&self) -> usize {
+        self.revision
+    }
+
+    // the rows edited since the file was last saved, in order
+    pub fn touched_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.touched.iter().enumerate().filter(|(_, touched)| **touched).map(|(y, _)| y)
+    }
+
+    fn touch(&mut self, y: usize) {
+        if self.touched.len() <= y {
+            self.touched.resize(y + 1, false);
+        }
+        self.touched[y] = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // nothing but a single blank row (or no rows at all)
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.rows.len() <= 1 && self.rows.iter().all(Row::is_empty)
+    }
+
+    // a document with no rows at all gets its first one, so the cursor has
+    // somewhere to be
+    pub fn ensure_row(&mut self) {
+        if self.rows.is_empty() {
+            self.rows.push(Row::default());
+        }
     }
 
     pub fn len(&self) -> usize {
         self.rows.len()
     }
 
+    // the text of every row, without newlines
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.rows.iter().map(Row::as_str)
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
+
+    // a new row after y starting with indent, without touching y itself
+    // returns the end of the indent, where typing carries on
+    pub fn insert_row_below(&mut self, y: usize, indent: &str) -> Position {
+        let end = Position { x: self.row_len(y), y };
+        self.insert_str(&end, &format!("\n{}", indent))
+    }
+
+    // the same, but the new row goes in before y and pushes it down
+    pub fn insert_row_above(&mut self, y: usize, indent: &str) -> Position {
+        self.insert_str(&Position { x: 0, y }, &format!("{}\n", indent));
+        Position {
+            x: indent.graphemes(true).count(),
+            y,
+        }
+    }
+
+    // puts row y + 1 on the end of row y, returns where its text starts now
+    // collapsing swaps the spaces and tabs around the seam for a single space,
+    // or for nothing when either row had nothing else on it
+    pub fn join_line(&mut self, y: usize, collapse: bool) -> Position {
+        let end = Position { x: self.row_len(y), y };
+        if y + 1 >= self.len() {
+            return end;
+        }
+        if !collapse {
+            self.join(y);
+            return end;
+        }
+        let upper = self.rows[y].as_str();
+        let trailing = upper.len() - upper.trim_end_matches([' ', '\t']).len();
+        let lower = &self.rows[y + 1];
+        let leading = lower.indentation().len();
+        let lower_blank = leading == lower.len();
+        let seam = Position { x: end.x - trailing, y };
+        self.delete_range(&seam, &Position { x: leading, y: y + 1 });
+        if seam.x == 0 || lower_blank {
+            seam
+        } else {
+            self.insert_str(&seam, " ")
+        }
+    }
+
+    // guesses whether the file is indented with tabs or spaces, and how many
+    // returns None if there's no indented rows to go off
+    pub fn detect_indent(&self) -> Option<IndentStyle> {
+        let mut tab_rows = 0;
+        let mut space_rows = 0;
+        // how often each increase in indentation shows up between rows
+        // single space steps are ignored, they're usually comment alignment
+        let mut steps = [0_usize; 9];
+        let mut previous_width = 0;
+
+        for row in self.rows.iter().take(INDENT_SAMPLE_ROWS) {
+            let indentation = row.indentation();
+            // whitespace only rows don't say anything about the indentation
+            if indentation.len() == row.len() {
+                continue;
+            }
+            if indentation.starts_with('\t') {
+                tab_rows += 1;
+                continue;
+            }
+            let width = indentation.len();
+            if width > 0 {
+                space_rows += 1;
+            }
+            if width > previous_width && width - previous_width < steps.len() {
+                steps[width - previous_width] += 1;
+            }
+            previous_width = width;
+        }
+
+        if tab_rows == 0 && space_rows == 0 {
+            None
+        } else if tab_rows > space_rows {
+            Some(IndentStyle::Tabs)
+        } else {
+            let mut best = 2;
+            for step in 2..steps.len() {
+                if steps[step] > steps[best] {
+                    best = step;
+                }
+            }
+            if steps[best] == 0 {
+                Some(IndentStyle::default())
+            } else {
+                Some(IndentStyle::Spaces(best))
+            }
+        }
+    }
+
+    // finds the next match of query starting at `at`, not including a match right at it
+    // when going backwards. with wrap the scan carries on from the other end
+    // of the document once it runs out of rows
+    pub fn find(
+        &self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+        wrap: bool,
+        ignore_case: bool,
+    ) -> Option<Position> {
+        if self.rows.is_empty() {
+            return None;
+        }
+        let last = self.rows.len() - 1;
+        match direction {
+            SearchDirection::Forward => {
+                for y in at.y..self.rows.len() {
+                    let x = if y == at.y { at.x } else { 0 };
+                    if let Some(x) = self.rows[y].find(query, x, direction, ignore_case) {
+                        return Some(Position { x, y });
+                    }
+                }
+                if wrap {
+                    for y in 0..=at.y.min(last) {
+                        if let Some(x) = self.rows[y].find(query, 0, direction, ignore_case) {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+            }
+            SearchDirection::Backward => {
+                for y in (0..=at.y.min(last)).rev() {
+                    let row = &self.rows[y];
+                    let x = if y == at.y { at.x } else { row.len() };
+                    if let Some(x) = row.find(query, x, direction, ignore_case) {
+                        return Some(Position { x, y });
+                    }
+                }
+                if wrap {
+                    for y in (at.y.min(last)..self.rows.len()).rev() {
+                        let row = &self.rows[y];
+                        if let Some(x) = row.find(query, row.len(), direction, ignore_case) {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // every non-overlapping match of query, in document order
+    pub fn find_all(&self, query: &str, ignore_case: bool) -> Vec<Position> {
+        let mut matches = Vec::new();
+        let step = query.graphemes(true).count().max(1);
+        for (y, row) in self.rows.iter().enumerate() {
+            let mut x = 0;
+            while let Some(found) = row.find(query, x, SearchDirection::Forward, ignore_case) {
+                matches.push(Position { x: found, y });
+                x = found + step;
+            }
+        }
+        matches
+    }
+
+    // where byte offset ends up, counting the line ending between rows
+    // an offset inside a character lands on the start of that character,
+    // and one past the end of the document lands at the end of it
+    pub fn position_of_offset(&self, offset: usize) -> Position {
+        let mut start = 0;
+        for (y, row) in self.rows.iter().enumerate() {
+            let end = start + row.as_str().len();
+            if offset <= end {
+                let x = row
+                    .as_str()
+                    .grapheme_indices(true)
+                    .take_while(|(index, grapheme)| start + index + grapheme.len() <= offset)
+                    .count();
+                return Position { x, y };
+            }
+            start = end + self.line_ending.as_str().len();
+        }
+        let y = self.len().saturating_sub(1);
+        Position {
+            x: self.rows.get(y).map_or(0, Row::len),
+            y,
+        }
+    }
+
+    pub fn set_highlight_limit(&mut self, limit: usize) {
+        if limit != self.highlight_limit {
+            self.highlight_limit = limit;
+            self.highlighted_until = 0;
+        }
+    }
+
+    // the longest row, by graphemes, and where it is
+    pub fn longest_row(&self) -> (usize, usize) {
+        self.rows.iter().enumerate().map(|(y, row)| (row.len(), y)).max().unwrap_or((0, 0))
+    }
+
+    // the whole text swapped for text, as one undo step
+    pub fn replace_all(&mut self, text: &str) {
+        let last = self.len().saturating_sub(1);
+        let end = Position { x: self.row_len(last), y: last };
+        self.history.start_group();
+        self.delete_range(&Position::default(), &end);
+        self.insert_str(&Position::default(), text);
+        self.history.start_group();
+    }
+
+    // how many undo groups to keep, the oldest go first
+    pub fn set_undo_limit(&mut self, limit: usize) {
+        self.history.set_limit(limit);
+    }
+
+    // changes from here on are undone separately from the ones before
+    pub fn start_undo_group(&mut self) {
+        self.history.start_group();
+    }
+
+    // swaps the grapheme at at with the one after it, returns false if there's
+    // not two there to swap
+    pub fn transpose(&mut self, at: &Position) -> bool {
+        if at.y >= self.len() || at.x + 1 >= self.rows[at.y].len() {
+            return false;
+        }
+        let removed = self.rows[at.y].substring(at.x, at.x + 2);
+        self.rows[at.y].transpose(at.x);
+        let inserted = self.rows[at.y].substring(at.x, at.x + 2);
+        self.history.record(Change {
+            start: at.clone(),
+            removed,
+            inserted,
+        });
+        self.touch(at.y);
+        self.unhighlight_from(at.y);
+        self.revision += 1;
+        self.dirty = true;
+        true
+    }
+
+    // puts back the text from before the last group of changes
+    // returns where the cursor should go, None if there's nothing to undo
+    pub fn undo(&mut self) -> Option<Position> {
+        let group = self.history.undo()?;
+        Some(self.take_back(&group))
+    }
+
+    // makes the last undone group of changes again
+    pub fn redo(&mut self) -> Option<Position> {
+        let group = self.history.redo()?;
+        Some(self.make_again(&group))
+    }
+
+    // goes to the text as it was after undo tree node id, across branches
+    pub fn undo_to(&mut self, id: usize) -> Option<Position> {
+        let (ups, downs) = self.history.travel(id)?;
+        let mut at = Position::default();
+        for group in &ups {
+            at = self.take_back(group);
+        }
+        for group in &downs {
+            at = self.make_again(group);
+        }
+        Some(at)
+    }
+
+    // the text from the edit made just before (or after) this one, whichever
+    // branch it was made on
+    pub fn undo_in_time(&mut self, forward: bool) -> Option<Position> {
+        let id = self.history.neighbour(forward)?;
+        self.undo_to(id)
+    }
+
+    // the undo tree a line per node, see History::lines
+    pub fn undo_tree(&self) -> Vec<(usize, String)> {
+        self.history.lines()
+    }
+
+    fn take_back(&mut self, group: &[Change]) -> Position {
+        for change in group.iter().rev() {
+            let end = Change::end_of(&change.start, &change.inserted);
+            self.take_text(&change.start, &end);
+            self.put_text(&change.start, &change.removed);
+        }
+        self.dirty = true;
+        group.iter().map(|change| change.start.clone()).min().unwrap_or_default()
+    }
+
+    fn make_again(&mut self, group: &[Change]) -> Position {
+        let mut at = Position::default();
+        for change in group {
+            let end = Change::end_of(&change.start, &change.removed);
+            self.take_text(&change.start, &end);
+            at = self.put_text(&change.start, &change.inserted);
+        }
+        self.dirty = true;
+        at
+    }
+
+    // the rows are only ever changed through put_text and take_text (and
+    // transpose), which is what lets every change be recorded for undo
+    fn put_text(&mut self, at: &Position, text: &str) -> Position {
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or_default();
+        let mut new_rows: Vec<Row> = lines.map(Row::from).collect();
+        let row = &mut self.rows[at.y];
+        let tail = row.split(at.x);
+        row.append(&Row::from(first));
+        let end = match new_rows.last_mut() {
+            Some(last) => {
+                let x = last.len();
+                last.append(&tail);
+                Position {
+                    x,
+                    y: at.y + new_rows.len(),
+                }
+            }
+            None => {
+                let x = row.len();
+                row.append(&tail);
+                Position { x, y: at.y }
+            }
+        };
+        let added = new_rows.len();
+        self.rows.splice(at.y + 1..at.y + 1, new_rows);
+        self.touch(at.y);
+        self.touched.splice(at.y + 1..at.y + 1, std::iter::repeat_n(true, added));
+        self.unhighlight_from(at.y);
+        self.revision += 1;
+        end
+    }
+
+    // start has to come before end, and both have to be in the document
+    // returns the text that was removed
+    fn take_text(&mut self, start: &Position, end: &Position) -> String {
+        let tail = self.rows[end.y].split(end.x);
+        let mut removed = self.rows[start.y].split(start.x).as_str().to_string();
+        for row in self.rows.drain(start.y + 1..=end.y) {
+            removed.push('\n');
+            removed.push_str(row.as_str());
+        }
+        self.rows[start.y].append(&tail);
+        self.touch(start.y);
+        let last = (end.y + 1).min(self.touched.len());
+        self.touched.drain(start.y + 1..last);
+        self.unhighlight_from(start.y);
+        self.revision += 1;
+        removed
+    }
+
+    // the position count graphemes away from at, where the end of a row counts
+    // as one grapheme, stopping at either end of the document
+    pub fn step(&self, at: &Position, count: usize, direction: SearchDirection) -> Position {
+        let mut at = at.clone();
+        let mut count = count;
+        let row_len = |y: usize| self.rows.get(y).map_or(0, Row::len);
+        while count > 0 {
+            match direction {
+                SearchDirection::Forward => {
+                    let left_in_row = row_len(at.y).saturating_sub(at.x);
+                    if count <= left_in_row {
+                        at.x += count;
+                        break;
+                    }
+                    if at.y + 1 >= self.len() {
+                        at.x = row_len(at.y);
+                        break;
+                    }
+                    count -= left_in_row + 1;
+                    at = Position { x: 0, y: at.y + 1 };
+                }
+                SearchDirection::Backward => {
+                    if count <= at.x {
+                        at.x -= count;
+                        break;
+                    }
+                    if at.y == 0 {
+                        at.x = 0;
+                        break;
+                    }
+                    count -= at.x + 1;
+                    at = Position {
+                        x: row_len(at.y - 1),
+                        y: at.y - 1,
+                    };
+                }
+            }
+        }
+        at
+    }
+
+    // The nearest row in direction that's indented as far as y, passing over
+    // blank rows and ones indented further. None once a row indented less gets
+    // in the way, since that's the end of y's block.
+    pub fn next_sibling_line(&self, y: usize, direction: SearchDirection, tab_stop: usize) -> Option<usize> {
+        let width = self.indent_of(y, tab_stop)?;
+        let mut rows: Box<dyn Iterator<Item = usize>> = match direction {
+            SearchDirection::Forward => Box::new(y + 1..self.len()),
+            SearchDirection::Backward => Box::new((0..y).rev()),
+        };
+        rows.find_map(|other| match self.indent_of(other, tab_stop) {
+            Some(other_width) if other_width < width => Some(None),
+            Some(other_width) if other_width == width => Some(Some(other)),
+            _ => None,
+        })
+        .flatten()
+    }
+
+    // the first row above y that's indented less than it
+    pub fn parent_line(&self, y: usize, tab_stop: usize) -> Option<usize> {
+        let width = self.indent_of(y, tab_stop)?;
+        (0..y).rev().find(|other| self.indent_of(*other, tab_stop).is_some_and(|other_width| other_width < width))
+    }
+
+    // None for blank rows, which don't have an indentation level of their own
+    fn indent_of(&self, y: usize, tab_stop: usize) -> Option<usize> {
+        let row = self.rows.get(y)?;
+        if row.indentation().len() == row.len() {
+            return None;
+        }
+        Some(row.indent_width(tab_stop))
+    }
+
+    // the rows of the paragraph around y, which ends at a blank row
+    // a list item starts a paragraph of its own
+    pub fn paragraph_at(&self, y: usize) -> Option<Range<usize>> {
+        let is_blank = |y: usize| self.rows[y].as_str().trim().is_empty();
+        let is_item = |y: usize| list_marker(self.rows[y].as_str().trim_start()).is_some();
+        if y >= self.len() || is_blank(y) {
+            return None;
+        }
+        let mut start = y;
+        while start > 0 && !is_item(start) && !is_blank(start - 1) {
+            start -= 1;
+        }
+        let mut end = y + 1;
+        while end < self.len() && !is_blank(end) && !is_item(end) {
+            end += 1;
+        }
+        Some(start..end)
+    }
+
+    // rewraps the words in rows to at most width columns, breaking between words
+    // the first row's indentation and list marker are kept, and the rows after it
+    // are lined up under the text
+    // returns how many rows the text takes up now
+    pub fn reflow(&mut self, range: Range<usize>, width: usize) -> usize {
+        let range = range.start.min(self.len())..range.end.min(self.len());
+        if range.is_empty() {
+            return 0;
+        }
+        let first = self.rows[range.start].as_str();
+        let indent = &first[..first.len() - first.trim_start().len()];
+        let marker = list_marker(first.trim_start()).unwrap_or("");
+        let first_prefix = format!("{}{}", indent, marker);
+        let rest_prefix = format!("{}{}", indent, " ".repeat(marker.graphemes(true).count()));
+
+        let mut words: Vec<&str> = Vec::new();
+        for (index, row) in self.rows[range.clone()].iter().enumerate() {
+            let text = row.as_str().trim_start();
+            let text = if index == 0 { &text[marker.len()..] } else { text };
+            words.extend(text.split_whitespace());
+        }
+
+        let mut lines = Vec::new();
+        let mut line = first_prefix.clone();
+        let mut line_len = line.graphemes(true).count();
+        let mut line_has_words = false;
+        for word in words {
+            let word_len = word.graphemes(true).count();
+            // a word longer than the width still gets a line to itself
+            if line_has_words && line_len + 1 + word_len > width {
+                lines.push(line);
+                line = rest_prefix.clone();
+                line_len = line.graphemes(true).count();
+                line_has_words = false;
+            }
+            if line_has_words {
+                line.push(' ');
+                line_len += 1;
+            }
+            line.push_str(word);
+            line_len += word_len;
+            line_has_words = true;
+        }
+        lines.push(line);
+
+        let start = Position { x: 0, y: range.start };
+        let end = Position {
+            x: self.rows[range.end - 1].len(),
+            y: range.end - 1,
+        };
+        let inserted = lines.join("\n");
+        let removed = self.take_text(&start, &end);
+        self.put_text(&start, &inserted);
+        self.history.record(Change {
+            start,
+            removed,
+            inserted,
+        });
+        self.dirty = true;
+        lines.len()
+    }
+
+    // turns the rows in range upside down, as one undo step
+    pub fn reverse_rows(&mut self, range: Range<usize>) {
+        let range = range.start.min(self.len())..range.end.min(self.len());
+        if range.len() < 2 {
+            return;
+        }
+        let start = Position { x: 0, y: range.start };
+        let end = Position {
+            x: self.rows[range.end - 1].len(),
+            y: range.end - 1,
+        };
+        let inserted = self.rows[range].iter().rev().map(Row::as_str).collect::<Vec<_>>().join("\n");
+        self.history.start_group();
+        let removed = self.take_text(&start, &end);
+        self.put_text(&start, &inserted);
+        self.history.record(Change {
+            start,
+            removed,
+            inserted,
+        });
+        self.history.start_group();
+        self.dirty = true;
+    }
+
+    // strips escape sequences from the rows in range, as one undo step
+    // returns how many rows had any
+    pub fn strip_ansi(&mut self, range: Range<usize>) -> usize {
+        self.history.start_group();
+        let mut changed = 0;
+        for y in range.start.min(self.len())..range.end.min(self.len()) {
+            let mut stripped = Row::from(self.rows[y].as_str());
+            if !stripped.strip_ansi() {
+                continue;
+            }
+            let text = stripped.as_str().to_string();
+            let start = Position { x: 0, y };
+            let end = Position {
+                x: self.rows[y].len(),
+                y,
+            };
+            let removed = self.take_text(&start, &end);
+            self.put_text(&start, &text);
+            self.history.record(Change {
+                start,
+                removed,
+                inserted: text,
+            });
+            changed += 1;
+        }
+        if changed > 0 {
+            self.dirty = true;
+        }
+        self.history.start_group();
+        changed
+    }
+
+    // rewrites indentation as all spaces, or as tabs with any leftover spaces after
+    // them, and with whole_line every run of blanks rather than just the leading one
+    // all of it goes in the undo group the caller started, returns how many rows changed
+    pub fn retab(&mut self, to_spaces: bool, tab_stop: usize, whole_line: bool) -> usize {
+        let mut changed = 0;
+        for y in 0..self.rows.len() {
+            let text = retabbed(self.rows[y].as_str(), to_spaces, tab_stop.max(1), whole_line);
+            if text == self.rows[y].as_str() {
+                continue;
+            }
+            let start = Position { x: 0, y };
+            let end = Position {
+                x: self.rows[y].len(),
+                y,
+            };
+            let removed = self.take_text(&start, &end);
+            self.put_text(&start, &text);
+            self.history.record(Change {
+                start,
+                removed,
+                inserted: text,
+            });
+            changed += 1;
+        }
+        if changed > 0 {
+            self.dirty = true;
+        }
+        self.history.start_group();
+        changed
+    }
+}
+
+fn retabbed(text: &str, to_spaces: bool, tab_stop: usize, whole_line: bool) -> String {
+    let next_col = |grapheme: &str, col: usize| {
+        if grapheme == "\t" {
+            (col / tab_stop + 1) * tab_stop
+        } else {
+            col + 1
+        }
+    };
+    let mut result = String::new();
+    let mut col = 0;
+    // the column the run of blanks being collected started at
+    let mut run_start: Option<usize> = None;
+    let mut leading = true;
+    for grapheme in text.graphemes(true) {
+        let blank = grapheme == " " || grapheme == "\t";
+        if blank && (leading || whole_line) {
+            run_start.get_or_insert(col);
+        } else {
+            if let Some(from) = run_start.take() {
+                push_blanks(&mut result, from..col, to_spaces, tab_stop, leading);
+            }
+            leading = false;
+            result.push_str(grapheme);
+        }
+        col = next_col(grapheme, col);
+    }
+    if let Some(from) = run_start {
+        push_blanks(&mut result, from..col, to_spaces, tab_stop, leading);
+    }
+    result
+}
+
+// blanks covering the columns in cols
+fn push_blanks(result: &mut String, cols: Range<usize>, to_spaces: bool, tab_stop: usize, leading: bool) {
+    // a single space between words isn't lining anything up, so it stays a space
+    if to_spaces || (!leading && cols.len() < 2) {
+        result.push_str(&" ".repeat(cols.len()));
+        return;
+    }
+    let mut at = cols.start;
+    while (at / tab_stop + 1) * tab_stop <= cols.end {
+        result.push('\t');
+        at = (at / tab_stop + 1) * tab_stop;
+    }
+    result.push_str(&" ".repeat(cols.end - at));
+}
+
+impl TextBuffer for Document {
+    fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn row_len(&self, y: usize) -> usize {
+        self.rows.get(y).map_or(0, Row::len)
+    }
+
+    fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        self.ensure_row();
+        if at.y >= self.len() {
+            return at.clone();
+        }
+        let at = Position {
+            x: at.x.min(self.rows[at.y].len()),
+            y: at.y,
+        };
+        self.dirty = true;
+        let end = self.put_text(&at, text);
+        self.history.record(Change {
+            start: at,
+            removed: String::new(),
+            inserted: text.to_string(),
+        });
+        end
+    }
+
+    fn delete_range(&mut self, start: &Position, end: &Position) {
+        let (start, end) = if start <= end {
+            (start, end)
+        } else {
+            (end, start)
+        };
+        if start.y >= self.len() || start == end {
+            return;
+        }
+        let end = if end.y >= self.len() {
+            let y = self.len() - 1;
+            Position { x: self.rows[y].len(), y }
+        } else {
+            Position {
+                x: end.x.min(self.rows[end.y].len()),
+                y: end.y,
+            }
+        };
+        let start = Position {
+            x: start.x.min(self.rows[start.y].len()),
+            y: start.y,
+        };
+        if start == end {
+            return;
+        }
+        self.dirty = true;
+        let removed = self.take_text(&start, &end);
+        self.history.record(Change {
+            start,
+            removed,
+            inserted: String::new(),
+        });
+    }
+
+    fn delete(&mut self, at: &Position) {
+        let end = self.step(at, 1, SearchDirection::Forward);
+        self.delete_range(at, &end);
+    }
+}
+
+// the bullet or number at the start of a list item, with the space after it
+// e.g. "- ", "* " or "12. "
+fn list_marker(text: &str) -> Option<&str> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let bullet = if digits > 0 {
+        let rest = &text[digits..];
+        if rest.starts_with(". ") || rest.starts_with(") ") {
+            digits + 1
+        } else {
+            return None;
+        }
+    } else if text.starts_with("- ") || text.starts_with("* ") || text.starts_with("+ ") {
+        1
+    } else {
+        return None;
+    };
+    let spaces = text[bullet..].len() - text[bullet..].trim_start_matches(' ').len();
+    Some(&text[..bullet + spaces])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // text split at newlines, the way open splits a file
+    fn document(text: &str) -> Document {
+        let lines: Vec<String> = text.split('\n').map(String::from).collect();
+        let mut document = Document::from_lines(&lines);
+        document.set_undo_limit(100);
+        document
+    }
+
+    fn at(x: usize, y: usize) -> Position {
+        Position { x, y }
+    }
+
+    fn fixture(name: &str) -> Document {
+        let path = format!("{}/test/{}", env!("CARGO_MANIFEST_DIR"), name);
+        Document::open(&path).expect("fixture opens")
+    }
+
+    // what Editor::default starts with when there's no file, once set_document has it
+    #[test]
+    fn a_new_buffer_is_one_empty_editable_row() {
+        let mut document = Document::default();
+        document.ensure_row();
+        assert_eq!(document.lines().collect::<Vec<_>>(), [""]);
+        assert!(document.is_empty());
+        assert_eq!(document.contents(), "");
+        document.insert(&Position::default(), 'x');
+        assert_eq!(document.contents(), "x");
+        assert!(!document.is_empty());
+    }
+
+    #[test]
+    fn trailing_newline_is_an_empty_last_row() {
+        let document = fixture("trailing_newline.txt");
+        assert_eq!(document.lines().collect::<Vec<_>>(), ["first line", "last line", ""]);
+        assert_eq!(document.contents(), "first line\nlast line\n");
+    }
+
+    #[test]
+    fn no_trailing_newline_ends_on_the_last_character() {
+        let document = fixture("no_trailing_newline.txt");
+        assert_eq!(document.lines().collect::<Vec<_>>(), ["first line", "last line"]);
+        assert_eq!(document.contents(), "first line\nlast line");
+        // stepping past the end stops after the last character
+        let end = document.step(&Position { x: 0, y: 1 }, 100, SearchDirection::Forward);
+        assert_eq!(end, Position { x: 9, y: 1 });
+    }
+
+    #[test]
+    fn appending_at_the_end_of_the_file() {
+        let mut with_newline = fixture("trailing_newline.txt");
+        let end = with_newline.step(&Position::default(), usize::MAX, SearchDirection::Forward);
+        assert_eq!(end, Position { x: 0, y: 2 });
+        with_newline.insert_str(&end, "more");
+        assert_eq!(with_newline.contents(), "first line\nlast line\nmore");
+
+        let mut without = fixture("no_trailing_newline.txt");
+        let end = without.step(&Position::default(), usize::MAX, SearchDirection::Forward);
+        without.insert_str(&end, "\n");
+        assert_eq!(without.contents(), "first line\nlast line\n");
+    }
+
+    #[test]
+    fn delete_range_within_a_row() {
+        let mut document = document("hello world\nnext");
+        document.delete_range(&at(5, 0), &at(11, 0));
+        assert_eq!(document.contents(), "hello\nnext");
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn delete_range_across_rows_joins_the_ends() {
+        let mut document = document("one\ntwo\nthree\nfour");
+        document.delete_range(&at(1, 0), &at(2, 2));
+        assert_eq!(document.contents(), "oree\nfour");
+        assert_eq!(document.len(), 2);
+    }
+
+    #[test]
+    fn delete_range_takes_the_ends_in_either_order() {
+        let mut forward = document("one\ntwo\nthree");
+        let mut reversed = document("one\ntwo\nthree");
+        forward.delete_range(&at(2, 0), &at(1, 2));
+        reversed.delete_range(&at(1, 2), &at(2, 0));
+        assert_eq!(forward.contents(), "onhree");
+        assert_eq!(reversed.contents(), forward.contents());
+    }
+
+    #[test]
+    fn delete_range_past_the_end_stops_at_the_end() {
+        let mut document = document("one\ntwo");
+        document.delete_range(&at(1, 0), &at(50, 9));
+        assert_eq!(document.contents(), "o");
+        // starting past the end, or at the end itself, is nothing to delete
+        document.delete_range(&at(1, 5), &at(3, 7));
+        document.delete_range(&at(1, 0), &at(1, 0));
+        assert_eq!(document.contents(), "o");
+    }
+
+    #[test]
+    fn delete_range_is_one_undo_step() {
+        let mut document = document("one\ntwo\nthree");
+        document.start_undo_group();
+        document.delete_range(&at(0, 0), &at(5, 2));
+        assert_eq!(document.contents(), "");
+        assert_eq!(document.undo(), Some(at(0, 0)));
+        assert_eq!(document.contents(), "one\ntwo\nthree");
+        assert_eq!(document.undo(), None);
+    }
+
+    #[test]
+    fn an_edit_undone_and_replaced_can_still_be_gone_back_to() {
+        let mut document = document("one");
+        document.start_undo_group();
+        document.insert_str(&at(3, 0), " two");
+        document.undo();
+        document.start_undo_group();
+        document.insert_str(&at(3, 0), " three");
+        // two is on a branch of its own now, but it was made just before three
+        assert_eq!(document.history.neighbour(false), Some(1));
+        assert_eq!(document.undo_in_time(false), Some(at(7, 0)));
+        assert_eq!(document.contents(), "one two");
+        document.undo_in_time(true);
+        assert_eq!(document.contents(), "one three");
+        // and travelling there by id goes the same way
+        document.undo_to(1);
+        assert_eq!(document.contents(), "one two");
+        document.undo_to(2);
+        assert_eq!(document.contents(), "one three");
+        assert_eq!(document.history.neighbour(true), None);
+    }
+
+    #[test]
+    fn retab_undoes_in_one_step() {
+        let mut document = fixture("tabs.txt");
+        document.set_undo_limit(100);
+        let before = document.contents();
+        document.start_undo_group();
+        // the row already indented with spaces is left alone
+        assert_eq!(document.retab(true, 4, false), 3);
+        for line in document.contents().lines() {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            assert!(!indent.contains('\t'), "{:?}", line);
+        }
+        document.undo();
+        assert_eq!(document.contents(), before);
+        assert_eq!(document.undo(), None);
+    }
+
+    #[test]
+    fn strip_ansi_undoes_in_one_step() {
+        let mut document = document("\x1b[31mred\x1b[0m\nplain\n\x1b[1mbold");
+        assert_eq!(document.strip_ansi(0..3), 2);
+        assert_eq!(document.contents(), "red\nplain\nbold");
+        document.undo();
+        assert_eq!(document.contents(), "\x1b[31mred\x1b[0m\nplain\n\x1b[1mbold");
+        assert_eq!(document.undo(), None);
+    }
+
+    #[test]
+    fn join_line_without_collapsing_keeps_every_blank() {
+        let mut document = document("foo  \n\t bar\nlast");
+        assert_eq!(document.join_line(0, false), at(5, 0));
+        assert_eq!(document.contents(), "foo  \t bar\nlast");
+    }
+
+    #[test]
+    fn join_line_collapses_the_seam_to_one_space() {
+        let mut document = document("foo  \n\t bar\nlast");
+        // the cursor lands on the text that came up from the next row
+        assert_eq!(document.join_line(0, true), at(4, 0));
+        assert_eq!(document.contents(), "foo bar\nlast");
+    }
+
+    #[test]
+    fn join_line_adds_no_space_next_to_a_blank_row() {
+        let mut blank_below = document("foo \n   \n  bar");
+        assert_eq!(blank_below.join_line(0, true), at(3, 0));
+        assert_eq!(blank_below.contents(), "foo\n  bar");
+        let mut blank_above = document("\n  bar");
+        assert_eq!(blank_above.join_line(0, true), at(0, 0));
+        assert_eq!(blank_above.contents(), "bar");
+    }
+
+    #[test]
+    fn join_line_on_the_last_row_does_nothing() {
+        for collapse in [false, true] {
+            let mut document = document("one\ntwo ");
+            assert_eq!(document.join_line(1, collapse), at(4, 1));
+            assert_eq!(document.contents(), "one\ntwo ");
+        }
+    }
+
+    #[test]
+    fn reverse_rows_turns_only_the_range_around() {
+        let mut document = document("one\ntwo\nthree\nfour\nfive");
+        document.reverse_rows(1..4);
+        assert_eq!(document.contents(), "one\nfour\nthree\ntwo\nfive");
+        assert_eq!(document.undo(), Some(at(0, 1)));
+        assert_eq!(document.contents(), "one\ntwo\nthree\nfour\nfive");
+        assert_eq!(document.undo(), None);
+    }
+
+    #[test]
+    fn reverse_rows_past_the_end_stops_at_the_last_row() {
+        let mut document = document("one\ntwo\nthree");
+        document.reverse_rows(1..10);
+        assert_eq!(document.contents(), "one\nthree\ntwo");
+        // one row is already the right way round
+        document.reverse_rows(2..3);
+        assert_eq!(document.contents(), "one\nthree\ntwo");
+    }
 }
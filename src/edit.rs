@@ -0,0 +1,48 @@
+// A completed text-modifying action that can be replayed at another cursor
+// position (like vim's `.`). This only remembers *what* was done, not the
+// text that was there before, so it is not enough to undo with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    // characters typed in one go, newlines included
+    Insert(String),
+    // characters typed over existing text in overwrite mode
+    Overwrite(String),
+    // number of graphemes removed with Delete
+    Delete(usize),
+    // number of graphemes removed with Backspace
+    Backspace(usize),
+    // the paragraph around the cursor rewrapped to this many columns
+    Reflow(usize),
+    // the number at or after the cursor changed by this much, Ctrl-A and Alt-X
+    AddToNumber(i64),
+    // the characters either side of the cursor swapped, Ctrl-T
+    Transpose,
+}
+
+impl Edit {
+    // folds another keypress of the same kind into this edit
+    // returns false if the two can't be combined
+    pub fn extend(&mut self, other: &Edit) -> bool {
+        match (self, other) {
+            (Edit::Insert(text), Edit::Insert(more))
+            | (Edit::Overwrite(text), Edit::Overwrite(more)) => {
+                text.push_str(more);
+                true
+            }
+            (Edit::Delete(count), Edit::Delete(more))
+            | (Edit::Backspace(count), Edit::Backspace(more)) => {
+                *count += more;
+                true
+            }
+            // pressing it five times in a row repeats as adding five
+            (Edit::AddToNumber(delta), Edit::AddToNumber(more)) => match delta.checked_add(*more) {
+                Some(sum) => {
+                    *delta = sum;
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
@@ -1,18 +1,72 @@
+use crate::calc;
+use crate::diff::{self, LineChange};
+use crate::git;
+use crate::keymap::{key_name, keys_name, Action, Keymap, Lookup};
+use crate::macros;
+use crate::paths;
+use crate::picker;
+use crate::quickfix;
+use crate::signs::{Sign, Signs};
+use crate::recent;
+use crate::shell;
 use crate::Navigable;
-use crate::{Document, Row, Terminal};
+use crate::{
+    highlight_line, Config, ControlCharStyle, CursorShape, Document, Edit, Event, HighlightState, Highlighting,
+    IndentStyle, LineEnding, LineNumberMode, LongLineStyle, Row, SearchCase, Size, SyntaxDefinition, Terminal,
+    TextBuffer,
+};
 use log::{debug, info};
-use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Read};
+use std::ops::Range;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::{Duration, Instant};
-use std::io::{stdout, Stdout, Write};
-use termion::screen::AlternateScreen;
-use termion::{color, event::Key};
+use termion::event::{Key, MouseButton, MouseEvent};
+use termion::{clear, color, cursor, style};
+use unicode_segmentation::UnicodeSegmentation;
 
 const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
+const LINE_NUMBER_FG_COLOR: color::Rgb = color::Rgb(120, 120, 120);
+// rows past the bottom of the screen that get highlighted ahead of time
+const HIGHLIGHT_MARGIN: usize = 20;
+const CURSOR_BG_COLOR: color::Rgb = color::Rgb(147, 161, 161);
+const RULER_BG_COLOR: color::Rgb = color::Rgb(60, 60, 60);
+const SEARCH_MATCH_BG_COLOR: color::Rgb = color::Rgb(38, 139, 210);
+const SELECTION_BG_COLOR: color::Rgb = color::Rgb(88, 110, 117);
+const MINIMAP_WIDTH: usize = 1;
+// the minimap shades rows this long or longer as full
+const MINIMAP_FULL_ROW: usize = 80;
+// rows looked at in each slice of the document
+const MINIMAP_SAMPLES: usize = 8;
+const MINIMAP_FG_COLOR: color::Rgb = color::Rgb(147, 161, 161);
+const MINIMAP_VIEW_BG_COLOR: color::Rgb = color::Rgb(60, 60, 60);
+const ERROR_SIGN_COLOR: color::Rgb = color::Rgb(220, 50, 47);
+const WARNING_SIGN_COLOR: color::Rgb = color::Rgb(181, 137, 0);
+const ADDED_SIGN_COLOR: color::Rgb = color::Rgb(133, 153, 0);
+const MODIFIED_SIGN_COLOR: color::Rgb = color::Rgb(38, 139, 210);
+const DELETED_SIGN_COLOR: color::Rgb = color::Rgb(220, 50, 47);
+// how long typing has to stop for before the diff signs and word highlights catch up
+const IDLE_DELAY: Duration = Duration::from_millis(300);
+// how long a message stays in the message bar
+const MESSAGE_TIME: Duration = Duration::from_secs(5);
+// turned through while a shell command runs, a frame every SPINNER_FRAME
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const SPINNER_FRAME: Duration = Duration::from_millis(100);
+const EMPTY_ROW_FG_COLOR: color::Rgb = color::Rgb(88, 88, 88);
+// the markers for rows that carry on past the edge of the screen
+const MARKER_FG_COLOR: color::Rgb = color::Rgb(181, 137, 0);
 
-#[derive(Default)]
+// Positions order the way they come in the document: by row, then by
+// grapheme within the row, which isn't the order of the fields, hence no derive.
+// So a < b means a comes before b, and min and max give the start and end
+// of the range between them.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -24,6 +78,24 @@ impl Position {
     }
 }
 
+impl Ord for Position {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
+    }
+}
+
+impl PartialOrd for Position {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
 struct StatusMessage {
     text: String,
     time: Instant,
@@ -38,6 +110,95 @@ impl StatusMessage {
     }
 }
 
+// how long the screen stays inverted for a visual bell
+const FLASH_TIME: Duration = Duration::from_millis(100);
+
+// Where keys go right now. It's the one field for it so the indicator in the
+// status bar can't say one thing while the keys do another. A selection and
+// a macro being recorded aren't in here, they carry on through prompts.
+#[derive(Clone, Debug, PartialEq)]
+enum Mode {
+    // typing into the document
+    Edit,
+    // partway through a binding of more than one key, the keys so far
+    Chord(Vec<Key>),
+    // prompt is reading a line, for a picker or a question
+    Prompt,
+    // the same, for Ctrl-F
+    Search,
+    // a shell command is running, see Editor::running, and every key but
+    // the ones that stop it is dropped until it's done
+    Running,
+}
+
+// the things Esc can cancel, in the order it cancels them
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operation {
+    // also covers search and the pickers, which are all prompts
+    Prompt,
+    Selection,
+    Cursors,
+    Message,
+    Overwrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SelectionKind {
+    // running from the anchor to the cursor like normal text
+    Linear,
+    // the rectangle with the anchor and the cursor in opposite corners
+    Block,
+}
+
+// the other end of a selection is wherever the cursor is
+struct Selection {
+    anchor: Position,
+    kind: SelectionKind,
+}
+
+// where Ctrl-X ! and friends put a command's output, and Ctrl-X o and O a new row
+#[derive(Clone, Copy, PartialEq)]
+enum Placement {
+    Cursor,
+    Below,
+    Above,
+}
+
+// what's done with a shell command's output once it's finished
+#[derive(Clone, Copy)]
+enum AfterCommand {
+    Insert(Placement),
+    Prettify,
+    SudoSave,
+}
+
+struct RunningCommand {
+    command: String,
+    started: Instant,
+    running: shell::Running,
+    then: AfterCommand,
+}
+
+// what the main loop can be woken up by when no input comes first
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Timer {
+    // a half typed binding times out
+    Chord,
+    // the diff signs and word highlights catch up
+    IdleWork,
+    AutoSave,
+    // the message bar is due to clear
+    Message,
+    // the spinner turns, and the running command is checked on
+    Spinner,
+}
+
+#[derive(Default)]
+struct Overlay {
+    lines: Vec<String>,
+    selected: Option<usize>,
+}
+
 // we want this to be public to main.rs
 // struct contains fields for the "class"
 pub struct Editor {
@@ -46,8 +207,86 @@ pub struct Editor {
     cursor_position: Position,
     document: Document,
     offset: Position,
-    screen: RefCell<AlternateScreen<Stdout>>,
     status_message: StatusMessage,
+    // the most recent edit, replayed by Ctrl-R
+    last_edit: Option<Edit>,
+    // whether keypresses are still being folded into last_edit
+    edit_in_progress: bool,
+    // toggled with the Insert key, typing replaces instead of shifting text right
+    overwrite: bool,
+    // the settings in effect, which are global_config with the current
+    // file's [filetype] section on top
+    config: Config,
+    global_config: Config,
+    // every match of the current search, highlighted while the search prompt is open
+    search_matches: Vec<Position>,
+    search_match_len: usize,
+    // other occurrences of the word under the cursor, on screen only, and the
+    // cursor, scroll and revision they were found for, so they're only drawn
+    // while that's still where things are
+    word_matches: Vec<Position>,
+    word_match_len: usize,
+    word_matched_for: Option<(Position, Position, usize)>,
+    // cached so the status bar doesn't hit the disk every frame
+    git_branch: Option<String>,
+    // the file as committed at HEAD, None outside a repo or for untracked files
+    committed: Option<String>,
+    // the document revision the diff signs were worked out for
+    diffed_revision: Option<usize>,
+    // and the revision and dirtiness the since-save signs were, a save changes only the latter
+    touched_for: Option<(usize, bool)>,
+    // past the large_file limits, so the passes that read the whole file are skipped
+    large_file: bool,
+    // the screen column Up and Down try to keep to, set by the first of a run of them
+    goal_column: Option<usize>,
+    // quitting has already warned about an unsaved scratch buffer
+    quit_warned: bool,
+    // when a visual bell's flash is due to end
+    flash_until: Option<Instant>,
+    // the directory listed in place of a document, see browse
+    browsing: Option<PathBuf>,
+    // with lock_files, the open file, held for as long as it's open: the
+    // lock goes when this is dropped
+    lock: Option<fs::File>,
+    // edits are refused, e.g. because another editor has the file locked
+    read_only: bool,
+    // the backup is only taken once, before the first change this session
+    backed_up: bool,
+    backup_path: Option<PathBuf>,
+    // a list drawn over the bottom of the document, e.g. the file picker
+    overlay: Overlay,
+    mode: Mode,
+    // locations loaded with Alt-E, stepped through with Alt-N and Alt-P
+    quickfix: Vec<quickfix::Entry>,
+    quickfix_index: Option<usize>,
+    // extra cursors that every edit is repeated at, as well as cursor_position
+    cursors: Vec<Position>,
+    // started with Alt-V or Alt-B and stretched by moving the cursor
+    selection: Option<Selection>,
+    // read ahead while collecting a paste, but not handled yet
+    pending_key: Option<Key>,
+    // the timer the main loop is waiting on, should nothing else come first
+    waiting_for: Option<Timer>,
+    // the shell command Mode::Running is waiting on
+    running: Option<RunningCommand>,
+    // what Ctrl-K cut, for Alt-Y
+    kill_register: String,
+    // where the cursor was left by the last Ctrl-K and the document's revision
+    // then, another kill from there adds on to the register instead
+    kill_chain: Option<(Position, usize)>,
+    // the cursor and revision when Ctrl-L last showed the row, another
+    // Ctrl-L from there copies it
+    peeked: Option<(Position, usize)>,
+    // the keys of the macro being recorded
+    recording: Option<Vec<Key>>,
+    // replayed with Alt-R
+    last_macro: Vec<Key>,
+    // what's left of the macro being played
+    replaying: VecDeque<Key>,
+    playing_macro: bool,
+    recorded_before_key: usize,
+    keymap: Keymap,
+    signs: Signs,
 }
 
 impl Editor {
@@ -60,199 +299,3846 @@ impl Editor {
         info!("Width: {}, Height: {}", width, height);
         loop {
             if let Err(error) = self.refresh_screen() {
-                die(error);
+                self.die(error);
             }
             if self.should_quit {
+                self.remove_backup();
                 break;
             }
-            if let Err(error) = self.process_keypresses() {
-                die(error);
+            if let Err(error) = self.process_event() {
+                self.die(error);
             }
         }
     }
-    pub fn terminal(&self) -> &Terminal {
-        &self.terminal
+    // the terminal is put back as the panic unwinds, see Terminal's Drop and main
+    fn die(&self, e: std::io::Error) {
+        // best effort, the terminal is what just failed
+        let _ = self.terminal.clear_screen();
+        let _ = self.terminal.flush();
+        panic!("{}", e);
+    }
+
+    pub fn cursor(&self) -> Position {
+        self.cursor_position.clone()
     }
 
-    pub fn document(&self) -> &Document {
-        &self.document
+    // puts the cursor there, or as near as the document allows, and scrolls to it
+    pub fn set_cursor(&mut self, position: Position) {
+        let y = position.y.min(self.document.len().saturating_sub(1));
+        let len = self.document.row(y).map_or(0, Row::len);
+        self.cursor_position = Position { x: position.x.min(len), y };
+        self.edit_in_progress = false;
+        self.scroll();
     }
 
-    fn refresh_screen(&self) -> Result<(), std::io::Error> {
+    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
         info!("refreshing");
-        Terminal::cursor_hide();
-        Terminal::clear_screen();
-        Terminal::cursor_position(&Position::default());
-        if self.should_quit {
-            Terminal::clear_screen();
-            println!("Goodbye.\r");
-        } else {
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            // after drawing rows, reset cursor
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
+        // only what's on screen (and whatever's above it) gets highlighted
+        let visible_end = self.offset.y + self.text_height();
+        if !self.large_file {
+            self.document.highlight(visible_end + HIGHLIGHT_MARGIN);
+        }
+        let frame = self.view().frame();
+        self.terminal.write(&frame)?;
+        self.terminal.flush()
+    }
+
+    // everything drawing needs, at the terminal's size
+    fn view(&self) -> View<'_> {
+        let words_shown = !self.words_are_stale();
+        View {
+            document: &self.document,
+            config: &self.config,
+            size: self.terminal.size(),
+            offset: self.offset.clone(),
+            cursor_position: self.cursor_position.clone(),
+            cursors: &self.cursors,
+            selection: self.selection.as_ref(),
+            search_matches: &self.search_matches,
+            search_match_len: self.search_match_len,
+            word_matches: if words_shown { &self.word_matches } else { &[] },
+            word_match_len: self.word_match_len,
+            signs: &self.signs,
+            overlay: &self.overlay,
+            mode: &self.mode,
+            message: self.message_visible().then_some(self.status_message.text.as_str()),
+            running: self.running.as_ref().map(|running| (running.command.as_str(), running.started.elapsed())),
+            recording: self.recording.is_some(),
+            git_branch: self.git_branch.as_deref(),
+            browsing: self.browsing.as_deref(),
+            large_file: self.large_file,
+            read_only: self.read_only,
+            overwrite: self.overwrite,
+            should_quit: self.should_quit,
+        }
+    }
+
+    // the main loop's turn: wait for whichever comes first, a key, a resize
+    // or one of the timers, and deal with it
+    fn process_event(&mut self) -> Result<(), std::io::Error> {
+        match self.next_event()? {
+            Event::Key(key) => self.process_key(key),
+            // the terminal already knows its new size, what's left is to
+            // keep the cursor in view and draw everything again
+            Event::Resize(_) => {
+                self.scroll();
+                self.terminal.clear_screen()
+            }
+            // only asked for with mouse_wheel, and only the wheel does anything
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelDown, _, _)) => {
+                self.scroll_view(SearchDirection::Forward);
+                Ok(())
+            }
+            Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _)) => {
+                self.scroll_view(SearchDirection::Backward);
+                Ok(())
+            }
+            Event::Mouse(_) => Ok(()),
+            Event::Tick => self.timer_ran_out(),
+        }
+    }
+
+    // what's waited on besides input, and when it's due
+    fn next_timer(&self) -> Option<(Instant, Timer)> {
+        let now = Instant::now();
+        // a half typed binding waits for nothing else
+        if let Mode::Chord(_) = self.mode {
+            let timeout = self.config.chord_timeout;
+            return (timeout > 0).then(|| (now + Duration::from_millis(timeout), Timer::Chord));
+        }
+        // and neither does a running command, it's checked on each turn of the spinner
+        if let Some(running) = &self.running {
+            let frames = running.started.elapsed().as_millis() / SPINNER_FRAME.as_millis();
+            let next_frame = SPINNER_FRAME * (frames as u32 + 1);
+            return Some((running.started + next_frame, Timer::Spinner));
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        let mut timers = Vec::new();
+        if self.idle_work_pending() {
+            timers.push((now + IDLE_DELAY, Timer::IdleWork));
+        } else if self.config.auto_save_after > 0 && self.document.is_dirty() && self.document.file_name.is_some() {
+            timers.push((now + Duration::from_secs(self.config.auto_save_after), Timer::AutoSave));
+        }
+        if !self.status_message.text.is_empty() && self.message_visible() {
+            timers.push((self.status_message.time + MESSAGE_TIME, Timer::Message));
+        }
+        timers.into_iter().min_by_key(|(due, _)| *due)
     }
 
-    fn write_screen(&self, string: &String) {
-        writeln!(self.screen.borrow_mut(), "{}", string).unwrap();
-        self.screen.borrow_mut().flush().unwrap();
+    fn timer_ran_out(&mut self) -> Result<(), std::io::Error> {
+        match self.waiting_for.take() {
+            Some(Timer::Chord) => return self.continue_chord(None),
+            Some(Timer::IdleWork) => self.do_idle_work(),
+            Some(Timer::AutoSave) => self.auto_save(),
+            Some(Timer::Spinner) => return self.check_command(),
+            // the redraw that comes next leaves it out
+            Some(Timer::Message) | None => (),
+        }
+        Ok(())
     }
 
-    fn process_keypresses(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+    fn process_key(&mut self, pressed_key: Key) -> Result<(), std::io::Error> {
+        if self.mode == Mode::Running {
+            if let Key::Esc | Key::Ctrl('c') = pressed_key {
+                self.stop_command();
+            }
+            return Ok(());
+        }
+        if let Mode::Chord(_) = self.mode {
+            return self.continue_chord(Some(pressed_key));
+        }
+
+        // where this key starts in a macro being recorded, the key itself is already in there
+        self.recorded_before_key = self.recording.as_ref().map_or(0, |keys| keys.len().saturating_sub(1));
 
         if let Some(navigation) = pressed_key.navigation_func() {
-            self.cursor_position = navigation(&self, &self.cursor_position);
+            let vertical = matches!(pressed_key, Key::Up | Key::Down | Key::PageUp | Key::PageDown);
+            if !vertical {
+                self.goal_column = None;
+            } else if self.goal_column.is_none() {
+                self.goal_column = Some(self.cursor_display_x());
+            }
+            for _ in 0..self.repeats_of(pressed_key)? {
+                self.cursor_position = navigation(&self.document, &self.config, &self.cursor_position);
+                let cursors: Vec<Position> =
+                    self.cursors.iter().map(|c| navigation(&self.document, &self.config, c)).collect();
+                self.cursors = cursors;
+            }
+            if vertical {
+                self.restore_goal_column();
+            }
+            self.merge_cursors();
+            // moving around finishes whatever was being typed,
+            // but it doesn't replace the last edit
+            self.edit_in_progress = false;
+            self.scroll();
+            return Ok(());
+        }
+        match self.keymap.lookup(&[pressed_key]) {
+            Lookup::Action(action) => return self.run_action(action),
+            Lookup::Prefix(_) => {
+                self.mode = Mode::Chord(vec![pressed_key]);
+                self.show_chord();
+                return Ok(());
+            }
+            Lookup::Unbound => (),
+        }
+        self.goal_column = None;
+        // a directory listing isn't for editing, only Enter does anything
+        if self.browsing.is_some() {
+            if pressed_key == Key::Char('\n') {
+                self.open_browsed_entry();
+            }
             self.scroll();
             return Ok(());
         }
+        if self.read_only {
+            if matches!(pressed_key, Key::Char(_) | Key::Delete | Key::Backspace) {
+                self.refuse_read_only();
+            }
+            return Ok(());
+        }
         match pressed_key {
-            Key::Ctrl('q') => self.should_quit = true,
+            Key::Char('\t') => self.insert_tab(),
+            Key::Char(c) => {
+                let text = self.typeahead(c)?;
+                // a lone newline is Enter, more than that is probably a paste
+                // that already has its own indentation
+                if text == "\n" && !self.overwrite && self.config.auto_indent && self.cursors.is_empty() {
+                    self.insert_newline();
+                } else if text.contains('\n') && !self.overwrite && self.config.reindent_on_paste && self.cursors.is_empty() {
+                    let text = self.reindented_paste(&text);
+                    self.record_edit(Edit::Insert(text));
+                } else if self.overwrite {
+                    self.record_edit(Edit::Overwrite(text));
+                } else {
+                    self.record_edit(Edit::Insert(text));
+                }
+            }
+            Key::Delete => self.record_edit(Edit::Delete(1)),
+            Key::Backspace => self.backspace(),
             _ => (),
         }
+        self.scroll();
 
         Ok(())
     }
 
-    fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
-        debug!(
-            "Cursor:  ({}, {}) - Offset: ({}, {})",
-            x, y, offset.x, offset.y
-        );
-        if y < offset.y {
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
+    // a binding of more than one key is collected a key at a time, with the keys
+    // that can follow listed over the bottom of the screen in the meantime
+    fn continue_chord(&mut self, key: Option<Key>) -> Result<(), std::io::Error> {
+        let key = match key {
+            // timed out, which finishes it if the keys so far are a binding too
+            None => {
+                let lookup = self.keymap.lookup(self.chord());
+                self.end_chord();
+                if let Lookup::Prefix(Some(action)) = lookup {
+                    return self.run_action(action);
+                }
+                return Ok(());
+            }
+            Some(Key::Esc) => {
+                self.end_chord();
+                return Ok(());
+            }
+            Some(key) => key,
+        };
+        if let Mode::Chord(keys) = &mut self.mode {
+            keys.push(key);
         }
-        if x < offset.x {
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        match self.keymap.lookup(self.chord()) {
+            Lookup::Action(action) => {
+                self.end_chord();
+                self.run_action(action)
+            }
+            Lookup::Prefix(_) => {
+                self.show_chord();
+                Ok(())
+            }
+            Lookup::Unbound => {
+                let keys = keys_name(self.chord());
+                self.end_chord();
+                self.fail(format!("{} isn't bound", keys));
+                Ok(())
+            }
         }
     }
 
-    fn render_welcome(&self) {
-        let mut welcome_msg = format!("Milli Editor -- version {}", VERSION);
-        let width = self.terminal.size().width as usize;
-        let len = welcome_msg.len();
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
-        welcome_msg.truncate(width);
-        let string = format!("~{}{}\r", spaces, welcome_msg);
-        self.write_screen(&string);
+    fn show_chord(&mut self) {
+        self.overlay.lines = self
+            .keymap
+            .continuations(self.chord())
+            .iter()
+            .map(|(key, action)| {
+                let description = action.map_or("...", Action::description);
+                format!("  {:<10} {}", key_name(*key), description)
+            })
+            .collect();
+        self.overlay.selected = None;
+        self.status_message = StatusMessage::from(format!("{} -", keys_name(self.chord())));
     }
 
-    pub fn draw_row(&self, row: &Row) {
-        let start = self.offset.x;
-        let end = self.terminal().size().width as usize + self.offset.x;
-        let row = row.render(start, end);
-        let string = format!("{}\r", row);
-        self.write_screen(&string);
+    // the keys of a binding typed so far, none outside one
+    fn chord(&self) -> &[Key] {
+        match &self.mode {
+            Mode::Chord(keys) => keys,
+            _ => &[],
+        }
     }
 
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self.document.row(terminal_row as usize + self.offset.y) {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.render_welcome();
-            } else {
-                let string = format!("~\r");
-                self.write_screen(&string);
+    fn end_chord(&mut self) {
+        self.mode = Mode::Edit;
+        self.overlay = Overlay::default();
+        self.status_message = StatusMessage::from(String::new());
+    }
+
+    fn run_action(&mut self, action: Action) -> Result<(), std::io::Error> {
+        self.goal_column = None;
+        if self.read_only && action.edits() {
+            self.refuse_read_only();
+            return Ok(());
+        }
+        match action {
+            Action::Quit => self.quit(),
+            Action::ForceQuit => self.force_quit(),
+            Action::Save => self.save()?,
+            Action::SaveSelection => self.save_selection()?,
+            Action::SaveSnapshot => self.save_snapshot()?,
+            Action::Reload => self.reload()?,
+            Action::ToggleReadOnly => {
+                self.read_only = !self.read_only;
+                let state = if self.read_only { "Read-only" } else { "Editable" };
+                self.status_message = StatusMessage::from(state.to_string());
+            }
+            Action::OpenFile => self.pick_file()?,
+            Action::OpenRecent => self.pick_recent()?,
+            Action::PickSyntax => self.pick_syntax()?,
+            Action::PickLineEnding => self.pick_line_ending()?,
+            Action::RepeatEdit => self.repeat_last_edit(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::UndoOlder => self.undo_in_time(false),
+            Action::UndoNewer => self.undo_in_time(true),
+            Action::UndoTree => self.pick_undo()?,
+            Action::Find => self.search()?,
+            Action::PeekLine => self.peek_line()?,
+            Action::GotoPercent => self.goto_percent()?,
+            Action::ScrollDown => self.scroll_view(SearchDirection::Forward),
+            Action::ScrollUp => self.scroll_view(SearchDirection::Backward),
+            Action::NextSibling => self.goto_indent_relative(Some(SearchDirection::Forward)),
+            Action::PreviousSibling => self.goto_indent_relative(Some(SearchDirection::Backward)),
+            Action::Parent => self.goto_indent_relative(None),
+            Action::CycleLineNumbers => self.change_setting(|config| config.line_numbers = config.line_numbers.next()),
+            Action::InsertLiteral => self.insert_literal()?,
+            Action::Reflow => self.reflow(),
+            Action::Increment => self.record_edit(Edit::AddToNumber(1)),
+            Action::Decrement => self.record_edit(Edit::AddToNumber(-1)),
+            Action::Transpose => self.record_edit(Edit::Transpose),
+            Action::KillLine => self.kill_line()?,
+            Action::OpenRowBelow => self.open_row(Placement::Below),
+            Action::OpenRowAbove => self.open_row(Placement::Above),
+            Action::Yank => self.yank(),
+            Action::AddCursors => self.add_cursors()?,
+            Action::SelectText => self.toggle_selection(SelectionKind::Linear),
+            Action::SelectBlock => self.toggle_selection(SelectionKind::Block),
+            Action::Surround => self.surround()?,
+            Action::InsertOutput => self.insert_output(Placement::Cursor)?,
+            Action::InsertOutputBelow => self.insert_output(Placement::Below)?,
+            Action::InsertOutputAbove => self.insert_output(Placement::Above)?,
+            Action::LoadQuickfix => self.load_quickfix()?,
+            Action::NextQuickfix => self.step_quickfix(SearchDirection::Forward),
+            Action::PreviousQuickfix => self.step_quickfix(SearchDirection::Backward),
+            Action::RecordMacro => self.toggle_recording()?,
+            Action::PlayMacro => self.play_macro(self.last_macro.clone())?,
+            Action::PickMacro => self.pick_macro()?,
+            Action::ToggleOverwrite => self.overwrite = !self.overwrite,
+            Action::ToggleMinimap => self.change_setting(|config| config.minimap = !config.minimap),
+            Action::FullFeatures => {
+                if self.large_file {
+                    self.large_file = false;
+                    self.status_message = StatusMessage::from("Highlighting everything, this may be slow".to_string());
+                } else {
+                    self.status_message = StatusMessage::from("Not a large file, nothing's turned off".to_string());
+                }
+            }
+            Action::ToggleLongLines => self.change_setting(|config| config.long_lines = !config.long_lines),
+            Action::ToggleCursorColumn => self.change_setting(|config| config.cursor_column = !config.cursor_column),
+            Action::NewScratch => {
+                if !self.refuse_if_dirty() {
+                    self.set_document(Document::scratch());
+                    self.status_message = StatusMessage::from("Scratch buffer, saving asks for a name".to_string());
+                }
+            }
+            Action::RetabToSpaces => self.retab(true),
+            Action::RetabToTabs => self.retab(false),
+            Action::ReverseLines => self.reverse_lines(),
+            Action::StripAnsi => self.strip_ansi(),
+            Action::Prettify => self.prettify()?,
+            Action::Calculate => self.calculate()?,
+            Action::ToggleWordHighlight => {
+                self.change_setting(|config| config.highlight_word = !config.highlight_word);
+                self.word_matches.clear();
+                self.word_matched_for = None;
+            }
+            Action::CommandPalette => self.command_palette()?,
+            Action::Cancel => {
+                self.cancel();
             }
         }
+        self.scroll();
+        Ok(())
     }
 
-    fn draw_status_bar(&self) {
-        let mut status;
-        let width = self.terminal.size().width as usize;
-        let mut file_name = "[No Name]".to_string();
-        if let Some(name) = &self.document.file_name {
-            file_name = name.clone();
-            file_name.truncate(20);
+    // blocks until there's a key, mouse event or resize, or until the soonest
+    // of next_timer's deadlines passes, which comes back as Event::Tick with
+    // waiting_for saying which timer it was
+    fn next_event(&mut self) -> Result<Event, std::io::Error> {
+        if let Some(key) = self.pending_key.take() {
+            return Ok(Event::Key(key));
+        }
+        // a macro's keys wait for a command it ran, as though they'd been typed after it
+        if !self.replaying.is_empty() && self.mode != Mode::Running {
+            return self.read_key().map(Event::Key);
+        }
+        if let Some(event) = self.wait_out_flash()? {
+            if let Event::Key(key) = event {
+                self.record_key(key);
+            }
+            return Ok(event);
+        }
+        let timer = self.next_timer();
+        self.waiting_for = timer.map(|(_, timer)| timer);
+        let event = self.terminal.read_event_until(timer.map(|(due, _)| due))?;
+        if let Event::Key(key) = event {
+            self.record_key(key);
+        }
+        Ok(event)
+    }
+
+    // every key is read through these two, so a macro being played comes first
+    // and one being recorded sees everything typed, prompts included
+    fn read_key(&mut self) -> Result<Key, std::io::Error> {
+        if let Some(key) = self.replaying.pop_front() {
+            return Ok(key);
+        }
+        let key = match self.wait_out_flash()? {
+            Some(Event::Key(key)) => key,
+            _ => self.terminal.read_key()?,
         };
+        Ok(self.record_key(key))
+    }
 
-        status = format!("{} - {} lines", file_name, self.document.len());
-        let line_indicator = format!(
-            "{}/{}",
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
-        );
+    // a message for something that didn't work, with the bell if it's on
+    fn fail(&mut self, message: String) {
+        self.status_message = StatusMessage::from(message);
+        self.alert();
+    }
 
-        let len = status.len() + line_indicator.len();
-        if width > len {
-            status.push_str(&" ".repeat(width - status.len()));
+    fn alert(&mut self) {
+        let rung = if self.config.visual_bell {
+            self.flash_until = Some(Instant::now() + FLASH_TIME);
+            self.terminal.set_inverted(true)
+        } else if self.config.bell {
+            self.terminal.bell()
+        } else {
+            Ok(())
+        };
+        if let Err(error) = rung {
+            info!("couldn't ring the bell: {}", error);
         }
+    }
 
-        status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_bg_color(STATUS_FG_COLOR);
-        self.write_screen(&status);
-        Terminal::reset_bg_color();
-        Terminal::reset_fg_color();
+    // While the screen is flashed, waits for the flash to run out, or for
+    // something to come in before then, which ends it early.
+    fn wait_out_flash(&mut self) -> Result<Option<Event>, std::io::Error> {
+        let until = match self.flash_until.take() {
+            Some(until) => until,
+            None => return Ok(None),
+        };
+        let event = self.terminal.read_event_until(Some(until))?;
+        self.terminal.set_inverted(false)?;
+        Ok(Some(event).filter(|event| *event != Event::Tick))
     }
 
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
-        let message = &self.status_message;
-        if Instant::now() - message.time < Duration::new(5, 0){
-            let mut text = message.text.clone();
-            text.truncate(self.terminal().size().width as usize);
-            print!("{}", text);
+    fn try_read_key(&mut self) -> Result<Option<Key>, std::io::Error> {
+        if let Some(key) = self.replaying.pop_front() {
+            return Ok(Some(key));
         }
+        let key = self.terminal.try_read_key()?;
+        Ok(key.map(|key| self.record_key(key)))
     }
 
-    // this is essentially an init function
-    // for the struct
-    // with default values (but none for now)
-    pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        
-        let mut initial_status = String::from("HELP: Ctrl-Q = quit");
-        let document = if args.len() > 1 {
-            let filename = &args[1];
-            let doc = Document::open(&filename);
-            
-            if doc.is_ok() {
-                doc.unwrap()
-            } else {
-                initial_status = format!("ERR: Coould not open file: {}", filename);
-                Document::default()
+    fn record_key(&mut self, key: Key) -> Key {
+        if let Some(keys) = &mut self.recording {
+            keys.push(key);
+        }
+        key
+    }
+
+    // Alt-M starts recording, and pressing it again stops and offers to save it by name
+    fn toggle_recording(&mut self) -> Result<(), std::io::Error> {
+        let mut keys = match self.recording.take() {
+            Some(keys) => keys,
+            None => {
+                self.recording = Some(Vec::new());
+                self.status_message = StatusMessage::from("Recording a macro (Alt-M to stop)".to_string());
+                return Ok(());
             }
+        };
+        // the keys that stopped it
+        keys.truncate(self.recorded_before_key);
+        self.last_macro = keys;
+        let name = self.prompt("Save macro as (ESC to keep it for Alt-R only): ", |_, _, _| None)?;
+        if let Some(name) = name {
+            match macros::save(&name, &self.last_macro) {
+                Ok(()) => self.status_message = StatusMessage::from(format!("Saved macro {}", name)),
+                Err(error) => {
+                    self.status_message = StatusMessage::from(format!("Could not save macro: {}", error));
+                }
+            }
+        }
+        Ok(())
+    }
 
-        } else {
-            Document::default()
+    // the keys are handled one after another without redrawing, like they were typed
+    fn play_macro(&mut self, keys: Vec<Key>) -> Result<(), std::io::Error> {
+        if self.recording.is_some() || self.playing_macro {
+            self.fail("Can't play a macro from inside one".to_string());
+            return Ok(());
+        }
+        self.playing_macro = true;
+        self.replaying = keys.into();
+        let mut result = Ok(());
+        while !self.replaying.is_empty() && !self.should_quit && result.is_ok() {
+            result = self.process_event();
+        }
+        self.replaying.clear();
+        self.playing_macro = false;
+        result
+    }
+
+    // every action by what it does, for the ones whose keys don't come to mind
+    fn command_palette(&mut self) -> Result<(), std::io::Error> {
+        let commands: Vec<(Action, String)> = self
+            .keymap
+            .commands()
+            .into_iter()
+            .filter(|(action, _)| !matches!(action, Action::CommandPalette | Action::Cancel))
+            .map(|(action, keys)| (action, format!("{:<48} {}", action.description(), keys_name(keys))))
+            .collect();
+        let items: Vec<String> = commands.iter().map(|(_, item)| item.clone()).collect();
+        let chosen = self.pick("Command (ESC to cancel, Arrows to select): ", &items)?;
+        if let Some((action, _)) = commands.into_iter().find(|(_, item)| Some(item) == chosen.as_ref()) {
+            self.run_action(action)?;
+        }
+        Ok(())
+    }
+
+    fn pick_macro(&mut self) -> Result<(), std::io::Error> {
+        let names = macros::names();
+        if names.is_empty() {
+            self.fail("No saved macros".to_string());
+            return Ok(());
+        }
+        let chosen = self.pick("Macro (ESC to cancel, Arrows to select): ", &names)?;
+        if let Some(keys) = chosen.as_deref().and_then(macros::load) {
+            self.last_macro = keys.clone();
+            self.play_macro(keys)?;
+        }
+        Ok(())
+    }
+
+    // a paste arrives as a burst of keys, which is much faster to insert as one
+    // string than one keypress (and one redraw) at a time
+    // the first key that isn't text is kept for next_key
+    fn typeahead(&mut self, first: char) -> Result<String, std::io::Error> {
+        let mut text = first.to_string();
+        while let Some(key) = self.try_read_key()? {
+            match key {
+                Key::Char(c) => text.push(c),
+                _ => {
+                    self.pending_key = Some(key);
+                    break;
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    // a held arrow key can repeat faster than the screen redraws, so copies of
+    // it that have already arrived are counted and moved through in one go
+    // like typeahead, the first different key is kept for next_key
+    fn repeats_of(&mut self, key: Key) -> Result<usize, std::io::Error> {
+        let mut count = 1;
+        if !self.config.coalesce_keys {
+            return Ok(count);
+        }
+        while let Some(next) = self.try_read_key()? {
+            if next != key {
+                self.pending_key = Some(next);
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn auto_save(&mut self) {
+        // nobody's there to answer whether to overwrite it, so it's left for a real save
+        if self.config.confirm_overwrite
+            && (self.document.changed_on_disk() || self.document.read_only_on_disk())
+        {
+            self.status_message =
+                StatusMessage::from("Auto-save skipped, save by hand to overwrite the file".to_string());
+            return;
+        }
+        match self.document.save() {
+            Ok(()) => self.status_message = StatusMessage::from("auto-saved".to_string()),
+            Err(error) => {
+                self.fail(format!("Auto-save failed: {}", error));
+            }
+        }
+    }
+
+    fn save(&mut self) -> Result<(), std::io::Error> {
+        if self.document.file_name.is_none() {
+            let new_name = self.prompt("Save as: ", |_, _, _| None)?;
+            if new_name.is_none() {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Ok(());
+            }
+            self.document.file_name = new_name.map(|name| paths::expand(&name));
+        }
+        if self.config.confirm_overwrite && !self.confirm_overwrite()? {
+            self.status_message = StatusMessage::from("Save aborted.".to_string());
+            return Ok(());
+        }
+
+        match self.document.save() {
+            Ok(()) => {
+                self.status_message = StatusMessage::from("File saved successfully.".to_string());
+                self.saved();
+            }
+            Err(error) if error.kind() == ErrorKind::PermissionDenied && !self.config.sudo_command.trim().is_empty() => {
+                self.save_with_sudo()?;
+            }
+            Err(_) => self.fail("Error writing file!".to_string()),
+        }
+        Ok(())
+    }
+
+    fn saved(&mut self) {
+        self.lock_file();
+        let file_name = self.document.file_name.clone();
+        if let Some(file_name) = &file_name {
+            recent::add(file_name);
+        }
+        self.git_branch = git_branch_for(file_name.as_deref());
+        self.load_committed();
+    }
+
+    // the file's someone else's, so the buffer is piped through sudo_command
+    // to write it instead, if the user says so
+    fn save_with_sudo(&mut self) -> Result<(), std::io::Error> {
+        let file_name = self.document.file_name.clone().unwrap_or_default();
+        let sudo = self.config.sudo_command.trim().to_string();
+        let question = format!("Permission denied, save with {}? (y/n) ", sudo);
+        let answer = self.prompt(&question, |_, _, _| None)?;
+        if !answer.is_some_and(|answer| answer.starts_with('y')) {
+            self.status_message = StatusMessage::from("Save aborted.".to_string());
+            return Ok(());
+        }
+        let command = format!("{} {} > /dev/null", sudo, shell_quoted(&file_name));
+        self.start_command(&command, &self.document.contents(), AfterCommand::SudoSave);
+        Ok(())
+    }
+
+    fn saved_with_sudo(&mut self) {
+        let file_name = self.document.file_name.clone().unwrap_or_default();
+        let sudo = self.config.sudo_command.trim().to_string();
+        if let Err(error) = self.document.mark_saved() {
+            info!("couldn't read back {} after {}: {}", file_name, sudo, error);
+        }
+        self.status_message = StatusMessage::from(format!("Saved with {}", sudo));
+        self.saved();
+    }
+
+    // the rows the selection covers, or all of them without one
+    fn selected_rows(&self) -> Range<usize> {
+        match &self.selection {
+            Some(selection) => {
+                let (start, end) = ordered(&selection.anchor, &self.cursor_position);
+                // a text selection that ends at the very start of a row doesn't take any of it
+                let linear = selection.kind == SelectionKind::Linear;
+                let end_y = if linear && end.x == 0 && end.y > start.y { end.y } else { end.y + 1 };
+                start.y..end_y
+            }
+            None => 0..self.document.len(),
+        }
+    }
+
+    // Ctrl-X w, the rows the selection touches go to another file and this one's
+    // left as it is, or the whole buffer without a selection
+    fn save_selection(&mut self) -> Result<(), std::io::Error> {
+        let rows = self.selected_rows();
+        let what = if self.selection.is_some() { "selection" } else { "buffer" };
+        let file_name = match self.prompt(&format!("Save {} as: ", what), |_, _, _| None)? {
+            Some(file_name) => paths::expand(&file_name),
+            None => return Ok(()),
         };
+        let path = Path::new(&file_name);
+        if path.exists() {
+            let answer = self.prompt(&format!("{} exists, overwrite? (y/n) ", file_name), |_, _, _| None)?;
+            if !answer.is_some_and(|answer| answer.starts_with('y')) {
+                self.status_message = StatusMessage::from("Save aborted.".to_string());
+                return Ok(());
+            }
+        }
+        let count = rows.len();
+        match self.document.write_rows(path, rows) {
+            Ok(()) => {
+                self.selection = None;
+                self.status_message = StatusMessage::from(format!("Wrote {} lines to {}", count, file_name));
+            }
+            Err(error) => self.fail(format!("ERR: Could not write {}: {}", file_name, error)),
+        }
+        Ok(())
+    }
 
+    // Ctrl-X p, the screen as it was before asking, at the terminal's size
+    fn save_snapshot(&mut self) -> Result<(), std::io::Error> {
+        let snapshot = self.view().render_to_string(self.config.snapshot_ansi);
+        let file_name = match self.prompt("Save snapshot as: ", |_, _, _| None)? {
+            Some(file_name) => paths::expand(&file_name),
+            None => return Ok(()),
+        };
+        match fs::write(&file_name, snapshot) {
+            Ok(()) => self.status_message = StatusMessage::from(format!("Wrote snapshot to {}", file_name)),
+            Err(error) => self.fail(format!("ERR: Could not write {}: {}", file_name, error)),
+        }
+        Ok(())
+    }
 
+    // Ctrl-X Ctrl-R, back to the file as it's saved, asking first if that
+    // loses anything. Undo gets the changes back.
+    fn reload(&mut self) -> Result<(), std::io::Error> {
+        let file_name = match &self.document.file_name {
+            Some(file_name) if self.browsing.is_none() => file_name.clone(),
+            _ => {
+                self.fail("Nothing to reload, this buffer has no file".to_string());
+                return Ok(());
+            }
+        };
+        if self.document.is_dirty() {
+            let question = format!("Discard changes and reload {}? (y/n) ", file_name);
+            let answer = self.prompt(&question, |_, _, _| None)?;
+            if !answer.is_some_and(|answer| answer.starts_with('y')) {
+                self.status_message = StatusMessage::from("Reload aborted.".to_string());
+                return Ok(());
+            }
+        }
+        match self.document.reload() {
+            Ok(()) => {
+                self.selection = None;
+                self.cursors.clear();
+                self.set_cursor(self.cursor_position.clone());
+                self.status_message = StatusMessage::from("File reloaded".to_string());
+            }
+            Err(error) => self.fail(format!("ERR: Could not reload {}: {}", file_name, error)),
+        }
+        Ok(())
+    }
 
-        Self {
-            should_quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            document,
-            cursor_position: Position::default(),
-            offset: Position::default(),
-            screen: RefCell::new(AlternateScreen::from(stdout())),
-            status_message: StatusMessage::from(initial_status),
+    // false if saving should stop, which includes the user saving elsewhere
+    // and that file being a problem too
+    fn confirm_overwrite(&mut self) -> Result<bool, std::io::Error> {
+        loop {
+            let file_name = self.document.file_name.clone().unwrap_or_default();
+            if self.document.changed_on_disk() {
+                let question = format!("{} changed on disk since it was read, overwrite? (y/n) ", file_name);
+                let answer = self.prompt(&question, |_, _, _| None)?;
+                if !answer.is_some_and(|answer| answer.starts_with('y')) {
+                    return Ok(false);
+                }
+            }
+            if !self.document.read_only_on_disk() {
+                return Ok(true);
+            }
+            let question = format!("{} is read-only: (c)hmod and save, save (a)s, or cancel: ", file_name);
+            match self.prompt(&question, |_, _, _| None)?.as_deref() {
+                Some(answer) if answer.starts_with('c') => {
+                    if let Err(error) = make_writable(Path::new(&file_name)) {
+                        self.fail(format!("ERR: Could not chmod {}: {}", file_name, error));
+                        return Ok(false);
+                    }
+                    return Ok(true);
+                }
+                Some(answer) if answer.starts_with('a') => match self.prompt("Save as: ", |_, _, _| None)? {
+                    Some(new_name) => self.document.file_name = Some(paths::expand(&new_name)),
+                    None => return Ok(false),
+                },
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    // replaces the current document with filename
+    // on failure the current document is left alone
+    fn open(&mut self, filename: &str) {
+        if Path::new(filename).is_dir() {
+            self.browse(Path::new(filename));
+            return;
+        }
+        let opened = Document::open_with_progress(filename, |percent| {
+            self.draw_loading(percent);
+        });
+        match opened {
+            Ok(document) => {
+                recent::add(filename);
+                self.set_document(document);
+            }
+            // like most editors, opening a file that isn't there is how you make one
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                self.set_document(Document::new_file(filename));
+                self.status_message = StatusMessage::from(format!("New file: {}", filename));
+            }
+            Err(error) => {
+                self.fail(format!("ERR: Could not open {}: {}", filename, error));
+            }
+        }
+    }
+
+    // lists dir like a document, where Enter opens the entry on the cursor row
+    fn browse(&mut self, dir: &Path) {
+        match picker::dir_entries(dir) {
+            Ok(entries) => {
+                self.set_document(Document::from_lines(&entries));
+                self.browsing = Some(dir.to_path_buf());
+                self.status_message = StatusMessage::from("Enter opens a file or directory".to_string());
+            }
+            Err(error) => {
+                self.fail(format!("ERR: Could not open {}: {}", dir.display(), error));
+            }
+        }
+    }
+
+    fn open_browsed_entry(&mut self) {
+        let dir = match &self.browsing {
+            Some(dir) => dir.clone(),
+            None => return,
+        };
+        let entry = match self.document.row(self.cursor_position.y) {
+            Some(row) => row.as_str().to_string(),
+            None => return,
+        };
+        if entry == "../" {
+            self.browse(&parent_dir(&dir));
+        } else if let Some(subdir) = entry.strip_suffix('/') {
+            self.browse(&dir.join(subdir));
+        } else {
+            self.open(&dir.join(entry).to_string_lossy());
+        }
+    }
+
+    // toggled settings last the session, not just until the next file's
+    // [filetype] settings replace them
+    fn change_setting<F: Fn(&mut Config)>(&mut self, change: F) {
+        change(&mut self.config);
+        change(&mut self.global_config);
+    }
+
+    // everything that belongs to the previous document is reset here
+    fn set_document(&mut self, mut document: Document) {
+        self.config = self.global_config.for_filetype(document.syntax().name);
+        document.ensure_row();
+        document.set_undo_limit(self.config.max_undo);
+        document.set_highlight_limit(self.config.minified_line_length);
+        // the config is the fallback when the file doesn't give anything away
+        let configured_indent = if self.config.expand_tabs {
+            IndentStyle::Spaces(self.config.indent_step())
+        } else {
+            IndentStyle::Tabs
+        };
+        document.indent = if self.config.detect_indent {
+            document.detect_indent().unwrap_or(configured_indent)
+        } else {
+            configured_indent
+        };
+        self.git_branch = git_branch_for(document.file_name.as_deref());
+        self.large_file = self.is_large(&document);
+        // the last file's lock goes with it
+        self.lock = None;
+        self.read_only = false;
+        if self.large_file {
+            self.word_matches.clear();
+            self.word_matched_for = None;
+        }
+        self.document = document;
+        self.cursor_position = Position::default();
+        self.offset = Position::default();
+        self.last_edit = None;
+        self.edit_in_progress = false;
+        // a freshly opened file always starts in insert mode
+        self.overwrite = false;
+        self.backed_up = false;
+        self.backup_path = None;
+        self.cursors.clear();
+        self.selection = None;
+        self.browsing = None;
+        self.quit_warned = false;
+        self.place_quickfix_signs();
+        self.load_committed();
+        self.lock_file();
+        let (longest, y) = self.document.longest_row();
+        let limit = self.config.minified_line_length;
+        if limit > 0 && longest > limit {
+            let prettify = if self.config.prettify_command.is_empty() { "" } else { ", Ctrl-X b to prettify" };
+            self.status_message = StatusMessage::from(format!(
+                "Line {} is {} characters, looks minified: not highlighting it{}",
+                y + 1,
+                longest,
+                prettify
+            ));
+        }
+    }
+
+    // picking the file that's already open keeps it as it is, edits and all,
+    // rather than reading a second copy from disk over them
+    fn open_picked(&mut self, file_name: &str) {
+        if self.is_open(file_name) && !self.config.reopen_open_file {
+            self.status_message = StatusMessage::from("Switched to existing buffer".to_string());
+        } else if !self.refuse_if_dirty() {
+            self.open(file_name);
         }
     }
-}
 
-fn die(e: std::io::Error) {
-    print!("{}", termion::clear::All);
-    panic!("{}", e);
+    fn pick_file(&mut self) -> Result<(), std::io::Error> {
+        let files = picker::list_files(Path::new("."));
+        if let Some(file_name) = self.pick("Open (ESC to cancel, Arrows to select): ", &files)? {
+            self.open_picked(&file_name);
+        }
+        Ok(())
+    }
+
+    fn pick_recent(&mut self) -> Result<(), std::io::Error> {
+        let files = recent::load();
+        if files.is_empty() {
+            self.fail("No recent files".to_string());
+            return Ok(());
+        }
+        if let Some(file_name) = self.pick("Recent (ESC to cancel, Arrows to select): ", &files)? {
+            self.open_picked(&file_name);
+        }
+        Ok(())
+    }
+
+    // lets a file be highlighted as something other than what its extension says
+    fn pick_syntax(&mut self) -> Result<(), std::io::Error> {
+        let names: Vec<String> = SyntaxDefinition::all()
+            .iter()
+            .map(|definition| definition.name.to_string())
+            .collect();
+        let chosen = self.pick("File type (ESC to cancel, Arrows to select): ", &names)?;
+        if let Some(syntax) = chosen.as_deref().and_then(SyntaxDefinition::by_name) {
+            self.status_message = StatusMessage::from(format!("File type: {}", syntax.name));
+            self.config = self.global_config.for_filetype(syntax.name);
+            self.document.set_syntax(syntax);
+        }
+        Ok(())
+    }
+
+    // 50 (or 50%) goes to the row halfway down
+    fn goto_percent(&mut self) -> Result<(), std::io::Error> {
+        let answer = match self.prompt("Go to percent: ", |_, _, _| None)? {
+            Some(answer) => answer,
+            None => return Ok(()),
+        };
+        let percent: usize = match answer.trim().trim_end_matches('%').parse() {
+            Ok(percent) => percent,
+            Err(_) => {
+                self.fail(format!("Not a percentage: {}", answer));
+                return Ok(());
+            }
+        };
+        let y = percent.min(100) * self.document.len() / 100;
+        self.set_cursor(Position { x: 0, y });
+        Ok(())
+    }
+
+    // Alt-{ and Alt-} to the rows either side indented like this one, or with
+    // no direction Alt-U to the one it's indented under, at its first character
+    fn goto_indent_relative(&mut self, direction: Option<SearchDirection>) {
+        let y = self.cursor_position.y;
+        let tab_stop = self.config.tab_stop;
+        let target = match direction {
+            Some(direction) => self.document.next_sibling_line(y, direction, tab_stop),
+            None => self.document.parent_line(y, tab_stop),
+        };
+        match target {
+            Some(y) => {
+                let x = self.document.row(y).map_or(0, |row| row.indentation().len());
+                self.set_cursor(Position { x, y });
+            }
+            None if direction.is_some() => self.fail("No more rows at this indentation".to_string()),
+            None => self.fail("Nothing this row is indented under".to_string()),
+        }
+    }
+
+    // e.g. the output of `cargo build 2> errors`
+    fn load_quickfix(&mut self) -> Result<(), std::io::Error> {
+        let path = match self.prompt("Errors file: ", |_, _, _| None)? {
+            Some(path) => paths::expand(&path),
+            None => return Ok(()),
+        };
+        match quickfix::load(Path::new(&path)) {
+            Ok(entries) if entries.is_empty() => {
+                self.fail(format!("No locations in {}", path));
+            }
+            Ok(entries) => {
+                self.quickfix = entries;
+                self.quickfix_index = None;
+                self.place_quickfix_signs();
+                self.step_quickfix(SearchDirection::Forward);
+            }
+            Err(error) => {
+                self.fail(format!("Could not read {}: {}", path, error));
+            }
+        }
+        Ok(())
+    }
+
+    // a mark next to every row of this file that the quickfix list points at
+    fn place_quickfix_signs(&mut self) {
+        let signs = self
+            .quickfix
+            .iter()
+            .filter(|entry| self.is_open(&entry.file))
+            .map(|entry| Sign {
+                line: entry.line.saturating_sub(1),
+                glyph: "●".to_string(),
+                color: if entry.message.starts_with("warning") {
+                    WARNING_SIGN_COLOR
+                } else {
+                    ERROR_SIGN_COLOR
+                },
+            })
+            .collect();
+        self.signs.set("quickfix", signs);
+    }
+
+    // checked once at open, whatever the file grows or shrinks to after
+    fn is_large(&self, document: &Document) -> bool {
+        let bytes = document
+            .file_name
+            .as_ref()
+            .and_then(|file_name| fs::metadata(file_name).ok())
+            .map_or(0, |metadata| metadata.len());
+        let (byte_limit, line_limit) = (self.config.large_file_bytes, self.config.large_file_lines);
+        (byte_limit > 0 && bytes > byte_limit) || (line_limit > 0 && document.len() > line_limit)
+    }
+
+    // asked again on every save, since a commit may have happened in between
+    fn load_committed(&mut self) {
+        self.committed = self
+            .document
+            .file_name
+            .as_deref()
+            .and_then(|file_name| git::committed(Path::new(file_name)));
+        self.place_diff_signs();
+    }
+
+    // things that aren't worth redoing on every key, only once typing stops
+    fn idle_work_pending(&self) -> bool {
+        self.diff_is_stale() || self.touched_signs_are_stale() || self.words_are_stale()
+    }
+
+    fn do_idle_work(&mut self) {
+        if self.diff_is_stale() {
+            self.place_diff_signs();
+        }
+        if self.touched_signs_are_stale() {
+            self.place_touched_signs();
+        }
+        if self.words_are_stale() {
+            self.match_word_under_cursor();
+        }
+    }
+
+    fn word_state(&self) -> (Position, Position, usize) {
+        (self.cursor_position.clone(), self.offset.clone(), self.document.revision())
+    }
+
+    fn words_are_stale(&self) -> bool {
+        self.config.highlight_word && !self.large_file && self.word_matched_for != Some(self.word_state())
+    }
+
+    fn match_word_under_cursor(&mut self) {
+        self.word_matched_for = Some(self.word_state());
+        self.word_matches.clear();
+        let Position { x, y } = self.cursor_position;
+        let (start, end, word) = match self.document.row(y).and_then(|row| {
+            let (start, end) = row.word_at(x, &self.config.word_chars)?;
+            Some((start, end, row.substring(start, end)))
+        }) {
+            Some(found) => found,
+            None => return,
+        };
+        let first = self.offset.y;
+        for line in first..first + self.text_height() {
+            let row = match self.document.row(line) {
+                Some(row) => row,
+                None => break,
+            };
+            for (from, to) in row.words(&self.config.word_chars) {
+                let is_cursor_word = line == y && from == start;
+                let same = to - from == end - start && row.substring(from, to) == word;
+                if same && !is_cursor_word {
+                    self.word_matches.push(Position { x: from, y: line });
+                }
+            }
+        }
+        self.word_match_len = end - start;
+    }
+
+    fn diff_is_stale(&self) -> bool {
+        self.committed.is_some() && self.diffed_revision != Some(self.document.revision())
+    }
+
+    fn place_diff_signs(&mut self) {
+        self.diffed_revision = Some(self.document.revision());
+        let signs = match &self.committed {
+            Some(committed) => {
+                let old: Vec<&str> = committed
+                    .split('\n')
+                    .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                    .collect();
+                let new: Vec<&str> = self.document.lines().collect();
+                diff::line_changes(&old, &new)
+                    .into_iter()
+                    .map(|(line, change)| {
+                        let (glyph, color) = match change {
+                            LineChange::Added => ("+", ADDED_SIGN_COLOR),
+                            LineChange::Modified => ("~", MODIFIED_SIGN_COLOR),
+                            LineChange::Deleted => ("-", DELETED_SIGN_COLOR),
+                        };
+                        Sign {
+                            line,
+                            glyph: glyph.to_string(),
+                            color,
+                        }
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        self.signs.set("git", signs);
+    }
+
+    fn touched_signs_are_stale(&self) -> bool {
+        let state = (self.document.revision(), self.document.is_dirty());
+        self.config.touched_signs && self.touched_for != Some(state)
+    }
+
+    fn place_touched_signs(&mut self) {
+        self.touched_for = Some((self.document.revision(), self.document.is_dirty()));
+        let color = self.config.touched_sign_color.0;
+        let signs = self
+            .document
+            .touched_rows()
+            .map(|line| Sign {
+                line,
+                glyph: "▎".to_string(),
+                color,
+            })
+            .collect();
+        self.signs.set("touched", signs);
+    }
+
+    fn step_quickfix(&mut self, direction: SearchDirection) {
+        if self.quickfix.is_empty() {
+            self.fail("No errors loaded (Alt-E)".to_string());
+            return;
+        }
+        let last = self.quickfix.len() - 1;
+        let index = match (self.quickfix_index, direction) {
+            (None, _) => 0,
+            (Some(index), SearchDirection::Forward) if index < last => index + 1,
+            (Some(index), SearchDirection::Backward) if index > 0 => index - 1,
+            (Some(_), _) => {
+                self.fail("No more errors".to_string());
+                return;
+            }
+        };
+        let entry = self.quickfix[index].clone();
+        if !self.is_open(&entry.file) {
+            if self.refuse_if_dirty() {
+                return;
+            }
+            self.open(&entry.file);
+            if !self.is_open(&entry.file) {
+                return;
+            }
+        }
+        self.quickfix_index = Some(index);
+        self.set_cursor(Position {
+            x: entry.col.saturating_sub(1),
+            y: entry.line.saturating_sub(1),
+        });
+        self.status_message = StatusMessage::from(format!(
+            "[{}/{}] {}:{}: {}",
+            index + 1,
+            self.quickfix.len(),
+            entry.file,
+            entry.line,
+            entry.message
+        ));
+    }
+
+    // There's only one buffer, so a list of files to open becomes a quickfix
+    // list with an entry at the top of each, and Alt-N and Alt-P step through
+    // them. Files that can't be read are left out and named in the message bar.
+    fn open_file_list(&mut self, files: Vec<String>) {
+        let (readable, unreadable): (Vec<String>, Vec<String>) =
+            files.into_iter().partition(|file| fs::File::open(file).is_ok_and(|_| Path::new(file).is_file()));
+        self.quickfix = readable
+            .into_iter()
+            .map(|file| quickfix::Entry {
+                file,
+                line: 1,
+                col: 1,
+                message: String::new(),
+            })
+            .collect();
+        self.quickfix_index = None;
+        if self.quickfix.is_empty() {
+            self.status_message = StatusMessage::from("No files to open on stdin".to_string());
+        } else {
+            self.step_quickfix(SearchDirection::Forward);
+        }
+        if !unreadable.is_empty() {
+            self.fail(format!("Couldn't open {}: {}", unreadable.len(), unreadable.join(", ")));
+        }
+    }
+
+    fn is_open(&self, file_name: &str) -> bool {
+        let current = match &self.document.file_name {
+            Some(current) => current,
+            None => return false,
+        };
+        match (std::fs::canonicalize(current), std::fs::canonicalize(file_name)) {
+            (Ok(current), Ok(other)) => current == other,
+            _ => current == file_name,
+        }
+    }
+
+    // the next save writes the chosen line ending, whatever the file had before
+    fn pick_line_ending(&mut self) -> Result<(), std::io::Error> {
+        let names: Vec<String> = LineEnding::ALL.iter().map(|ending| ending.name().to_string()).collect();
+        let chosen = self.pick("Line endings (ESC to cancel, Arrows to select): ", &names)?;
+        if let Some(line_ending) = chosen.as_deref().and_then(LineEnding::by_name) {
+            self.status_message = StatusMessage::from(format!("Line endings: {}", line_ending.name()));
+            self.document.set_line_ending(line_ending);
+        }
+        Ok(())
+    }
+
+    // notes in a scratch buffer aren't anywhere else, so quitting warns about
+    // them the first time, other buffers quit straight away
+    fn quit(&mut self) {
+        if self.document.is_scratch() && self.document.is_dirty() && !self.quit_warned {
+            self.quit_warned = true;
+            self.status_message =
+                StatusMessage::from("The scratch buffer isn't saved, quit again to lose it".to_string());
+            return;
+        }
+        self.should_quit = true;
+    }
+
+    // Ctrl-X q, straight out whatever isn't saved. The terminal is put back
+    // the same as any other quit, on the way out of run.
+    fn force_quit(&mut self) {
+        if self.document.is_dirty() {
+            info!("force quit, dropping unsaved changes to {:?}", self.document.file_name);
+        }
+        self.should_quit = true;
+    }
+
+    fn refuse_read_only(&mut self) {
+        self.fail("The buffer is read-only, Ctrl-X Ctrl-Q to edit it anyway".to_string());
+    }
+
+    // With lock_files, takes the lock on the document's file if it's there
+    // and nobody else has it. When somebody does, the buffer goes read-only.
+    fn lock_file(&mut self) {
+        let file_name = match &self.document.file_name {
+            Some(file_name) if self.config.lock_files && self.lock.is_none() => file_name,
+            _ => return,
+        };
+        let file = match fs::File::open(file_name) {
+            Ok(file) => file,
+            // not there yet, it's locked on the first save
+            Err(_) => return,
+        };
+        match file.try_lock() {
+            Ok(()) => self.lock = Some(file),
+            Err(fs::TryLockError::WouldBlock) => {
+                self.read_only = true;
+                self.status_message = StatusMessage::from(
+                    "File is locked by another editor, opened read-only (Ctrl-X Ctrl-Q to edit anyway)".to_string(),
+                );
+            }
+            Err(fs::TryLockError::Error(error)) => info!("couldn't lock {}: {}", file_name, error),
+        }
+    }
+
+    // without multiple buffers, opening a file throws away the current one
+    fn refuse_if_dirty(&mut self) -> bool {
+        if self.document.is_dirty() {
+            self.fail("Save your changes before opening another file.".to_string());
+        }
+        self.document.is_dirty()
+    }
+
+    // lets the user fuzzy search through items, drawn as a list over the document
+    // returns None if cancelled or nothing matched
+    fn pick(&mut self, prompt: &str, items: &[String]) -> Result<Option<String>, std::io::Error> {
+        let mut candidates: Vec<&String> = items.iter().collect();
+        let mut selected: usize = 0;
+        let mut cancelled = false;
+        let mut last_query: Option<String> = None;
+        let list_height = (self.text_height() / 2).max(1);
+
+        self.overlay.lines = candidates.iter().take(list_height).map(|c| c.to_string()).collect();
+        self.overlay.selected = Some(0);
+        self.prompt(prompt, |editor, key, query| {
+            match key {
+                Key::Esc => cancelled = true,
+                Key::Up => selected = selected.saturating_sub(1),
+                Key::Down => selected = selected.saturating_add(1),
+                _ => (),
+            }
+            if last_query.as_ref() != Some(query) {
+                candidates = picker::fuzzy_filter(query, items);
+                selected = 0;
+                last_query = Some(query.clone());
+            }
+            selected = selected.min(candidates.len().saturating_sub(1));
+            // keep the selection on screen by scrolling the list with it
+            let first = selected.saturating_sub(list_height - 1);
+            editor.overlay.lines = candidates
+                .iter()
+                .skip(first)
+                .take(list_height)
+                .map(|c| c.to_string())
+                .collect();
+            editor.overlay.selected = Some(selected - first);
+            Some(format!("{} matches", candidates.len()))
+        })?;
+        self.overlay = Overlay::default();
+        self.status_message = StatusMessage::from(String::new());
+
+        if cancelled {
+            return Ok(None);
+        }
+        let chosen = candidates.get(selected).map(|c| c.to_string());
+        if chosen.is_none() {
+            self.fail("No matches".to_string());
+        }
+        Ok(chosen)
+    }
+
+    // reads a line of input in the message bar
+    // the callback runs after every keypress and can return a note to show after the input
+    // returns None if the prompt was cancelled with Esc
+    fn prompt<C>(&mut self, prompt: &str, callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &String) -> Option<String>,
+    {
+        self.prompt_in(Mode::Prompt, prompt, callback)
+    }
+
+    // prompt, with mode saying what sort of prompt it is
+    fn prompt_in<C>(&mut self, mode: Mode, prompt: &str, mut callback: C) -> Result<Option<String>, std::io::Error>
+    where
+        C: FnMut(&mut Self, Key, &String) -> Option<String>,
+    {
+        let mut result = String::new();
+        let mut note: Option<String> = None;
+        self.mode = mode;
+        while self.prompting() {
+            let mut text = format!("{}{}", prompt, result);
+            if let Some(note) = &note {
+                text = format!("{}  {}", text, note);
+            }
+            self.status_message = StatusMessage::from(text);
+            self.refresh_screen()?;
+            let key = self.read_key()?;
+            match key {
+                Key::Backspace => {
+                    result.pop();
+                }
+                Key::Char('\n') => break,
+                Key::Char(c) if !c.is_control() => result.push(c),
+                Key::Esc => {
+                    // the callback still hears about it, e.g. so the picker knows
+                    callback(self, key, &result);
+                    self.cancel();
+                    result.truncate(0);
+                    note = None;
+                    break;
+                }
+                _ => (),
+            }
+            note = callback(self, key, &result);
+        }
+        self.mode = Mode::Edit;
+        self.status_message = StatusMessage::from(note.unwrap_or_default());
+        if result.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(result))
+    }
+
+    fn prompting(&self) -> bool {
+        matches!(self.mode, Mode::Prompt | Mode::Search)
+    }
+
+    fn current_operation(&self) -> Option<Operation> {
+        if self.prompting() {
+            Some(Operation::Prompt)
+        } else if self.selection.is_some() {
+            Some(Operation::Selection)
+        } else if !self.cursors.is_empty() {
+            Some(Operation::Cursors)
+        } else if self.message_visible() && !self.status_message.text.is_empty() {
+            Some(Operation::Message)
+        } else if self.overwrite {
+            Some(Operation::Overwrite)
+        } else {
+            None
+        }
+    }
+
+    // what Esc does everywhere, so it only ever undoes one thing at a time
+    fn cancel(&mut self) -> Option<Operation> {
+        let operation = self.current_operation();
+        debug!("cancelling {:?}", operation);
+        match operation {
+            Some(Operation::Prompt) => self.mode = Mode::Edit,
+            Some(Operation::Selection) => self.selection = None,
+            Some(Operation::Cursors) => self.cursors.clear(),
+            Some(Operation::Message) => self.status_message = StatusMessage::from(String::new()),
+            Some(Operation::Overwrite) => self.overwrite = false,
+            None => (),
+        }
+        operation
+    }
+
+    fn search(&mut self) -> Result<(), std::io::Error> {
+        let old_position = self.cursor();
+        let mut direction = SearchDirection::Forward;
+        // only recomputed when the query changes, not when stepping between matches
+        let mut last_query = String::new();
+        let mut last_case = self.config.search_case;
+        // so the bell goes once when the query stops matching, not on every key after
+        let mut failing = false;
+        let query = self.prompt_in(
+            Mode::Search,
+            "Search (ESC to cancel, Arrows to navigate): ",
+            |editor, key, query| {
+                if key == Key::Alt('c') {
+                    editor.config.search_case = editor.config.search_case.next();
+                }
+                let case = editor.config.search_case;
+                let ignore_case = case.ignores_case(query);
+                if *query != last_query || case != last_case {
+                    editor.search_matches = editor.document.find_all(query, ignore_case);
+                    editor.search_match_len = query.graphemes(true).count();
+                    last_query = query.clone();
+                    last_case = case;
+                }
+                let case_note = match case {
+                    SearchCase::Smart => "",
+                    SearchCase::Sensitive => " (match case)",
+                    SearchCase::Insensitive => " (ignore case)",
+                };
+                let mut moved = false;
+                match key {
+                    // the cursor goes back where it was anyway
+                    Key::Esc => return None,
+                    Key::Right | Key::Down => {
+                        direction = SearchDirection::Forward;
+                        editor.move_cursor(Key::Right);
+                        moved = true;
+                    }
+                    Key::Left | Key::Up => direction = SearchDirection::Backward,
+                    _ => direction = SearchDirection::Forward,
+                }
+                if query.is_empty() {
+                    return Some(case_note.trim_start().to_string());
+                }
+                let wrap = editor.config.wrap_search;
+                let at = editor.cursor_position.clone();
+                if let Some(position) = editor.document.find(query, &at, direction, wrap, ignore_case) {
+                    failing = false;
+                    let wrapped = match direction {
+                        SearchDirection::Forward => position < at,
+                        SearchDirection::Backward => position > at,
+                    };
+                    let total = editor.search_matches.len();
+                    let mut note = match editor.search_matches.iter().position(|m| *m == position) {
+                        Some(index) => format!("[{}/{}]", index + 1, total),
+                        None => format!("[?/{}]", total),
+                    };
+                    if wrapped {
+                        note.push_str(" search wrapped");
+                    }
+                    note.push_str(case_note);
+                    editor.cursor_position = position;
+                    editor.scroll();
+                    Some(note)
+                } else {
+                    // keep the cursor put
+                    if moved {
+                        editor.move_cursor(Key::Left);
+                    }
+                    let start = Position::default();
+                    let stepped = matches!(key, Key::Right | Key::Down | Key::Left | Key::Up);
+                    if !failing || stepped {
+                        editor.alert();
+                    }
+                    failing = true;
+                    let found_anywhere = editor.document.find(query, &start, direction, true, ignore_case);
+                    if wrap || found_anywhere.is_none() {
+                        Some(format!("Pattern not found: {}{}", query, case_note))
+                    } else {
+                        Some(format!("No more matches{}", case_note))
+                    }
+                }
+            },
+        )?;
+        if query.is_none() {
+            self.cursor_position = old_position;
+            self.scroll();
+        }
+        self.search_matches.clear();
+        self.edit_in_progress = false;
+        Ok(())
+    }
+
+    fn move_cursor(&mut self, key: Key) {
+        if let Some(navigation) = key.navigation_func() {
+            self.cursor_position = navigation(&self.document, &self.config, &self.cursor_position);
+        }
+    }
+
+    // every change to the document goes through here
+    fn apply_edit(&mut self, edit: &Edit) {
+        self.backup();
+        let Position { x, y } = self.cursor_position;
+        let len = self.document.row(y).map_or(0, Row::len);
+        if x > len {
+            match edit {
+                // out in virtual space there's nothing to delete, Backspace just heads back
+                Edit::Delete(_) | Edit::AddToNumber(_) | Edit::Transpose => return,
+                Edit::Backspace(count) => {
+                    self.cursor_position.x = x.saturating_sub(*count).max(len);
+                    return;
+                }
+                // anything typed there fills the gap with spaces first
+                _ => {
+                    let end = Position { x: len, y };
+                    self.cursor_position = self.document.insert_str(&end, &" ".repeat(x - len));
+                }
+            }
+        }
+        match edit {
+            Edit::Insert(text) => {
+                self.cursor_position = self.document.insert_str(&self.cursor_position, text);
+            }
+            Edit::Overwrite(text) => {
+                for c in text.chars() {
+                    self.document.replace(&self.cursor_position, c);
+                    self.move_cursor(Key::Right);
+                }
+            }
+            // joining two rows either way leaves the cursor where the lower one's text starts
+            Edit::Delete(1) if x == len && y + 1 < self.document.len() => {
+                self.cursor_position = self.document.join_line(y, self.config.join_collapse_whitespace);
+            }
+            Edit::Backspace(1) if x == 0 && y > 0 => {
+                self.cursor_position = self.document.join_line(y - 1, self.config.join_collapse_whitespace);
+            }
+            Edit::Delete(count) => {
+                let at = self.cursor_position.clone();
+                let end = self.document.step(&at, *count, SearchDirection::Forward);
+                self.document.delete_range(&at, &end);
+            }
+            Edit::Backspace(count) => {
+                let at = self.cursor_position.clone();
+                let start = self.document.step(&at, *count, SearchDirection::Backward);
+                self.document.delete_range(&start, &at);
+                self.cursor_position = start;
+            }
+            Edit::AddToNumber(delta) => {
+                let found = self.document.row(y).and_then(|row| {
+                    let (range, value) = row.number_at(x)?;
+                    Some((row.substring(range.start, range.end), range, value))
+                });
+                let (text, range, value) = match found {
+                    Some(found) => found,
+                    None => return self.fail("No number under the cursor".to_string()),
+                };
+                let replacement = match renumbered(&text, value, *delta) {
+                    Some(replacement) => replacement,
+                    None => return self.fail("Number out of range".to_string()),
+                };
+                let start = Position { x: range.start, y };
+                self.document.delete_range(&start, &Position { x: range.end, y });
+                let end = self.document.insert_str(&start, &replacement);
+                // on its last digit, like vim
+                self.cursor_position = Position { x: end.x - 1, y };
+            }
+            // like emacs: the one before the cursor goes after the one under it and the
+            // cursor moves on, or at the end of a row the last two swap
+            Edit::Transpose => {
+                if x == 0 {
+                    return;
+                }
+                let first = if x == len { x.saturating_sub(2) } else { x - 1 };
+                if self.document.transpose(&Position { x: first, y }) {
+                    self.cursor_position.x = (first + 2).min(len);
+                }
+            }
+            Edit::Reflow(width) => {
+                if let Some(paragraph) = self.document.paragraph_at(self.cursor_position.y) {
+                    let start = paragraph.start;
+                    let count = self.document.reflow(paragraph, *width);
+                    // ready for the next paragraph, like gq
+                    self.cursor_position = Position { x: 0, y: start + count };
+                    if self.cursor_position.y >= self.document.len() {
+                        self.cursor_position.y = self.document.len().saturating_sub(1);
+                        self.move_cursor(Key::End);
+                    }
+                }
+            }
+        }
+    }
+
+    fn backup(&mut self) {
+        if self.backed_up || !self.config.backup {
+            return;
+        }
+        self.backed_up = true;
+        let dir = if self.config.backup_dir.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(&self.config.backup_dir))
+        };
+        match self.document.write_backup(dir.as_deref()) {
+            Ok(path) => self.backup_path = path,
+            Err(error) => {
+                self.fail(format!("Backup failed: {}", error));
+            }
+        }
+    }
+
+    fn remove_backup(&mut self) {
+        if self.config.keep_backup {
+            return;
+        }
+        if let Some(path) = self.backup_path.take() {
+            if let Err(error) = std::fs::remove_file(&path) {
+                info!("couldn't remove backup {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    // puts a cursor on every occurrence of the word under the cursor
+    // (or of a query, when the cursor isn't on a word)
+    fn add_cursors(&mut self) -> Result<(), std::io::Error> {
+        let Position { x, y } = self.cursor_position;
+        let word = self
+            .document
+            .row(y)
+            .and_then(|row| row.word_at(x, &self.config.word_chars).map(|(start, end)| row.substring(start, end)));
+        let whole_word = word.is_some();
+        let query = match word {
+            Some(word) => word,
+            None => match self.prompt("Edit all: ", |_, _, _| None)? {
+                Some(query) => query,
+                None => return Ok(()),
+            },
+        };
+        let len = query.graphemes(true).count();
+        let mut matches = self.document.find_all(&query, false);
+        // foo shouldn't put a cursor in the middle of foo_bar
+        if whole_word {
+            let document = &self.document;
+            let word_chars = &self.config.word_chars;
+            matches.retain(|m| {
+                let word = document.row(m.y).and_then(|row| row.word_at(m.x, word_chars));
+                word == Some((m.x, m.x + len))
+            });
+        }
+        if matches.is_empty() {
+            self.fail(format!("Pattern not found: {}", query));
+            return Ok(());
+        }
+        // the occurrence the cursor was in stays the main cursor
+        let primary = matches
+            .iter()
+            .position(|m| m.y == y && (m.x..=m.x + len).contains(&x))
+            .unwrap_or(0);
+        self.cursor_position = matches.remove(primary);
+        self.cursors = matches;
+        self.edit_in_progress = false;
+        self.scroll();
+        self.status_message = StatusMessage::from(format!(
+            "{} cursors on {} (ESC to go back to one)",
+            self.cursors.len() + 1,
+            query
+        ));
+        Ok(())
+    }
+
+    // starts a selection at the cursor, or stops it if it's already that kind
+    fn toggle_selection(&mut self, kind: SelectionKind) {
+        let anchor = match self.selection.take() {
+            Some(selection) if selection.kind == kind => return,
+            Some(selection) => selection.anchor,
+            None => self.cursor_position.clone(),
+        };
+        self.selection = Some(Selection { anchor, kind });
+        self.edit_in_progress = false;
+        let message = match kind {
+            SelectionKind::Linear => "Selecting text (ESC to stop)",
+            SelectionKind::Block => "Selecting a block, typing goes on every row (ESC to stop)",
+        };
+        self.status_message = StatusMessage::from(message.to_string());
+    }
+
+    // The start and end of each stretch of text a surround goes around: the
+    // selection, or each row of a block one, or else the word under the cursor.
+    fn surround_ranges(&self) -> Vec<(Position, Position)> {
+        let selection = match &self.selection {
+            Some(selection) => selection,
+            None => {
+                let Position { x, y } = self.cursor_position;
+                let word = self.document.row(y).and_then(|row| row.word_at(x, &self.config.word_chars));
+                return word.map_or(Vec::new(), |(start, end)| vec![(Position { x: start, y }, Position { x: end, y })]);
+            }
+        };
+        match selection.kind {
+            SelectionKind::Linear => {
+                let (start, end) = ordered(&selection.anchor, &self.cursor_position);
+                if start == end {
+                    return Vec::new();
+                }
+                vec![(start, end)]
+            }
+            SelectionKind::Block => {
+                let (top, bottom, left, right) = self.block_bounds(&selection.anchor);
+                let tab_stop = self.config.tab_stop;
+                (top..=bottom)
+                    .filter_map(|y| {
+                        let row = self.document.row(y)?;
+                        let from = row.display_col_to_char(left, tab_stop);
+                        let to = row.display_col_to_char(right, tab_stop);
+                        (from < to).then_some((Position { x: from, y }, Position { x: to, y }))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // Alt-S then a key: ( [ { or their closers put the pair around the text,
+    // < asks for a tag to wrap it in, and anything else goes on both sides
+    fn surround(&mut self) -> Result<(), std::io::Error> {
+        let ranges = self.surround_ranges();
+        if ranges.is_empty() {
+            self.fail("Nothing to surround".to_string());
+            return Ok(());
+        }
+        self.status_message = StatusMessage::from("Surround with: ".to_string());
+        self.refresh_screen()?;
+        let key = self.read_key()?;
+        self.status_message = StatusMessage::from(String::new());
+        let (open, close) = match key {
+            Key::Char('(') | Key::Char(')') => ("(".to_string(), ")".to_string()),
+            Key::Char('[') | Key::Char(']') => ("[".to_string(), "]".to_string()),
+            Key::Char('{') | Key::Char('}') => ("{".to_string(), "}".to_string()),
+            Key::Char('<') | Key::Char('>') => {
+                let tag = match self.prompt("Surround with tag: <", |_, _, _| None)? {
+                    Some(tag) if !tag.trim().is_empty() => tag,
+                    _ => return Ok(()),
+                };
+                let tag = tag.trim().trim_end_matches('>');
+                // attributes only go on the opening tag
+                let name = tag.split_whitespace().next().unwrap_or(tag);
+                (format!("<{}>", tag), format!("</{}>", name))
+            }
+            Key::Char(c) if !c.is_control() => (c.to_string(), c.to_string()),
+            _ => return Ok(()),
+        };
+        self.document.start_undo_group();
+        self.backup();
+        // from the last one back, so the ones before it stay where they were
+        for (start, end) in ranges.iter().rev() {
+            self.document.insert_str(end, &close);
+            self.document.insert_str(start, &open);
+        }
+        self.selection = None;
+        self.cursors.clear();
+        self.cursor_position = ranges[0].0.clone();
+        self.edit_in_progress = false;
+        Ok(())
+    }
+
+    // Ctrl-X ! runs a command and types its output in at the cursor, Ctrl-X r
+    // and Ctrl-X R put it on rows of its own below or above the cursor row
+    fn insert_output(&mut self, placement: Placement) -> Result<(), std::io::Error> {
+        let command = match self.prompt("Insert output of: ", |_, _, _| None)? {
+            Some(command) if !command.trim().is_empty() => command,
+            _ => return Ok(()),
+        };
+        self.start_command(&command, "", AfterCommand::Insert(placement));
+        Ok(())
+    }
+
+    fn insert_command_output(&mut self, command: &str, text: &str, placement: Placement) {
+        // the newline at the very end is the end of the last row, which
+        // the row the output goes on has already got
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        if text.is_empty() {
+            self.status_message = StatusMessage::from(format!("{} printed nothing", command));
+            return;
+        }
+        self.document.start_undo_group();
+        self.backup();
+        self.selection = None;
+        self.cursors.clear();
+        self.edit_in_progress = false;
+        let y = self.cursor_position.y;
+        match placement {
+            Placement::Cursor => {
+                self.cursor_position = self.document.insert_str(&self.cursor_position.clone(), text);
+            }
+            Placement::Below => {
+                let end = Position {
+                    x: self.document.row_len(y),
+                    y,
+                };
+                self.document.insert_str(&end, &format!("\n{}", text));
+                self.cursor_position = Position { x: 0, y: y + 1 };
+            }
+            Placement::Above => {
+                self.document.insert_str(&Position { x: 0, y }, &format!("{}\n", text));
+                self.cursor_position = Position { x: 0, y };
+            }
+        }
+        let rows = text.lines().count();
+        self.status_message = StatusMessage::from(format!("{} rows from {}", rows, command));
+    }
+
+    // Runs command on input, and once it's done, then is carried out with
+    // what it printed. The editor carries on drawing meanwhile, with the
+    // spinner going round so it's plain it hasn't hung, but the document
+    // can't be touched until the command finishes or is stopped.
+    fn start_command(&mut self, command: &str, input: &str, then: AfterCommand) {
+        match shell::spawn(command, input) {
+            Ok(running) => {
+                self.running = Some(RunningCommand {
+                    command: command.to_string(),
+                    started: Instant::now(),
+                    running,
+                    then,
+                });
+                self.mode = Mode::Running;
+                self.status_message = StatusMessage::from(String::new());
+            }
+            Err(error) => self.fail(format!("{}: {}", command, error)),
+        }
+    }
+
+    fn check_command(&mut self) -> Result<(), std::io::Error> {
+        let done = match self.running.as_mut().map(|running| running.running.is_done()) {
+            Some(Ok(done)) => done,
+            Some(Err(error)) => return Err(error),
+            None => return Ok(()),
+        };
+        if !done {
+            return Ok(());
+        }
+        if let Some(RunningCommand { command, running, then, .. }) = self.running.take() {
+            self.mode = Mode::Edit;
+            let output = running.output();
+            if let Some(text) = self.command_output(&command, output) {
+                match then {
+                    AfterCommand::Insert(placement) => self.insert_command_output(&command, &text, placement),
+                    AfterCommand::Prettify => self.prettified(&command, &text),
+                    AfterCommand::SudoSave => self.saved_with_sudo(),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Esc or Ctrl-C while a command runs
+    fn stop_command(&mut self) {
+        if let Some(RunningCommand { command, running, .. }) = self.running.take() {
+            self.mode = Mode::Edit;
+            match running.kill() {
+                Ok(()) => self.status_message = StatusMessage::from(format!("Stopped {}", command)),
+                Err(error) => self.fail(format!("Couldn't stop {}: {}", command, error)),
+            }
+        }
+    }
+
+    // what the command printed, or None once it's been said why not
+    fn command_output(&mut self, command: &str, output: io::Result<process::Output>) -> Option<String> {
+        let output = match output {
+            Ok(output) => output,
+            Err(error) => {
+                self.fail(format!("{}: {}", command, error));
+                return None;
+            }
+        };
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().find(|line| !line.trim().is_empty()).map_or_else(
+                || output.status.to_string(),
+                |line| line.trim().to_string(),
+            );
+            self.fail(format!("{}: {}", command, reason));
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+    }
+
+    // Ctrl-X b, the whole buffer through prettify_command, for minified files
+    // that are one enormous row. Undoes in one go.
+    fn prettify(&mut self) -> Result<(), std::io::Error> {
+        let command = self.config.prettify_command.clone();
+        if command.trim().is_empty() {
+            self.fail("Set prettify_command to a formatter first, e.g. prettify_command = jq .".to_string());
+            return Ok(());
+        }
+        let input = self.document.lines().collect::<Vec<_>>().join("\n");
+        self.start_command(&command, &input, AfterCommand::Prettify);
+        Ok(())
+    }
+
+    fn prettified(&mut self, command: &str, text: &str) {
+        // a newline at the end is kept, as the empty row after it, just like opening a file
+        if text.is_empty() {
+            self.fail(format!("{} printed nothing, leaving the buffer be", command));
+            return;
+        }
+        self.backup();
+        self.selection = None;
+        self.cursors.clear();
+        self.edit_in_progress = false;
+        self.document.replace_all(text);
+        self.set_cursor(Position::default());
+        self.status_message = StatusMessage::from(format!("{} rows from {}", self.document.len(), command));
+    }
+
+    // an edit made with a selection going uses it up first, a linear selection is
+    // replaced and a block one gets the edit on every row, like vim's block insert
+    // returns true if there's nothing left of the edit to do
+    fn edit_selection(&mut self, edit: &Edit) -> bool {
+        let selection = match self.selection.take() {
+            Some(selection) => selection,
+            None => return false,
+        };
+        // rewrapping goes by paragraph and the rest by the cursor, they just drop the selection
+        if let Edit::Reflow(_) | Edit::AddToNumber(_) | Edit::Transpose = edit {
+            return false;
+        }
+        let deleting = matches!(edit, Edit::Delete(_) | Edit::Backspace(_));
+        match selection.kind {
+            SelectionKind::Linear => {
+                let (start, end) = ordered(&selection.anchor, &self.cursor_position);
+                if start == end {
+                    return false;
+                }
+                self.cursors.clear();
+                self.backup();
+                self.document.delete_range(&start, &end);
+                self.cursor_position = start;
+                deleting
+            }
+            SelectionKind::Block => self.edit_block(&selection.anchor, deleting),
+        }
+    }
+
+    fn edit_block(&mut self, anchor: &Position, deleting: bool) -> bool {
+        let (top, bottom, left, right) = self.block_bounds(anchor);
+        let tab_stop = self.config.tab_stop;
+        self.cursors.clear();
+        self.backup();
+        if deleting && left < right {
+            for y in top..=bottom {
+                if let Some(row) = self.document.row(y) {
+                    let from = row.display_col_to_char(left, tab_stop);
+                    let to = row.display_col_to_char(right, tab_stop);
+                    self.document.delete_range(&Position { x: from, y }, &Position { x: to, y });
+                }
+            }
+            let x = self.document.row(top).map_or(0, |row| row.display_col_to_char(left, tab_stop));
+            self.cursor_position = Position { x, y: top };
+            return true;
+        }
+        // otherwise there's a cursor on every row at the left edge,
+        // which stay behind so that typing carries on there
+        let mut cursors = Vec::new();
+        for y in top..=bottom {
+            let (len, display_len, x) = match self.document.row(y) {
+                Some(row) => (row.len(), row.display_len(tab_stop), row.display_col_to_char(left, tab_stop)),
+                None => continue,
+            };
+            if display_len >= left {
+                cursors.push(Position { x, y });
+            } else if self.config.block_pad_short_rows && !deleting {
+                let padding = " ".repeat(left - display_len);
+                cursors.push(self.document.insert_str(&Position { x: len, y }, &padding));
+            }
+        }
+        if cursors.is_empty() {
+            return true;
+        }
+        self.cursor_position = cursors.remove(0);
+        self.cursors = cursors;
+        false
+    }
+
+    // cursors that have ended up in the same place become one
+    fn merge_cursors(&mut self) {
+        let primary = self.cursor_position.clone();
+        self.cursors.retain(|c| *c != primary);
+        self.cursors.sort();
+        self.cursors.dedup();
+    }
+
+    // with multiple cursors, edits that would split or join rows are skipped at the
+    // other cursors, and at the main cursor they go back to a single cursor
+    fn stays_on_row(&self, edit: &Edit, at: &Position) -> bool {
+        let len = self.document.row(at.y).map_or(0, Row::len);
+        match edit {
+            Edit::Insert(text) | Edit::Overwrite(text) => !text.contains('\n'),
+            Edit::Delete(count) => at.x + count <= len,
+            Edit::Backspace(count) => *count <= at.x,
+            Edit::Reflow(_) => false,
+            Edit::AddToNumber(_) | Edit::Transpose => true,
+        }
+    }
+
+    // applies the edit at every cursor, from the last one in the document to the first
+    // so an edit never moves the cursors that haven't had it yet
+    fn apply_edit_everywhere(&mut self, edit: &Edit) {
+        if self.cursors.is_empty() {
+            self.apply_edit(edit);
+            return;
+        }
+        let primary = self.cursor_position.clone();
+        if !self.stays_on_row(edit, &primary) {
+            self.cursors.clear();
+            self.apply_edit(edit);
+            return;
+        }
+        let mut cursors: Vec<(Position, bool)> = self.cursors.drain(..).map(|c| (c, false)).collect();
+        cursors.push((primary, true));
+        cursors.sort();
+        let mut done: Vec<(Position, bool)> = Vec::new();
+        while let Some((at, is_primary)) = cursors.pop() {
+            if !self.stays_on_row(edit, &at) {
+                done.push((at, is_primary));
+                continue;
+            }
+            let len_before = self.document.row(at.y).map_or(0, Row::len);
+            self.cursor_position = at.clone();
+            self.apply_edit(edit);
+            let moved_to = self.cursor_position.clone();
+            let len_after = self.document.row(at.y).map_or(0, Row::len);
+            // the cursors after this one on the same row shift along with the text
+            let changed_from = at.x.min(moved_to.x);
+            for (position, _) in done.iter_mut().filter(|(p, _)| p.y == at.y) {
+                let shifted = (position.x + len_after).saturating_sub(len_before);
+                position.x = shifted.max(changed_from);
+            }
+            done.push((moved_to, is_primary));
+        }
+        for (position, is_primary) in done {
+            if is_primary {
+                self.cursor_position = position;
+            } else {
+                self.cursors.push(position);
+            }
+        }
+        self.merge_cursors();
+    }
+
+    fn record_edit(&mut self, edit: Edit) {
+        let extended = self.selection.is_none()
+            && self.edit_in_progress
+            && self.last_edit.as_mut().is_some_and(|last_edit| last_edit.extend(&edit));
+        // whatever extends the last edit is undone along with it
+        if !extended {
+            self.document.start_undo_group();
+        }
+        if self.edit_selection(&edit) {
+            self.edit_in_progress = false;
+            return;
+        }
+        self.apply_edit_everywhere(&edit);
+        if !extended {
+            self.last_edit = Some(edit);
+            self.edit_in_progress = true;
+        }
+    }
+
+    fn undo(&mut self) {
+        match self.document.undo() {
+            Some(at) => self.jump_after_history(at),
+            None => self.fail("Nothing to undo".to_string()),
+        }
+    }
+
+    fn redo(&mut self) {
+        match self.document.redo() {
+            Some(at) => self.jump_after_history(at),
+            None => self.fail("Nothing to redo".to_string()),
+        }
+    }
+
+    // undoing and then editing leaves what was undone on a branch of its own,
+    // these go by when edits were made instead, so they can get back to it
+    fn undo_in_time(&mut self, forward: bool) {
+        match self.document.undo_in_time(forward) {
+            Some(at) => self.jump_after_history(at),
+            None if forward => self.fail("Already at the newest edit".to_string()),
+            None => self.fail("Already at the oldest edit".to_string()),
+        }
+    }
+
+    fn pick_undo(&mut self) -> Result<(), std::io::Error> {
+        let tree = self.document.undo_tree();
+        let lines: Vec<String> = tree.iter().map(|(_, line)| line.clone()).collect();
+        let chosen = self.pick("Undo to (ESC to cancel, Arrows to select): ", &lines)?;
+        let id = tree.into_iter().find(|(_, line)| Some(line) == chosen.as_ref()).map(|(id, _)| id);
+        if let Some(at) = id.and_then(|id| self.document.undo_to(id)) {
+            self.jump_after_history(at);
+        }
+        Ok(())
+    }
+
+    fn jump_after_history(&mut self, at: Position) {
+        self.cursor_position = at;
+        self.cursors.clear();
+        self.selection = None;
+        // the next edit starts a group of its own
+        self.edit_in_progress = false;
+    }
+
+    fn insert_tab(&mut self) {
+        let edit = match self.document.indent {
+            IndentStyle::Tabs => Edit::Insert("\t".to_string()),
+            // pad out to the next tab stop, by screen column so a literal tab
+            // or wide text earlier in the row is allowed for
+            IndentStyle::Spaces(width) => {
+                let width = width.max(1);
+                let count = width - self.cursor_display_x() % width;
+                Edit::Insert(" ".repeat(count))
+            }
+        };
+        if self.overwrite {
+            if let Edit::Insert(text) = edit {
+                self.record_edit(Edit::Overwrite(text));
+            }
+        } else {
+            self.record_edit(edit);
+        }
+    }
+
+    // Enter with auto_indent, see the config for what it does
+    fn insert_newline(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let (indent, before, after) = match self.document.row(y) {
+            Some(row) => {
+                // with the cursor inside the indentation, only what's before it carries on
+                let indent: String = row.indentation().chars().take(x).collect();
+                let char_at = |at: usize| row.substring(at, at + 1).chars().next();
+                (indent, x.checked_sub(1).and_then(char_at), char_at(x))
+            }
+            None => (String::new(), None, None),
+        };
+        if let Some((leader, rest)) = self.comment_leader() {
+            if rest.trim().is_empty() {
+                self.record_edit(Edit::Backspace(x - indent.len()));
+            } else {
+                self.record_edit(Edit::Insert(format!("\n{}{}", indent, leader)));
+            }
+            return;
+        }
+        let closer = self
+            .document
+            .syntax()
+            .blocks
+            .iter()
+            .find(|(open, _)| self.config.indent_after_opener && before == Some(*open))
+            .map(|(_, close)| *close);
+        let closer = match closer {
+            Some(closer) => closer,
+            None => {
+                self.record_edit(Edit::Insert(format!("\n{}", indent)));
+                return;
+            }
+        };
+        let level = match self.document.indent {
+            IndentStyle::Tabs => "\t".to_string(),
+            IndentStyle::Spaces(width) => " ".repeat(width.max(1)),
+        };
+        let inner = format!("{}{}", indent, level);
+        if after == Some(closer) {
+            self.record_edit(Edit::Insert(format!("\n{}\n{}", inner, indent)));
+            // back up to the row in between
+            self.cursor_position = Position {
+                x: inner.graphemes(true).count(),
+                y: y + 1,
+            };
+        } else {
+            self.record_edit(Edit::Insert(format!("\n{}", inner)));
+        }
+    }
+
+    // Ctrl-X o and Ctrl-X O, like vim's o and O: an empty row below or above
+    // this one without splitting it, indented to match with auto_indent on,
+    // or a level further in below a row that opens a block
+    fn open_row(&mut self, placement: Placement) {
+        let y = self.cursor_position.y;
+        let mut indent = String::new();
+        if let (true, Some(row)) = (self.config.auto_indent, self.document.row(y)) {
+            indent.push_str(row.indentation());
+            let last = row.as_str().trim_end().chars().last();
+            let opens = self.document.syntax().blocks.iter().any(|(open, _)| last == Some(*open));
+            if placement == Placement::Below && self.config.indent_after_opener && opens {
+                match self.document.indent {
+                    IndentStyle::Tabs => indent.push('\t'),
+                    IndentStyle::Spaces(width) => indent.push_str(&" ".repeat(width.max(1))),
+                }
+            }
+        }
+        self.backup();
+        self.selection = None;
+        self.cursors.clear();
+        self.edit_in_progress = false;
+        self.document.start_undo_group();
+        self.cursor_position = match placement {
+            Placement::Above => self.document.insert_row_above(y, &indent),
+            _ => self.document.insert_row_below(y, &indent),
+        };
+        self.scroll();
+    }
+
+    // With the cursor past the comment marker of a row that's a line comment,
+    // the marker and the blanks after it, which carry on to the next row, and
+    // the rest of the row after those. Doubled markers like /// or
+    // ## and inner doc comments like //! are carried on whole.
+    fn comment_leader(&self) -> Option<(String, String)> {
+        let marker = self.document.syntax().line_comment?;
+        if !self.config.continue_comments {
+            return None;
+        }
+        let Position { x, y } = self.cursor_position;
+        let row = self.document.row(y)?;
+        let indent = row.indentation().len();
+        let text = row.substring(indent, x);
+        let after = text.strip_prefix(marker)?;
+        let last = marker.chars().last()?;
+        let after = after.trim_start_matches([last, '!']);
+        let marked = text.len() - after.len();
+        let blanks = after.len() - after.trim_start_matches([' ', '\t']).len();
+        let (leader, rest) = text.split_at(marked + blanks);
+        let rest = format!("{}{}", rest, row.substring(x, row.len()));
+        Some((leader.to_string(), rest))
+    }
+
+    // the rows of a paste moved so its least indented row lines up with the
+    // cursor's indentation, and the rest keep their places relative to it
+    fn reindented_paste(&self, text: &str) -> String {
+        let Position { x, y } = self.cursor_position;
+        let (indent, in_indent, rest_of_row) = match self.document.row(y) {
+            Some(row) => {
+                let indent = row.indentation();
+                (indent.chars().take(x).collect(), x <= indent.len(), x < row.len())
+            }
+            None => (String::new(), true, false),
+        };
+        let tab_stop = self.config.tab_stop;
+        let base = text
+            .split('\n')
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| indent_width(line, tab_stop))
+            .min()
+            .unwrap_or(0);
+        let mut result = String::new();
+        let last = text.split('\n').count() - 1;
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                result.push('\n');
+            }
+            if line.trim().is_empty() {
+                // whatever was after the cursor ends up on the last row
+                if i == last && rest_of_row {
+                    result.push_str(&indent);
+                }
+                continue;
+            }
+            // the first row goes where the cursor is, which only has the
+            // indentation to stand in for when the cursor's inside it
+            if i == 0 && !in_indent {
+                result.push_str(line);
+                continue;
+            }
+            if i > 0 {
+                result.push_str(&indent);
+            }
+            result.push_str(&dedented(line, base, tab_stop));
+        }
+        result
+    }
+
+    // in the indentation of a soft-tabbed row, Backspace goes back a whole
+    // tab stop, the way Tab went forward one
+    fn backspace(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        let mut count = 1;
+        if let IndentStyle::Spaces(width) = self.document.indent {
+            let width = width.max(1);
+            let in_indent = self
+                .document
+                .row(y)
+                .is_some_and(|row| row.substring(0, x).chars().all(|c| c == ' '));
+            if x > 0 && in_indent {
+                count = x - (x - 1) / width * width;
+            }
+        }
+        self.record_edit(Edit::Backspace(count));
+    }
+
+    fn reflow(&mut self) {
+        if self.document.paragraph_at(self.cursor_position.y).is_none() {
+            self.fail("Not in a paragraph".to_string());
+            return;
+        }
+        // a reflow never runs on into the next keypress
+        self.edit_in_progress = false;
+        self.record_edit(Edit::Reflow(self.config.text_width.max(1)));
+        self.edit_in_progress = false;
+    }
+
+    fn retab(&mut self, to_spaces: bool) {
+        let tab_stop = self.config.tab_stop;
+        let col = self.cursor_display_x();
+        self.edit_in_progress = false;
+        self.document.start_undo_group();
+        let changed = self.document.retab(to_spaces, tab_stop, self.config.retab_whole_line);
+        // the cursor stays over the same column
+        if let Some(row) = self.document.row(self.cursor_position.y) {
+            self.cursor_position.x = row.display_col_to_char(col, tab_stop);
+        }
+        let style = if to_spaces { "spaces" } else { "tabs" };
+        self.status_message = StatusMessage::from(format!("{} rows changed to {}", changed, style));
+    }
+
+    // Ctrl-X =, asks for a sum and types the answer in at the cursor
+    fn calculate(&mut self) -> Result<(), std::io::Error> {
+        let expression = match self.prompt("Calculate: ", |_, _, _| None)? {
+            Some(expression) if !expression.trim().is_empty() => expression,
+            _ => return Ok(()),
+        };
+        match calc::evaluate(&expression) {
+            Ok(value) => {
+                let answer = calc::format(value);
+                self.status_message = StatusMessage::from(format!("{} = {}", expression.trim(), answer));
+                self.record_edit(Edit::Insert(answer));
+            }
+            Err(error) => self.fail(format!("Can't work out {}: {}", expression.trim(), error)),
+        }
+        Ok(())
+    }
+
+    // Ctrl-X a, for command output pasted in with its colours
+    // works on the selected rows, or all of them
+    fn strip_ansi(&mut self) {
+        let rows = self.selected_rows();
+        self.edit_in_progress = false;
+        let changed = self.document.strip_ansi(rows);
+        self.selection = None;
+        self.set_cursor(self.cursor_position.clone());
+        self.status_message = StatusMessage::from(format!("Stripped escape codes from {} rows", changed));
+    }
+
+    // Ctrl-X v, the selected rows (or all of them) in the opposite order
+    // the selection stays where it was, over the same rows
+    fn reverse_lines(&mut self) {
+        let rows = self.selected_rows();
+        self.edit_in_progress = false;
+        self.document.reverse_rows(rows.clone());
+        let clamp = |document: &Document, position: &mut Position| {
+            position.x = position.x.min(document.row(position.y).map_or(0, Row::len));
+        };
+        clamp(&self.document, &mut self.cursor_position);
+        if let Some(selection) = &mut self.selection {
+            clamp(&self.document, &mut selection.anchor);
+        }
+        self.status_message = StatusMessage::from(format!("Reversed {} lines", rows.len()));
+    }
+
+    // Ctrl-K, like emacs: the rest of the row goes into the kill register, or
+    // with nothing after the cursor the newline, joining the next row on
+    // kills one after another undo together and yank back as one
+    fn kill_line(&mut self) -> Result<(), std::io::Error> {
+        let Position { x, y } = self.cursor();
+        let len = self.document.row(y).map_or(0, Row::len);
+        let at = Position { x: x.min(len), y };
+        let (end, text) = if at.x < len {
+            (Position { x: len, y }, self.document.row(y).map_or(String::new(), |row| row.substring(at.x, len)))
+        } else if y + 1 < self.document.len() {
+            (Position { x: 0, y: y + 1 }, "\n".to_string())
+        } else {
+            self.fail("Nothing to kill".to_string());
+            return Ok(());
+        };
+        if self.kill_chain != Some((self.cursor_position.clone(), self.document.revision())) {
+            self.kill_register.clear();
+            self.document.start_undo_group();
+        }
+        self.backup();
+        self.selection = None;
+        self.cursors.clear();
+        self.edit_in_progress = false;
+        self.document.delete_range(&at, &end);
+        self.kill_register.push_str(&text);
+        self.cursor_position = at;
+        self.kill_chain = Some((self.cursor_position.clone(), self.document.revision()));
+        if self.config.kill_to_clipboard {
+            self.terminal.set_clipboard(&self.kill_register)?;
+        }
+        Ok(())
+    }
+
+    // Alt-Y, the kill register goes in at the cursor, or over the selection
+    fn yank(&mut self) {
+        if self.kill_register.is_empty() {
+            self.fail("Nothing to yank".to_string());
+            return;
+        }
+        self.edit_in_progress = false;
+        self.record_edit(Edit::Insert(self.kill_register.clone()));
+        // typing straight after is an edit of its own
+        self.edit_in_progress = false;
+    }
+
+    // Ctrl-V, the next key goes into the document as is instead of being a command
+    fn insert_literal(&mut self) -> Result<(), std::io::Error> {
+        self.status_message = StatusMessage::from("^V".to_string());
+        self.refresh_screen()?;
+        let key = self.read_key()?;
+        self.status_message = StatusMessage::from(String::new());
+        let c = match key {
+            Key::Char(c) => c,
+            Key::Ctrl(c) if c.is_ascii_lowercase() => (c as u8 - b'a' + 1) as char,
+            Key::Ctrl(c @ '4'..='7') => (c as u8 - b'4' + 0x1c) as char,
+            Key::Esc => '\x1b',
+            Key::Backspace => '\x7f',
+            Key::Null => '\0',
+            _ => {
+                self.fail("Can't insert that key literally".to_string());
+                return Ok(());
+            }
+        };
+        let text = match self.config.control_chars {
+            // a newline is left alone, or it would split the row
+            ControlCharStyle::Caret if c.is_control() && c != '\t' && c != '\n' => {
+                format!("^{}", ((c as u8) ^ 0x40) as char)
+            }
+            _ => c.to_string(),
+        };
+        if self.overwrite {
+            self.record_edit(Edit::Overwrite(text));
+        } else {
+            self.record_edit(Edit::Insert(text));
+        }
+        Ok(())
+    }
+
+    // shows the cursor row in the message bar, even the parts scrolled off screen
+    // rows wider than the screen are shown as a window around the cursor
+    // pressed again without moving, it copies the row to the kill register
+    fn peek_line(&mut self) -> Result<(), std::io::Error> {
+        let Position { x, y } = self.cursor_position;
+        let row = match self.document.row(y) {
+            Some(row) => row,
+            None => return Ok(()),
+        };
+        let here = Some((self.cursor_position.clone(), self.document.revision()));
+        if self.peeked == here {
+            self.kill_register = row.as_str().to_string();
+            self.peeked = None;
+            if self.config.kill_to_clipboard {
+                self.terminal.set_clipboard(&self.kill_register)?;
+            }
+            self.status_message = StatusMessage::from("Copied the row, Alt-Y pastes it".to_string());
+            return Ok(());
+        }
+        self.peeked = here;
+        let tab_stop = self.config.tab_stop;
+        let line: Vec<char> = row.expanded(tab_stop).chars().collect();
+        let cursor_col = row.char_to_display_col(x, tab_stop);
+        let width = self.terminal.size().width as usize;
+
+        let text = if line.len() <= width {
+            line.iter().collect()
+        } else {
+            let start = cursor_col
+                .saturating_sub(width / 2)
+                .min(line.len().saturating_sub(width));
+            let end = start + width;
+            let mut window: String = line[start..end].iter().collect();
+            if start > 0 {
+                window = format!("…{}", window.chars().skip(1).collect::<String>());
+            }
+            if end < line.len() {
+                window.pop();
+                window.push('…');
+            }
+            window
+        };
+        self.status_message = StatusMessage::from(text);
+        Ok(())
+    }
+
+    fn repeat_last_edit(&mut self) {
+        if let Some(edit) = self.last_edit.clone() {
+            info!("repeating {:?}", edit);
+            self.document.start_undo_group();
+            self.apply_edit_everywhere(&edit);
+            self.edit_in_progress = false;
+        }
+    }
+
+    // the rows and columns of the document that fit, as the view lays them out
+    fn text_height(&self) -> usize {
+        self.view().text_height()
+    }
+
+    fn text_width(&self) -> usize {
+        self.view().text_width()
+    }
+
+    fn cursor_display_x(&self) -> usize {
+        self.view().cursor_display_x()
+    }
+
+    fn block_bounds(&self, anchor: &Position) -> (usize, usize, usize, usize) {
+        self.view().block_bounds(anchor)
+    }
+
+    // Ctrl-E and Ctrl-W, and the mouse wheel: the view moves and the cursor
+    // stays put, unless it would end up off the screen (or inside the margin),
+    // in which case it's pulled along to the nearest row still in view
+    fn scroll_view(&mut self, direction: SearchDirection) {
+        let step = self.config.scroll_step.max(1);
+        let last = self.document.len().saturating_sub(1);
+        self.offset.y = match direction {
+            SearchDirection::Forward => self.offset.y.saturating_add(step).min(last),
+            SearchDirection::Backward => self.offset.y.saturating_sub(step),
+        };
+        let margin = self.scroll_margin();
+        let top = if self.offset.y == 0 { 0 } else { self.offset.y + margin };
+        let bottom = (self.offset.y + self.text_height()).saturating_sub(margin + 1).max(top);
+        let y = self.cursor_position.y.clamp(top, bottom).min(last);
+        if y != self.cursor_position.y {
+            self.goal_column = Some(self.cursor_display_x());
+            self.cursor_position.y = y;
+            self.restore_goal_column();
+            self.edit_in_progress = false;
+        }
+    }
+
+    // puts the cursor as close to the goal column as its row allows, by the column
+    // on screen rather than the grapheme, so tabs above and below don't throw it off
+    fn restore_goal_column(&mut self) {
+        let goal = match self.goal_column {
+            Some(goal) => goal,
+            None => return,
+        };
+        let tab_stop = self.config.tab_stop;
+        let Position { y, .. } = self.cursor_position;
+        let row = match self.document.row(y) {
+            Some(row) => row,
+            None => return,
+        };
+        let row_end = row.display_len(tab_stop);
+        let x = if goal >= row_end {
+            let past_end = if self.config.virtual_edit { goal - row_end } else { 0 };
+            row.len() + past_end
+        } else {
+            // a goal inside a tab lands on the tab
+            let x = row.display_col_to_char(goal, tab_stop);
+            if row.char_to_display_col(x, tab_stop) > goal {
+                x - 1
+            } else {
+                x
+            }
+        };
+        self.cursor_position.x = x;
+    }
+
+    // the scroll_off margin, less if the screen's too short for it
+    fn scroll_margin(&self) -> usize {
+        self.config.scroll_off.min(self.text_height().saturating_sub(1) / 2)
+    }
+
+    fn scroll(&mut self) {
+        let x = self.cursor_display_x();
+        let y = self.cursor_position.y;
+        let width = self.text_width();
+        let height = self.text_height();
+        let margin = self.scroll_margin();
+        let len = self.document.len();
+        let offset = &mut self.offset;
+        debug!(
+            "Cursor:  ({}, {}) - Offset: ({}, {})",
+            x, y, offset.x, offset.y
+        );
+        offset.y = scrolled_top(offset.y, y, height, margin, len);
+        if x < offset.x {
+            offset.x = x;
+        } else if x >= offset.x.saturating_add(width) {
+            offset.x = x.saturating_sub(width).saturating_add(1);
+        }
+    }
+
+    // drawn straight into the message bar, since the editor can't refresh
+    // until the document has finished loading
+    fn draw_loading(&self, percent: u64) {
+        self.draw_progress(&format!("Loading… {}%", percent));
+    }
+
+    fn draw_progress(&self, text: &str) {
+        let terminal = &self.terminal;
+        let drawn = terminal
+            .cursor_position(&Position {
+                x: 0,
+                y: (terminal.size().height as usize).saturating_sub(1),
+            })
+            .and_then(|_| terminal.clear_current_line())
+            .and_then(|_| terminal.write(text))
+            .and_then(|_| terminal.flush());
+        if let Err(error) = drawn {
+            info!("couldn't draw progress: {}", error);
+        }
+    }
+
+    fn message_visible(&self) -> bool {
+        self.status_message.time.elapsed() < MESSAGE_TIME
+    }
+
+    // this is essentially an init function
+    // for the struct
+    // with default values (but none for now)
+    pub fn default() -> Self {
+        let args = Args::parse(env::args().skip(1));
+        // these are answered before the terminal gets taken over
+        if args.help {
+            print!("{}", USAGE);
+            process::exit(0);
+        }
+        if args.version {
+            println!("milli {}", VERSION);
+            process::exit(0);
+        }
+        if args.highlight {
+            match args.file_name.as_deref().map(print_highlighting) {
+                Some(Ok(())) => process::exit(0),
+                Some(Err(error)) => eprintln!("milli: {}", error),
+                None => eprintln!("milli: --highlight needs a file"),
+            }
+            process::exit(1);
+        }
+        // read before the terminal is set up, since that's where keys come from otherwise
+        let stdin_files = if args.stdin_files { read_file_list() } else { Vec::new() };
+        let global_config = Config::load();
+
+        let initial_status = String::from("HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = find | Ctrl-O = open | Ctrl-R = repeat last edit | Insert = toggle overwrite");
+
+        let mut editor = Self {
+            should_quit: false,
+            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            document: Document::default(),
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            status_message: StatusMessage::from(initial_status),
+            last_edit: None,
+            edit_in_progress: false,
+            overwrite: false,
+            config: global_config.clone(),
+            global_config,
+            search_matches: Vec::new(),
+            search_match_len: 0,
+            word_matches: Vec::new(),
+            word_match_len: 0,
+            word_matched_for: None,
+            git_branch: None,
+            committed: None,
+            diffed_revision: None,
+            touched_for: None,
+            large_file: false,
+            browsing: None,
+            lock: None,
+            read_only: false,
+            quit_warned: false,
+            flash_until: None,
+            goal_column: None,
+            backed_up: false,
+            backup_path: None,
+            overlay: Overlay::default(),
+            mode: Mode::Edit,
+            quickfix: Vec::new(),
+            quickfix_index: None,
+            cursors: Vec::new(),
+            selection: None,
+            pending_key: None,
+            waiting_for: None,
+            running: None,
+            kill_register: String::new(),
+            kill_chain: None,
+            peeked: None,
+            recording: None,
+            last_macro: Vec::new(),
+            replaying: VecDeque::new(),
+            playing_macro: false,
+            recorded_before_key: 0,
+            keymap: Keymap::default(),
+            signs: Signs::default(),
+        };
+        if editor.config.mouse_wheel {
+            if let Err(error) = editor.terminal.set_mouse_reporting(true) {
+                info!("couldn't turn on mouse reporting: {}", error);
+            }
+        }
+        editor.set_document(Document::default());
+        if let Some(file_name) = &args.file_name {
+            editor.open(&paths::expand(file_name));
+        }
+        if args.stdin_files {
+            editor.open_file_list(stdin_files);
+        }
+        if let Some(error) = args.error {
+            editor.status_message = StatusMessage::from(error);
+        } else if let Some(offset) = args.offset {
+            editor.set_cursor(editor.document.position_of_offset(offset));
+        }
+        editor
+    }
+}
+
+const USAGE: &str = "\
+usage: milli [--offset N] [--stdin-files] [file | directory]
+
+  --offset N     start with the cursor N bytes into the file
+  --stdin-files  open the files listed on stdin, one per line,
+                 moving between them with Alt-N and Alt-P
+  --highlight    print how each line of the file is highlighted and exit
+  -v, --version  print the version and exit
+  -h, --help     print this and exit
+
+keys:
+  Ctrl-S  save                      Ctrl-Q  quit
+  Ctrl-O  open a file               Alt-O   open a recent file
+  Ctrl-F  find (Alt-C: match case)  Ctrl-L  peek at the row
+  Ctrl-Z  undo                      Ctrl-Y  redo
+  Ctrl-R  repeat the last edit      Ctrl-V  insert a key literally
+  Ctrl-N  cycle line numbers        Insert  toggle overwrite
+  Alt-V   select text               Alt-B   select a block
+  Alt-D   a cursor on every match   Alt-Q   rewrap the paragraph
+  Alt-T   pick the syntax           Alt-L   pick the line ending
+  Alt-E   load a quickfix list      Alt-N/P next/previous quickfix entry
+  Alt-M   record a macro            Alt-R   play it back
+  Alt-K   play a saved macro        Ctrl-X  emacs-style bindings
+  Alt-G   go to a percentage        Alt-S   surround the selection
+  Alt-{/} previous/next row indented the same
+  Alt-U   up to the row it's indented under
+  Ctrl-A  add one to a number       Alt-X   take one from it
+  Ctrl-T  swap two characters       Ctrl-K  cut to the end of the row
+  Alt-Y   paste what Ctrl-K cut     Ctrl-E/W scroll the view down/up
+  Ctrl-X q quit at once, dropping unsaved changes
+  F1      run any command by name
+  Esc     cancel
+";
+
+// What a frame is drawn from: the document and config, the size to draw
+// it at, and the bits of the editor's state that show. The editor lends it
+// all for each refresh, see Editor::view, so drawing needs no terminal.
+struct View<'a> {
+    document: &'a Document,
+    config: &'a Config,
+    size: Size,
+    offset: Position,
+    cursor_position: Position,
+    cursors: &'a [Position],
+    selection: Option<&'a Selection>,
+    search_matches: &'a [Position],
+    search_match_len: usize,
+    // empty while they're stale
+    word_matches: &'a [Position],
+    word_match_len: usize,
+    signs: &'a Signs,
+    overlay: &'a Overlay,
+    mode: &'a Mode,
+    // None once it's timed out
+    message: Option<&'a str>,
+    // the command being run and for how long so far
+    running: Option<(&'a str, Duration)>,
+    recording: bool,
+    git_branch: Option<&'a str>,
+    browsing: Option<&'a Path>,
+    large_file: bool,
+    read_only: bool,
+    overwrite: bool,
+    should_quit: bool,
+}
+
+impl View<'_> {
+    // the whole frame is built up first and written in one go, every line
+    // clears whatever was left after it so the screen never goes blank
+    fn frame(&self) -> String {
+        let mut frame = format!("{}{}", cursor::Hide, cursor::Goto(1, 1));
+        if self.should_quit {
+            frame.push_str(&format!("{}{}Goodbye.\r\n", clear::All, CursorShape::Default));
+        } else {
+            self.draw_rows(&mut frame);
+            let mut footer_row = self.text_height();
+            if self.config.status_bar {
+                self.draw_status_bar(&mut frame, footer_row);
+                footer_row += 1;
+            }
+            if self.config.message_bar {
+                self.draw_message_bar(&mut frame, footer_row);
+            }
+            // after drawing rows, reset cursor
+            let x = self
+                .cursor_display_x()
+                .saturating_sub(self.offset.x)
+                .saturating_add(self.gutter_width())
+                .saturating_add(self.signs.width());
+            let y = self.cursor_position.y.saturating_sub(self.offset.y);
+            let shape = if self.overwrite {
+                self.config.overwrite_cursor
+            } else {
+                self.config.insert_cursor
+            };
+            frame.push_str(&format!(
+                "{}{}",
+                cursor::Goto(x.saturating_add(1) as u16, y.saturating_add(1) as u16),
+                shape
+            ));
+        }
+        frame.push_str(cursor::Show.as_ref());
+        frame
+    }
+
+    // The screen as it would be drawn, for snapshots and comparing against
+    // files. With ansi it's the very bytes refresh_screen writes, without it
+    // the text each terminal row ends up showing, trailing spaces trimmed.
+    fn render_to_string(&self, ansi: bool) -> String {
+        let frame = self.frame();
+        if ansi {
+            frame
+        } else {
+            screen_text(&frame, self.size)
+        }
+    }
+
+    // top row, bottom row, then the left and right screen columns of a block selection
+    fn block_bounds(&self, anchor: &Position) -> (usize, usize, usize, usize) {
+        let col = |at: &Position| {
+            let row = self.document.row(at.y);
+            row.map_or(0, |row| row.char_to_display_col(at.x, self.config.tab_stop))
+        };
+        let (anchor_col, cursor_col) = (col(anchor), col(&self.cursor_position));
+        (
+            anchor.y.min(self.cursor_position.y),
+            anchor.y.max(self.cursor_position.y),
+            anchor_col.min(cursor_col),
+            anchor_col.max(cursor_col),
+        )
+    }
+
+    // the screen columns of row y that are selected
+    fn selected_cols(&self, row: &Row, y: usize) -> Option<(usize, usize)> {
+        let selection = self.selection?;
+        let tab_stop = self.config.tab_stop;
+        match selection.kind {
+            SelectionKind::Linear => {
+                let (start, end) = ordered(&selection.anchor, &self.cursor_position);
+                if y < start.y || y > end.y {
+                    return None;
+                }
+                let from = if y == start.y { row.char_to_display_col(start.x, tab_stop) } else { 0 };
+                let to = if y == end.y {
+                    row.char_to_display_col(end.x, tab_stop)
+                } else {
+                    row.display_len(tab_stop)
+                };
+                Some((from, to))
+            }
+            SelectionKind::Block => {
+                let (top, bottom, left, right) = self.block_bounds(&selection.anchor);
+                (top..=bottom).contains(&y).then_some((left, right))
+            }
+        }
+    }
+
+    // columns taken up by the line numbers, including the space after them
+    fn gutter_width(&self) -> usize {
+        if self.config.line_numbers == LineNumberMode::Off {
+            return 0;
+        }
+        self.document.len().max(1).to_string().len() + 1
+    }
+
+    // columns left over for the document itself
+    // the status and message bars can each be turned off to give the text more room
+    fn footer_height(&self) -> usize {
+        self.config.status_bar as usize + self.config.message_bar as usize
+    }
+
+    fn text_height(&self) -> usize {
+        (self.size.height as usize).saturating_sub(self.footer_height())
+    }
+
+    fn text_width(&self) -> usize {
+        (self.size.width as usize)
+            .saturating_sub(self.gutter_width())
+            .saturating_sub(self.signs.width())
+            .saturating_sub(self.minimap_width())
+    }
+
+    fn minimap_shown(&self) -> bool {
+        self.config.minimap && !self.large_file
+    }
+
+    fn minimap_width(&self) -> usize {
+        if self.minimap_shown() {
+            MINIMAP_WIDTH
+        } else {
+            0
+        }
+    }
+
+    // each screen row of the minimap stands for an equal slice of the document, shaded
+    // by how long its rows are, and the slices on screen right now get a background
+    fn draw_minimap(&self, frame: &mut String, terminal_row: usize, height: usize) {
+        let len = self.document.len().max(1);
+        let per_row = len.div_ceil(height.max(1));
+        let first = terminal_row * per_row;
+        let last = (first + per_row).min(len);
+        // only a few rows of each slice are looked at, so long files stay cheap
+        let step = (per_row / MINIMAP_SAMPLES).max(1);
+        let lengths: Vec<usize> = (first..last)
+            .step_by(step)
+            .filter_map(|y| self.document.row(y))
+            .map(|row| row.display_len(self.config.tab_stop).min(MINIMAP_FULL_ROW))
+            .collect();
+        let average = lengths.iter().sum::<usize>() / lengths.len().max(1);
+        let shades = [' ', '░', '▒', '▓', '█'];
+        let shade = shades[(average * (shades.len() - 1)).div_ceil(MINIMAP_FULL_ROW)];
+        let in_view = first < last && first < self.offset.y + height && last > self.offset.y;
+        let column = self.size.width as usize - MINIMAP_WIDTH + 1;
+        frame.push_str(&cursor::Goto(column as u16, terminal_row.saturating_add(1) as u16).to_string());
+        frame.push_str(&color::Fg(MINIMAP_FG_COLOR).to_string());
+        if in_view {
+            frame.push_str(&format!("{}{}{}", color::Bg(MINIMAP_VIEW_BG_COLOR), shade, color::Bg(color::Reset)));
+        } else {
+            frame.push(shade);
+        }
+        frame.push_str(&color::Fg(color::Reset).to_string());
+    }
+
+    // the signs column is only there while something has put a sign in it
+    fn draw_sign(&self, frame: &mut String, y: usize) {
+        let width = self.signs.width();
+        if width == 0 {
+            return;
+        }
+        match self.signs.at(y) {
+            Some(sign) => {
+                let padding = width - sign.glyph.graphemes(true).count();
+                frame.push_str(&format!(
+                    "{}{}{}{}",
+                    color::Fg(sign.color),
+                    sign.glyph,
+                    color::Fg(color::Reset),
+                    " ".repeat(padding)
+                ));
+            }
+            None => frame.push_str(&" ".repeat(width)),
+        }
+    }
+
+    fn draw_gutter(&self, y: usize, long: bool) -> String {
+        let width = self.gutter_width();
+        if width == 0 {
+            return String::new();
+        }
+        let fg = if long && self.config.long_line_style == LongLineStyle::Number {
+            self.config.long_line_color.0
+        } else {
+            LINE_NUMBER_FG_COLOR
+        };
+        let cursor_y = self.cursor_position.y;
+        let number = match self.config.line_numbers {
+            LineNumberMode::Relative => y.abs_diff(cursor_y),
+            LineNumberMode::Hybrid if y != cursor_y => y.abs_diff(cursor_y),
+            _ => y.saturating_add(1),
+        };
+        format!(
+            "{}{:>width$} {}",
+            color::Fg(fg),
+            number,
+            color::Fg(color::Reset),
+            width = width - 1
+        )
+    }
+
+    // where the cursor is on screen, before scrolling, since a tab takes up several columns
+    // offset.x is counted in these columns too
+    fn cursor_display_x(&self) -> usize {
+        let Position { x, y } = self.cursor_position;
+        self.document
+            .row(y)
+            .map_or(x, |row| row.char_to_display_col(x, self.config.tab_stop))
+    }
+
+    // only on a buffer that's nothing yet, not even a scratch one
+    fn shows_welcome(&self) -> bool {
+        self.config.show_welcome
+            && self.document.file_name.is_none()
+            && !self.document.is_scratch()
+            && self.document.is_empty()
+    }
+
+    fn render_welcome(&self, frame: &mut String) {
+        let mut welcome_msg = format!("Milli Editor -- version {}", VERSION);
+        let width = self.size.width as usize;
+        let len = welcome_msg.len();
+        let padding = width.saturating_sub(len) / 2;
+        let marker = &self.config.empty_row_marker;
+        let spaces = " ".repeat(padding.saturating_sub(marker.chars().count()));
+        welcome_msg.truncate(width);
+        frame.push_str(&format!("{}{}{}", marker, spaces, welcome_msg));
+    }
+
+    fn draw_row(&self, frame: &mut String, row: &Row, y: usize) {
+        let mut start = self.offset.x;
+        let mut end = self.text_width() + self.offset.x;
+        let tab_stop = self.config.tab_stop;
+        let row_end = row.display_len(tab_stop);
+        let limit = self.config.max_line_length;
+        let long = self.config.long_lines && row_end > limit;
+        frame.push_str(&self.draw_gutter(y, long));
+        self.draw_sign(frame, y);
+        // text hidden off either side gets a marker in the column at that edge
+        let precedes = &self.config.precedes_marker;
+        let extends = &self.config.extends_marker;
+        let show_precedes = start > 0 && row_end > 0 && !precedes.is_empty();
+        let show_extends = row_end > end && !extends.is_empty();
+        if show_precedes {
+            frame.push_str(&draw_marker(precedes));
+            start += precedes.graphemes(true).count();
+        }
+        if show_extends {
+            end = end.saturating_sub(extends.graphemes(true).count()).max(start);
+        }
+        // everything from here on is in screen columns rather than graphemes
+        let mut backgrounds: Vec<(usize, usize, color::Rgb)> = self
+            .search_matches
+            .iter()
+            .filter(|m| m.y == y)
+            .map(|m| {
+                let from = row.char_to_display_col(m.x, tab_stop);
+                let to = row.char_to_display_col(m.x.saturating_add(self.search_match_len), tab_stop);
+                (from, to, SEARCH_MATCH_BG_COLOR)
+            })
+            .collect();
+        let len = self.word_match_len;
+        backgrounds.extend(self.word_matches.iter().filter(|m| m.y == y).map(|m| {
+            let from = row.char_to_display_col(m.x, tab_stop);
+            let to = row.char_to_display_col(m.x + len, tab_stop);
+            (from, to, self.config.word_highlight_color.0)
+        }));
+        let tags = &self.config.comment_tags;
+        for (from, to) in row.comment_words(&self.config.word_chars) {
+            if let Some(bg) = tags.color_of(&row.substring(from, to), self.config.comment_tag_color) {
+                backgrounds.push((row.char_to_display_col(from, tab_stop), row.char_to_display_col(to, tab_stop), bg.0));
+            }
+        }
+        if long && (self.config.long_line_style == LongLineStyle::Overflow || self.gutter_width() == 0) {
+            backgrounds.push((limit, row_end, self.config.long_line_color.0));
+        }
+        // the extra cursors are drawn as blocks, the terminal only has the one
+        let mut cursor_at_end = false;
+        for c in self.cursors.iter().filter(|c| c.y == y) {
+            let col = row.char_to_display_col(c.x, tab_stop);
+            if col >= row_end {
+                cursor_at_end = true;
+            } else {
+                backgrounds.push((col, row.char_to_display_col(c.x + 1, tab_stop), CURSOR_BG_COLOR));
+            }
+        }
+        if let Some((from, to)) = self.selected_cols(row, y) {
+            backgrounds.push((from, to, SELECTION_BG_COLOR));
+        }
+        // columns shaded all the way down, behind whatever text hasn't got a
+        // background already: the ruler just past text_width, the cursor's, and
+        // a block under the cursor on an empty row, first so it wins
+        let mut columns = Vec::new();
+        if self.config.empty_row_cursor && y == self.cursor_position.y && row.is_empty() {
+            columns.push((self.cursor_display_x(), self.config.empty_row_cursor_color.0));
+        }
+        if self.config.ruler {
+            columns.push((self.config.text_width, RULER_BG_COLOR));
+        }
+        if self.config.cursor_column {
+            columns.push((self.cursor_display_x(), self.config.cursor_column_color.0));
+        }
+        columns.retain(|(column, _)| (start..end).contains(column));
+        columns.sort_by_key(|(column, _)| *column);
+        columns.dedup_by_key(|(column, _)| *column);
+        for &(column, bg) in &columns {
+            if !backgrounds.iter().any(|(from, to, _)| (*from..*to).contains(&column)) {
+                backgrounds.push((column, column + 1, bg));
+            }
+        }
+        backgrounds.sort_by_key(|(from, _, _)| *from);
+        // render the row in pieces so each of those can get its background
+        let mut x = start;
+        for (from, to, bg) in backgrounds {
+            let from = from.max(x);
+            let to = to.min(end).min(row_end);
+            if from >= to {
+                continue;
+            }
+            frame.push_str(&row.render(x, from, tab_stop));
+            frame.push_str(&format!(
+                "{}{}{}",
+                color::Bg(bg),
+                row.render(from, to, tab_stop),
+                color::Bg(color::Reset)
+            ));
+            x = to;
+        }
+        frame.push_str(&row.render(x, end, tab_stop));
+        if show_extends {
+            frame.push_str(&draw_marker(extends));
+        }
+        let mut drawn_to = row_end.max(start);
+        if cursor_at_end && (start..end).contains(&row_end) {
+            frame.push_str(&format!("{} {}", color::Bg(CURSOR_BG_COLOR), color::Bg(color::Reset)));
+            drawn_to += 1;
+        }
+        // rows that stop short of a column are padded out to it
+        for (column, bg) in columns {
+            if drawn_to <= column {
+                let padding = column - drawn_to;
+                frame.push_str(&format!("{}{} {}", " ".repeat(padding), color::Bg(bg), color::Bg(color::Reset)));
+                drawn_to = column + 1;
+            }
+        }
+    }
+
+    fn draw_overlay_line(&self, frame: &mut String, index: usize) {
+        let width = self.size.width as usize;
+        // the pickers list file names, which could have anything in them
+        let line = truncated(&bar_text(&self.overlay.lines[index], self.config.escape_bar_control_chars), width);
+        let line = format!("{:<width$}", line, width = width);
+        if self.overlay.selected == Some(index) {
+            frame.push_str(&format!("{}{}{}", style::Invert, line, style::Reset));
+        } else {
+            frame.push_str(&line);
+        }
+    }
+
+    // without a message bar, messages and prompts take over the last text row
+    fn draw_rows(&self, frame: &mut String) {
+        let mut height = self.text_height();
+        if !self.config.message_bar && self.message.is_some() {
+            height = height.saturating_sub(1);
+            self.draw_message_bar(frame, height);
+        }
+        let overlay_start = height.saturating_sub(self.overlay.lines.len());
+        let welcome_row = self.shows_welcome().then_some(height / 3);
+        for terminal_row in 0..height {
+            frame.push_str(&goto_row(terminal_row));
+            if terminal_row >= overlay_start {
+                self.draw_overlay_line(frame, terminal_row - overlay_start);
+            } else {
+                let shown = shown_row(self.offset.y, terminal_row, self.document.len())
+                    .and_then(|y| self.document.row(y).map(|row| (y, row)));
+                if let Some((y, row)) = shown {
+                    self.draw_row(frame, row, y);
+                } else if welcome_row == Some(terminal_row) {
+                    self.render_welcome(frame);
+                } else {
+                    // rows past the end of the document don't get a line number
+                    if self.config.dim_empty_rows {
+                        frame.push_str(&format!(
+                            "{}{}{}",
+                            color::Fg(EMPTY_ROW_FG_COLOR),
+                            self.config.empty_row_marker,
+                            color::Fg(color::Reset)
+                        ));
+                    } else {
+                        frame.push_str(&self.config.empty_row_marker);
+                    }
+                }
+            }
+            frame.push_str(clear::UntilNewline.as_ref());
+            if self.minimap_shown() && terminal_row < overlay_start {
+                self.draw_minimap(frame, terminal_row, height);
+            }
+        }
+    }
+
+    // e.g. -- SEARCH --, or -- VISUAL -- recording, None when just editing
+    fn mode_indicator(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        match self.mode {
+            Mode::Edit => (),
+            Mode::Chord(keys) => parts.push(format!("-- {} --", keys_name(keys))),
+            Mode::Prompt => parts.push("-- PROMPT --".to_string()),
+            Mode::Search => parts.push("-- SEARCH --".to_string()),
+            Mode::Running => parts.push("-- RUNNING --".to_string()),
+        }
+        match self.selection.map(|selection| selection.kind) {
+            Some(SelectionKind::Linear) => parts.push("-- VISUAL --".to_string()),
+            Some(SelectionKind::Block) => parts.push("-- VISUAL BLOCK --".to_string()),
+            None => (),
+        }
+        if self.recording {
+            parts.push("recording".to_string());
+        }
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    fn draw_status_bar(&self, frame: &mut String, terminal_row: usize) {
+        let mut status;
+        let width = self.size.width as usize;
+        // a third of a narrow terminal is all the name gets, whatever the config says
+        let name_width = self.config.status_name_width.min(width / 3);
+        let mut file_name = "[No Name]".to_string();
+        if self.document.is_scratch() {
+            file_name = "[Scratch]".to_string();
+        } else if let Some(dir) = self.browsing {
+            let dir = bar_text(&dir.display().to_string(), self.config.escape_bar_control_chars);
+            file_name = format!("{}/", ellipsized_path(dir.trim_end_matches('/'), name_width.saturating_sub(1)));
+        } else if let Some(name) = &self.document.file_name {
+            file_name = ellipsized_path(&bar_text(name, self.config.escape_bar_control_chars), name_width);
+        };
+
+        let modified = if self.document.is_dirty() { "*" } else { "" };
+        status = format!("{}{} - {} lines", file_name, modified, self.document.len());
+        if self.large_file {
+            status.push_str(" [Large File]");
+        }
+        if self.read_only {
+            status.push_str(" [Read Only]");
+        }
+        if let Some(indicator) = self.mode_indicator() {
+            status.push_str(&format!(" {}", indicator));
+        }
+        if let Some(branch) = self.git_branch {
+            status.push_str(&format!(" - {}", bar_text(branch, self.config.escape_bar_control_chars)));
+        }
+        let indent = match self.document.indent {
+            IndentStyle::Tabs => format!("Tab Size: {}", self.config.tab_stop),
+            IndentStyle::Spaces(width) => format!("Spaces: {}", width),
+        };
+        let mode = if self.overwrite { "OVR" } else { "INS" };
+        // only what's shown changes, positions are from 0 everywhere else
+        let base = if self.config.zero_based_position { 0 } else { 1 };
+        let line_indicator = format!(
+            "{} | {} | {} | {} | Ln {}/{} Col {}",
+            self.document.syntax().name,
+            indent,
+            self.document.line_ending().name(),
+            mode,
+            self.cursor_position.y.saturating_add(base),
+            self.document.len(),
+            self.cursor_position.x.saturating_add(base)
+        );
+
+        let len = status.graphemes(true).count() + line_indicator.graphemes(true).count();
+        // at least a space between the two, even if it pushes the end off
+        status.push_str(&" ".repeat(width.saturating_sub(len).max(1)));
+
+        status = truncated(&format!("{}{}", status, line_indicator), width);
+        frame.push_str(&format!(
+            "{}{}{}{}{}{}{}",
+            goto_row(terminal_row),
+            color::Bg(STATUS_BG_COLOR),
+            color::Fg(STATUS_FG_COLOR),
+            status,
+            clear::UntilNewline,
+            color::Bg(color::Reset),
+            color::Fg(color::Reset)
+        ));
+    }
+
+    fn draw_message_bar(&self, frame: &mut String, terminal_row: usize) {
+        frame.push_str(&goto_row(terminal_row));
+        // truncating by bytes could land in the middle of a character
+        let width = self.size.width as usize;
+        if let Some((command, running_for)) = self.running {
+            let text = spinner_text(command, running_for);
+            frame.push_str(&truncated(&bar_text(&text, self.config.escape_bar_control_chars), width));
+        } else if let Some(message) = self.message {
+            let text = bar_text(message, self.config.escape_bar_control_chars);
+            frame.push_str(&truncated(&text, width));
+        }
+        frame.push_str(clear::UntilNewline.as_ref());
+    }
+}
+
+// milli [--offset N] [--stdin-files] [--highlight] [--version] [--help] [file | directory]
+#[derive(Default)]
+struct Args {
+    file_name: Option<String>,
+    // byte offset to put the cursor at, for tools that work in bytes
+    offset: Option<usize>,
+    // the files to open are piped in, e.g. from find
+    stdin_files: bool,
+    // shown in the message bar, since there's no terminal to print to yet
+    error: Option<String>,
+    // print the file's highlighting instead of editing it
+    highlight: bool,
+    version: bool,
+    help: bool,
+}
+
+impl Args {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Self {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            if arg == "--version" || arg == "-v" {
+                parsed.version = true;
+            } else if arg == "--help" || arg == "-h" {
+                parsed.help = true;
+            } else if arg == "--highlight" {
+                parsed.highlight = true;
+            } else if arg == "--stdin-files" {
+                parsed.stdin_files = true;
+            } else if arg == "--offset" {
+                match args.next().map(|value| value.parse()) {
+                    Some(Ok(offset)) => parsed.offset = Some(offset),
+                    _ => parsed.error = Some("--offset needs a number of bytes".to_string()),
+                }
+            } else if parsed.file_name.is_none() {
+                parsed.file_name = Some(arg);
+            }
+        }
+        parsed
+    }
+}
+
+// The top row to show so that row y is on a screen height rows tall, with
+// margin rows around it where there's room, given the top row before. The
+// margin runs out at the end of the file rather than showing past it.
+fn scrolled_top(top: usize, y: usize, height: usize, margin: usize, len: usize) -> usize {
+    if y < top.saturating_add(margin) {
+        y.saturating_sub(margin)
+    } else if y.saturating_add(margin) >= top.saturating_add(height) {
+        // the top row that puts y on the last row of the screen
+        let bottom = (y + 1).saturating_sub(height);
+        (bottom + margin).min(len.saturating_sub(height)).max(bottom)
+    } else {
+        top
+    }
+}
+
+// the document row on terminal_row with top at the top, None past the end,
+// where the empty row marker goes
+fn shown_row(top: usize, terminal_row: usize, len: usize) -> Option<usize> {
+    let y = top + terminal_row;
+    (y < len).then_some(y)
+}
+
+// For milli --highlight, each line of the file as its number and then the
+// runs of graphemes that aren't plain text, e.g. `2: PrimaryKeyword 0..3`.
+// test/ has files and what they should come out as.
+fn print_highlighting(file_name: &str) -> Result<(), std::io::Error> {
+    let text = fs::read_to_string(file_name)?;
+    print!("{}", highlighting_dump(file_name, &text));
+    Ok(())
+}
+
+// the state each line leaves open carries on into the next, as in a document
+fn highlighting_dump(file_name: &str, text: &str) -> String {
+    let syntax = SyntaxDefinition::detect(file_name, text.lines().next().unwrap_or(""));
+    let mut state = HighlightState::Normal;
+    let mut dump = String::new();
+    for (y, line) in text.lines().enumerate() {
+        let (highlighting, ends_in) = highlight_line(line, &syntax, state);
+        state = ends_in;
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for x in 1..=highlighting.len() {
+            if x == highlighting.len() || highlighting[x] != highlighting[start] {
+                if highlighting[start] != Highlighting::None {
+                    runs.push(format!("{:?} {}..{}", highlighting[start], start, x));
+                }
+                start = x;
+            }
+        }
+        dump.push_str(&format!("{}: {}\n", y + 1, runs.join(", ")));
+    }
+    dump
+}
+
+// the non-empty lines of stdin
+fn read_file_list() -> Vec<String> {
+    let mut list = String::new();
+    if let Err(error) = io::stdin().read_to_string(&mut list) {
+        info!("couldn't read the file list: {}", error);
+    }
+    list.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect()
+}
+
+// what the message bar says while a shell command runs, the spinner turned
+// on a frame every SPINNER_FRAME
+fn spinner_text(command: &str, running_for: Duration) -> String {
+    let frame = (running_for.as_millis() / SPINNER_FRAME.as_millis()) as usize;
+    format!("{} Running {} (Esc or Ctrl-C to stop)", SPINNER[frame % SPINNER.len()], command)
+}
+
+// the first width graphemes of text, String::truncate counts bytes and
+// panics part way through a character
+fn truncated(text: &str, width: usize) -> String {
+    text.graphemes(true).take(width).collect()
+}
+
+// Text that's safe to put in a bar, where a file name or a prompt's input could
+// have anything in it. Tabs and line breaks become spaces and the other control
+// characters ^X, or are left out without escape, so nothing can move the
+// cursor or start an escape sequence and every grapheme is a column.
+fn bar_text(text: &str, escape: bool) -> String {
+    let mut safe = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\t' | '\n' | '\r' => safe.push(' '),
+            _ if !c.is_control() => safe.push(c),
+            _ if !escape => (),
+            '\0'..='\x1f' | '\x7f' => {
+                safe.push('^');
+                safe.push(((c as u8) ^ 0x40) as char);
+            }
+            _ => safe.push_str(&format!("\\u{{{:x}}}", c as u32)),
+        }
+    }
+    safe
+}
+
+// path cut down to width by dropping whole directories from the front, as in
+// …/src/editor.rs, or just the last part of it when even that won't fit
+fn ellipsized_path(path: &str, width: usize) -> String {
+    if path.graphemes(true).count() <= width {
+        return path.to_string();
+    }
+    let parts: Vec<&str> = path.split('/').collect();
+    let base = parts.last().copied().unwrap_or(path);
+    let mut kept = base.graphemes(true).count();
+    let mut start = parts.len() - 1;
+    // the ellipsis and its slash take two columns
+    if kept + 2 > width {
+        return base.to_string();
+    }
+    while start > 1 && kept + parts[start - 1].graphemes(true).count() + 3 <= width {
+        start -= 1;
+        kept += parts[start].graphemes(true).count() + 1;
+    }
+    format!("…/{}", parts[start..].join("/"))
+}
+
+// text for value + delta written the way text was: hex stays hex in the same
+// case, and leading zeros keep the digits as wide as they were
+// None if the result won't fit
+fn renumbered(text: &str, value: i64, delta: i64) -> Option<String> {
+    if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        // hex wraps around like an unsigned number would
+        let value = (value as u64).wrapping_add(delta as u64);
+        let width = digits.len();
+        let hex = if digits.chars().any(|c| c.is_ascii_uppercase()) {
+            format!("{:0width$X}", value, width = width)
+        } else {
+            format!("{:0width$x}", value, width = width)
+        };
+        return Some(format!("{}{}", &text[..2], hex));
+    }
+    let value = value.checked_add(delta)?;
+    let digits = text.trim_start_matches('-');
+    let width = if digits.len() > 1 && digits.starts_with('0') { digits.len() } else { 1 };
+    let sign = if value < 0 { "-" } else { "" };
+    Some(format!("{}{:0width$}", sign, value.unsigned_abs(), width = width))
+}
+
+// how many columns line's indentation takes up
+fn indent_width(line: &str, tab_stop: usize) -> usize {
+    let tab_stop = tab_stop.max(1);
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += tab_stop - width % tab_stop,
+            _ => break,
+        }
+    }
+    width
+}
+
+// line with columns of its indentation taken off the front, where a tab that
+// only partly fits is made up for with spaces
+fn dedented(line: &str, columns: usize, tab_stop: usize) -> String {
+    let tab_stop = tab_stop.max(1);
+    let mut width = 0;
+    for (i, c) in line.char_indices() {
+        if width >= columns {
+            return format!("{}{}", " ".repeat(width - columns), &line[i..]);
+        }
+        match c {
+            ' ' => width += 1,
+            '\t' => width += tab_stop - width % tab_stop,
+            _ => return line[i..].to_string(),
+        }
+    }
+    " ".repeat(width.saturating_sub(columns))
+}
+
+// Plays a frame onto a blank screen of size and gives back the text on it,
+// a row per line. Only the escapes frames are made of do anything: moving the
+// cursor and clearing, everything else (colours, the cursor's shape) is dropped.
+fn screen_text(frame: &str, size: Size) -> String {
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut screen = vec![vec![" "; width]; height];
+    let (mut x, mut y) = (0, 0);
+    let mut rest = frame;
+    while let Some(grapheme) = rest.graphemes(true).next() {
+        rest = &rest[grapheme.len()..];
+        match grapheme {
+            "\x1b" if rest.starts_with('[') => {
+                // parameters up to the final byte, which says what it was
+                let end = rest[1..].find(|c: char| ('@'..='~').contains(&c)).map_or(rest.len(), |end| end + 1);
+                let params = &rest[1..end];
+                let command = rest[end..].chars().next();
+                rest = rest.get(end + 1..).unwrap_or("");
+                let mut numbers = params.split(';').map(|n| n.parse::<usize>().unwrap_or(0));
+                match command {
+                    Some('H') => {
+                        y = numbers.next().unwrap_or(1).saturating_sub(1);
+                        x = numbers.next().unwrap_or(1).saturating_sub(1);
+                    }
+                    Some('K') => {
+                        if let Some(row) = screen.get_mut(y) {
+                            row.iter_mut().skip(x).for_each(|cell| *cell = " ");
+                        }
+                    }
+                    Some('J') if params == "2" => screen.iter_mut().flatten().for_each(|cell| *cell = " "),
+                    _ => (),
+                }
+            }
+            // OSC, up to the bell
+            "\x1b" if rest.starts_with(']') => rest = rest.split_once('\x07').map_or("", |(_, after)| after),
+            "\x1b" => rest = &rest[rest.chars().next().map_or(0, char::len_utf8)..],
+            "\r" => x = 0,
+            "\n" | "\r\n" => {
+                x = 0;
+                y += 1;
+            }
+            _ => {
+                if let Some(cell) = screen.get_mut(y).and_then(|row| row.get_mut(x)) {
+                    *cell = grapheme;
+                }
+                x += 1;
+            }
+        }
+    }
+    let mut text = String::new();
+    for row in screen {
+        text.push_str(row.concat().trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+// in single quotes for sh, with any of its own closed off around a \'
+fn shell_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn goto_row(terminal_row: usize) -> String {
+    cursor::Goto(1, terminal_row.saturating_add(1) as u16).to_string()
+}
+
+fn draw_marker(marker: &str) -> String {
+    format!("{}{}{}", color::Fg(MARKER_FG_COLOR), marker, color::Fg(color::Reset))
+}
+
+// the two positions with the one that comes first in the document first
+fn ordered(a: &Position, b: &Position) -> (Position, Position) {
+    (a.min(b).clone(), a.max(b).clone())
+}
+
+// gives the owner write permission, like chmod u+w
+fn make_writable(path: &Path) -> Result<(), std::io::Error> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o200);
+    std::fs::set_permissions(path, permissions)
+}
+
+// dir/.. without piling up ..s where it can be helped
+fn parent_dir(dir: &Path) -> PathBuf {
+    match dir.parent() {
+        Some(parent) if dir.file_name().is_some() && !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        Some(_) if dir.file_name().is_some() => PathBuf::from("."),
+        _ => dir.join(".."),
+    }
+}
+
+fn git_branch_for(file_name: Option<&str>) -> Option<String> {
+    // an unnamed buffer belongs to wherever the editor was started
+    let dir = match file_name.map(Path::new).and_then(Path::parent) {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    git::head(&dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::tests::Rng;
+
+    // moves the cursor down a row at a time from the top, scrolling after
+    // each step like Editor::scroll does, returns the cursor row and top row
+    fn press_down(times: usize, height: usize, margin: usize, len: usize) -> (usize, usize) {
+        let (mut y, mut top) = (0, 0);
+        for _ in 0..times {
+            y = (y + 1).min(len - 1);
+            top = scrolled_top(top, y, height, margin, len);
+        }
+        (y, top)
+    }
+
+    // what each terminal row shows, None for the empty row marker
+    fn screen(top: usize, height: usize, len: usize) -> Vec<Option<usize>> {
+        (0..height).map(|terminal_row| shown_row(top, terminal_row, len)).collect()
+    }
+
+    #[test]
+    fn a_document_as_tall_as_the_screen_fits_without_markers() {
+        let height = 20;
+        for margin in [0, 3] {
+            let (y, top) = press_down(height, height, margin, height);
+            assert_eq!((y, top), (height - 1, 0));
+            let rows: Vec<Option<usize>> = (0..height).map(Some).collect();
+            assert_eq!(screen(top, height, height), rows);
+        }
+    }
+
+    #[test]
+    fn a_document_one_row_short_of_the_screen_has_one_marker() {
+        let height = 20;
+        let screen = screen(0, height, height - 1);
+        assert_eq!(screen[height - 2], Some(height - 2));
+        assert_eq!(screen[height - 1], None);
+    }
+
+    #[test]
+    fn scrolling_to_the_last_of_a_hundred_rows_shows_it_on_the_last_line() {
+        let (height, len) = (20, 100);
+        for margin in [0, 3] {
+            let (y, top) = press_down(len, height, margin, len);
+            assert_eq!((y, top), (len - 1, len - height));
+            let screen = screen(top, height, len);
+            assert_eq!(screen[height - 1], Some(len - 1));
+            assert!(screen.iter().all(Option::is_some));
+        }
+    }
+
+    // graphemes that are more than one char, or that a byte-at-a-time cut
+    // would split, along with control characters and lone joiners
+    const AWKWARD: [&str; 16] = [
+        "a", " ", "é", "e\u{301}", "\u{301}", "\u{200d}", "👩\u{200d}💻", "🇳🇿", "中", "ｗ",
+        "\t", "\r", "\0", "\x1b", "\x7f", "\u{85}",
+    ];
+
+    fn awkward_text(rng: &mut Rng) -> String {
+        (0..rng.below(12)).map(|_| *rng.pick(&AWKWARD)).collect()
+    }
+
+    fn assert_row_fits(row: &Row, rng: &mut Rng) {
+        let tab_stop = 1 + rng.below(8);
+        assert_eq!(row.as_str().graphemes(true).count(), row.len());
+        let display_len = row.display_len(tab_stop);
+        assert!(display_len >= row.len());
+        assert!(row.display_col_to_char(rng.below(display_len + 3), tab_stop) <= row.len());
+        let start = rng.below(display_len + 2);
+        let end = start + rng.below(display_len + 2);
+        let mut drawn = Row::from(&row.render(start, end, tab_stop)[..]);
+        drawn.strip_ansi();
+        assert!(drawn.len() <= end - start, "{:?} {:?}", row.as_str(), drawn.as_str());
+        assert!(!drawn.as_str().contains(|c: char| c.is_control() && c != '\x1b'), "{:?} {:?}", row.as_str(), drawn.as_str());
+    }
+
+    #[test]
+    fn awkward_text_is_edited_and_drawn_without_panicking() {
+        for seed in 1..=200 {
+            let mut rng = Rng::new(seed);
+            let lines: Vec<String> = (0..1 + rng.below(4)).map(|_| awkward_text(&mut rng)).collect();
+            let mut document = Document::from_lines(&lines);
+            let mut row = Row::from(&awkward_text(&mut rng)[..]);
+            for _ in 0..50 {
+                let y = rng.below(document.len() + 1);
+                let at = Position {
+                    x: rng.below(document.row_len(y) + 3),
+                    y,
+                };
+                match rng.below(4) {
+                    0 => {
+                        document.insert_str(&at, &awkward_text(&mut rng));
+                    }
+                    1 => document.delete(&at),
+                    2 => document.join(at.y),
+                    _ => {
+                        let end = Position {
+                            x: rng.below(8),
+                            y: rng.below(document.len() + 1),
+                        };
+                        document.delete_range(&at, &end);
+                    }
+                }
+                let x = rng.below(row.len() + 3);
+                match rng.below(5) {
+                    0 => row.insert(x, rng.pick(&AWKWARD).chars().next().unwrap_or('a')),
+                    1 => row.delete(x),
+                    2 => row.transpose(x),
+                    3 => row.append(&Row::from(&awkward_text(&mut rng)[..])),
+                    _ => drop(row.split(x)),
+                }
+                assert_row_fits(&row, &mut rng);
+                for y in 0..document.len() {
+                    assert_row_fits(document.row(y).unwrap(), &mut rng);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn awkward_text_in_the_bars_stays_inside_its_width() {
+        let mut rng = Rng::new(7);
+        for _ in 0..2000 {
+            let text = awkward_text(&mut rng) + &awkward_text(&mut rng);
+            let width = rng.below(16);
+            assert!(truncated(&text, width).graphemes(true).count() <= width);
+            for escape in [false, true] {
+                let safe = bar_text(&text, escape);
+                assert!(!safe.contains(char::is_control), "{:?}", safe);
+                assert!(truncated(&safe, width).graphemes(true).count() <= width);
+            }
+            // only escaping makes it any longer
+            assert!(bar_text(&text, false).chars().count() <= text.chars().count());
+        }
+    }
+
+    #[test]
+    fn a_tab_or_newline_in_a_file_name_is_a_space_in_the_bar() {
+        let name = "notes\tfrom\nmonday.txt";
+        assert_eq!(bar_text(name, false), "notes from monday.txt");
+        assert_eq!(bar_text(name, true), "notes from monday.txt");
+        assert_eq!(truncated(&bar_text(name, true), 10), "notes from");
+    }
+
+    #[test]
+    fn other_control_characters_are_escaped_or_dropped() {
+        let name = "a\x1b[2Jb\x07\u{9b}c";
+        assert_eq!(bar_text(name, true), "a^[[2Jb^G\\u{9b}c");
+        assert_eq!(bar_text(name, false), "a[2Jbc");
+    }
+
+    // a frame drawn from nothing but a document, a config and a size, with
+    // the cursor at the top and nothing else going on
+    fn snapshot(document: &Document, config: &Config, size: Size) -> String {
+        let (signs, overlay) = (Signs::default(), Overlay::default());
+        let view = View {
+            document,
+            config,
+            size,
+            offset: Position::default(),
+            cursor_position: Position::default(),
+            cursors: &[],
+            selection: None,
+            search_matches: &[],
+            search_match_len: 0,
+            word_matches: &[],
+            word_match_len: 0,
+            signs: &signs,
+            overlay: &overlay,
+            mode: &Mode::Edit,
+            message: Some("HELP: Ctrl-Q = quit"),
+            running: None,
+            recording: false,
+            git_branch: None,
+            browsing: None,
+            large_file: false,
+            read_only: false,
+            overwrite: false,
+            should_quit: false,
+        };
+        view.render_to_string(false)
+    }
+
+    #[test]
+    fn a_small_document_is_drawn_as_recorded() {
+        let lines: Vec<String> = ["fn main() {", "    println!(\"a row too long for it\");"].iter().map(|line| line.to_string()).collect();
+        let document = Document::from_lines(&lines);
+        let size = Size { width: 20, height: 5 };
+        let expected = fs::read_to_string(format!("{}/test/snapshot.txt", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        assert_eq!(snapshot(&document, &Config::default(), size), expected);
+    }
+
+    #[test]
+    fn a_frame_is_played_onto_the_screen_it_was_drawn_for() {
+        let size = Size { width: 6, height: 3 };
+        let frame = format!(
+            "old\r\ntext{}{}abc{}toolong{}{}\x1b[K",
+            clear::All,
+            cursor::Goto(2, 1),
+            cursor::Goto(1, 3),
+            color::Fg(color::Red),
+            cursor::Goto(4, 3)
+        );
+        assert_eq!(screen_text(&frame, size), " abc\n\ntoo\n");
+    }
+
+    #[test]
+    fn the_highlighting_fixtures_come_out_as_recorded() {
+        for name in ["literals.rs", "patch.diff"] {
+            let path = format!("{}/test/{}", env!("CARGO_MANIFEST_DIR"), name);
+            let text = fs::read_to_string(&path).unwrap();
+            let expected = fs::read_to_string(format!("{}.highlighting", path)).unwrap();
+            assert_eq!(highlighting_dump(&path, &text), expected, "{}", name);
+        }
+    }
 }
@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// just enough git for the status bar and the diff signs, read straight from .git where we can
+// any problem along the way means we're not in a repo, so None
+
+// walks up from path looking for the .git directory
+// worktrees and submodules have a .git file pointing at the real one instead
+fn find_git_dir(path: &Path) -> Option<PathBuf> {
+    let start = fs::canonicalize(path).ok()?;
+    for dir in start.ancestors() {
+        let candidate = dir.join(".git");
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+        if candidate.is_file() {
+            let contents = fs::read_to_string(&candidate).ok()?;
+            let git_dir = contents.trim().strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(git_dir));
+        }
+    }
+    None
+}
+
+// the current branch name, or the short commit hash if HEAD is detached
+pub fn head(path: &Path) -> Option<String> {
+    let git_dir = find_git_dir(path)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+    if let Some(reference) = head.strip_prefix("ref:") {
+        let reference = reference.trim();
+        let branch = reference.strip_prefix("refs/heads/").unwrap_or(reference);
+        Some(branch.to_string())
+    } else if head.len() >= 7 && head.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(head[..7].to_string())
+    } else {
+        None
+    }
+}
+
+// the file as it was committed at HEAD. Reading that out of .git would mean
+// inflating objects, so this one asks git itself
+pub fn committed(file: &Path) -> Option<String> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    find_git_dir(dir)?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("HEAD:./{}", file.file_name()?.to_str()?))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
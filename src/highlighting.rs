@@ -0,0 +1,78 @@
+use termion::{color, style};
+
+// what a row starts in because of the rows above it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HighlightState {
+    #[default]
+    Normal,
+    // a string that was left open at the end of the previous row
+    String,
+    // inside a /* */ style comment
+    BlockComment,
+}
+
+// what a grapheme in a row is, as far as colouring goes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Highlighting {
+    None,
+    Number,
+    String,
+    Character,
+    Comment,
+    PrimaryKeyword,
+    SecondaryKeyword,
+    // drawn in caret notation rather than as itself, see Row::render
+    ControlChar,
+    // whole rows of a diff
+    Added,
+    Removed,
+    Hunk,
+    FileHeader,
+}
+
+impl Highlighting {
+    // None means the terminal's own foreground colour
+    pub fn to_color(self) -> Option<color::Rgb> {
+        match self {
+            Highlighting::None => None,
+            Highlighting::Number => Some(color::Rgb(220, 163, 163)),
+            Highlighting::String => Some(color::Rgb(211, 54, 130)),
+            Highlighting::Character => Some(color::Rgb(108, 113, 196)),
+            Highlighting::Comment => Some(color::Rgb(133, 153, 0)),
+            Highlighting::PrimaryKeyword => Some(color::Rgb(181, 137, 0)),
+            Highlighting::SecondaryKeyword => Some(color::Rgb(42, 161, 152)),
+            Highlighting::ControlChar => Some(color::Rgb(203, 75, 22)),
+            Highlighting::Added => Some(color::Rgb(133, 153, 0)),
+            Highlighting::Removed => Some(color::Rgb(220, 50, 47)),
+            Highlighting::Hunk => Some(color::Rgb(42, 161, 152)),
+            Highlighting::FileHeader => None,
+        }
+    }
+
+    pub fn is_bold(self) -> bool {
+        self == Highlighting::FileHeader
+    }
+
+    // the escape code to switch to this colour
+    pub fn fg(self) -> String {
+        let fg = match self.to_color() {
+            Some(rgb) => format!("{}", color::Fg(rgb)),
+            None => format!("{}", color::Fg(color::Reset)),
+        };
+        if self.is_bold() {
+            format!("{}{}", style::Bold, fg)
+        } else {
+            fg
+        }
+    }
+
+    // fg, plus turning bold off again when previous had it on
+    pub fn switch_from(self, previous: Highlighting) -> String {
+        if previous.is_bold() && !self.is_bold() {
+            // normal intensity, which unlike NoBold's 21 no terminal reads as double underline
+            format!("\x1b[22m{}", self.fg())
+        } else {
+            self.fg()
+        }
+    }
+}
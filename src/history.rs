@@ -0,0 +1,381 @@
+use crate::Position;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use unicode_segmentation::UnicodeSegmentation;
+
+// One change to the text: what was taken out at start and what was put in
+// its place. Unlike an Edit, this is enough to go back and forth with.
+#[derive(Clone)]
+pub struct Change {
+    pub start: Position,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Change {
+    // where text ends up ending when it sits at start
+    pub fn end_of(start: &Position, text: &str) -> Position {
+        match text.rsplit_once('\n') {
+            Some((before, last)) => Position {
+                x: last.graphemes(true).count(),
+                y: start.y + before.matches('\n').count() + 1,
+            },
+            None => Position {
+                x: start.x + text.graphemes(true).count(),
+                y: start.y,
+            },
+        }
+    }
+
+    // appends another change if it carries straight on from this one,
+    // so a run of typing or deleting doesn't take a change per keypress
+    fn absorb(&mut self, other: &Change) -> bool {
+        if self.removed.is_empty() && other.removed.is_empty() {
+            if Self::end_of(&self.start, &self.inserted) == other.start {
+                self.inserted.push_str(&other.inserted);
+                return true;
+            }
+        } else if self.inserted.is_empty() && other.inserted.is_empty() {
+            // Delete keeps removing at the same spot
+            if self.start == other.start {
+                self.removed.push_str(&other.removed);
+                return true;
+            }
+            // Backspace removes what comes just before it
+            if Self::end_of(&other.start, &other.removed) == self.start {
+                self.removed.insert_str(0, &other.removed);
+                self.start = other.start.clone();
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// changes that are undone and redone together
+pub type Group = Vec<Change>;
+
+// The undo tree. Each node is a group of changes that get undone together,
+// e.g. one burst of typing, made on top of its parent's text; the root is the
+// text from before anything was recorded. Editing after an undo starts a new
+// branch instead of throwing away what was undone, vim style. Nodes are keyed
+// by when they were made, so the map's order is the order things happened in,
+// and the limit counts nodes.
+pub struct History {
+    nodes: BTreeMap<usize, Node>,
+    root: usize,
+    // the node whose text the document has now
+    current: usize,
+    // whether changes still go into current, until the next start_group
+    open: bool,
+    next_id: usize,
+    // how many groups are kept before the oldest are dropped
+    limit: usize,
+}
+
+struct Node {
+    parent: Option<usize>,
+    children: Vec<usize>,
+    // the child redo goes back down into, the one last come up out of
+    redo: Option<usize>,
+    changes: Group,
+    made: Instant,
+}
+
+impl Node {
+    fn new(parent: Option<usize>, changes: Group) -> Self {
+        Self {
+            parent,
+            children: Vec::new(),
+            redo: None,
+            changes,
+            made: Instant::now(),
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(0, Node::new(None, Vec::new()));
+        Self {
+            nodes,
+            root: 0,
+            current: 0,
+            open: false,
+            next_id: 1,
+            limit: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    // changes recorded from now on go into a group of their own
+    pub fn start_group(&mut self) {
+        self.open = false;
+    }
+
+    pub fn record(&mut self, change: Change) {
+        if self.open {
+            let group = &mut self.node_mut(self.current).changes;
+            let absorbed = group.last_mut().is_some_and(|last| last.absorb(&change));
+            if !absorbed {
+                group.push(change);
+            }
+            return;
+        }
+        // anything undone from here stays where it was, as a branch beside this one
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(id, Node::new(Some(self.current), vec![change]));
+        let parent = self.node_mut(self.current);
+        parent.children.push(id);
+        parent.redo = Some(id);
+        self.current = id;
+        self.open = true;
+        self.trim();
+    }
+
+    // steps up to the parent, returns the changes to take back out
+    pub fn undo(&mut self) -> Option<Group> {
+        let id = self.current;
+        let parent = self.nodes[&id].parent?;
+        self.node_mut(parent).redo = Some(id);
+        self.current = parent;
+        self.open = false;
+        Some(self.nodes[&id].changes.clone())
+    }
+
+    // steps back down the branch last undone out of, returns the changes to make again
+    pub fn redo(&mut self) -> Option<Group> {
+        let id = self.nodes[&self.current].redo?;
+        self.current = id;
+        self.open = false;
+        Some(self.nodes[&id].changes.clone())
+    }
+
+    // the node made just before (or after) the current one, whichever branch
+    // it's on, like vim's g- and g+
+    pub fn neighbour(&self, forward: bool) -> Option<usize> {
+        if forward {
+            self.nodes.range(self.current + 1..).next().map(|(id, _)| *id)
+        } else {
+            self.nodes.range(..self.current).next_back().map(|(id, _)| *id)
+        }
+    }
+
+    // the way from the current node to id: up to where their branches meet,
+    // then down. Returns the groups to undo and then the groups to redo, in
+    // that order, or None if there's no such node.
+    pub fn travel(&mut self, id: usize) -> Option<(Vec<Group>, Vec<Group>)> {
+        if !self.nodes.contains_key(&id) {
+            return None;
+        }
+        let down_path = self.path_from_root(id);
+        let mut ups = Vec::new();
+        while !down_path.contains(&self.current) {
+            ups.push(self.undo()?);
+        }
+        let meeting = down_path.iter().position(|node| *node == self.current)?;
+        let mut downs = Vec::new();
+        for &node in &down_path[meeting + 1..] {
+            self.node_mut(self.current).redo = Some(node);
+            downs.push(self.redo()?);
+        }
+        Some((ups, downs))
+    }
+
+    // the tree drawn a node per line, depth-first with the older branch
+    // first, each with its id for travel. A branch that isn't the first is
+    // indented under the node it split off from.
+    pub fn lines(&self) -> Vec<(usize, String)> {
+        let now = Instant::now();
+        let mut lines = Vec::new();
+        let mut stack = vec![(self.root, 0)];
+        while let Some((id, indent)) = stack.pop() {
+            let node = &self.nodes[&id];
+            let marker = if id == self.current { '>' } else { ' ' };
+            let text = if id == 0 {
+                "original text".to_string()
+            } else if node.parent.is_none() {
+                // what came before went over the limit
+                "oldest kept".to_string()
+            } else {
+                format!("{} ({} ago)", describe(&node.changes), ago(now.duration_since(node.made).as_secs()))
+            };
+            lines.push((id, format!("{} {}{:>4}  {}", marker, "  ".repeat(indent), id, text)));
+            for (i, child) in node.children.iter().enumerate().rev() {
+                stack.push((*child, if i == 0 { indent } else { indent + 1 }));
+            }
+        }
+        lines
+    }
+
+    fn node_mut(&mut self, id: usize) -> &mut Node {
+        self.nodes.get_mut(&id).expect("undo tree node")
+    }
+
+    fn path_from_root(&self, id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[path.last().expect("undo tree path")].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    // Drops the oldest groups until the limit's met. The oldest is either a
+    // branch tip that isn't the current node, which just goes, or the root's
+    // only child, which becomes the root, its text the oldest that can be
+    // gone back to. The path from the root to the current node stays whole.
+    fn trim(&mut self) {
+        while self.nodes.len() > self.limit + 1 {
+            match self.oldest_droppable() {
+                Some(id) => self.drop_node(id),
+                None => return,
+            }
+        }
+    }
+
+    fn oldest_droppable(&self) -> Option<usize> {
+        let root = &self.nodes[&self.root];
+        let only_child = match root.children[..] {
+            [child] if self.current != self.root => Some(child),
+            _ => None,
+        };
+        self.nodes
+            .iter()
+            .find(|(id, node)| {
+                let tip = node.parent.is_some() && node.children.is_empty() && **id != self.current;
+                tip || only_child == Some(**id)
+            })
+            .map(|(id, _)| *id)
+    }
+
+    fn drop_node(&mut self, id: usize) {
+        let node = self.nodes.remove(&id).expect("undo tree node");
+        let parent = node.parent.expect("the root isn't dropped");
+        if node.children.is_empty() && id != self.current {
+            let parent = self.node_mut(parent);
+            parent.children.retain(|child| *child != id);
+            if parent.redo == Some(id) {
+                parent.redo = parent.children.last().copied();
+            }
+        } else {
+            self.nodes.remove(&parent);
+            self.nodes.insert(
+                id,
+                Node {
+                    parent: None,
+                    changes: Vec::new(),
+                    ..node
+                },
+            );
+            self.root = id;
+        }
+    }
+}
+
+// what a group did, shortened to fit on a line
+fn describe(changes: &[Change]) -> String {
+    let first = match changes.first() {
+        Some(first) => first,
+        None => return "nothing".to_string(),
+    };
+    let text = |text: &str| {
+        let short: String = text.chars().take(20).collect::<String>().replace('\n', "\\n");
+        if text.chars().count() > 20 {
+            format!("\"{}...\"", short)
+        } else {
+            format!("\"{}\"", short)
+        }
+    };
+    let what = match (first.removed.is_empty(), first.inserted.is_empty()) {
+        (true, _) => format!("added {}", text(&first.inserted)),
+        (false, true) => format!("deleted {}", text(&first.removed)),
+        (false, false) => format!("replaced {} with {}", text(&first.removed), text(&first.inserted)),
+    };
+    let line = first.start.y + 1;
+    match changes.len() {
+        1 => format!("{} on line {}", what, line),
+        n => format!("{} on line {} and {} more", what, line, n - 1),
+    }
+}
+
+fn ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        _ => format!("{}h", seconds / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // each in a group of its own, as if typed at different times
+    fn record(history: &mut History, text: &str) {
+        history.start_group();
+        history.record(Change {
+            start: Position::default(),
+            removed: String::new(),
+            inserted: text.to_string(),
+        });
+    }
+
+    fn inserted(group: Option<Group>) -> Option<String> {
+        group.map(|changes| changes.iter().map(|change| change.inserted.as_str()).collect())
+    }
+
+    #[test]
+    fn only_as_many_groups_as_the_limit_are_kept() {
+        let mut history = History::default();
+        history.set_limit(2);
+        for text in ["a", "b", "c"] {
+            record(&mut history, text);
+        }
+        assert_eq!(inserted(history.undo()), Some("c".to_string()));
+        assert_eq!(inserted(history.undo()), Some("b".to_string()));
+        assert_eq!(inserted(history.undo()), None);
+        // and what was undone can all be redone
+        assert_eq!(inserted(history.redo()), Some("b".to_string()));
+        assert_eq!(inserted(history.redo()), Some("c".to_string()));
+    }
+
+    #[test]
+    fn a_limit_of_nothing_keeps_nothing_to_undo() {
+        let mut history = History::default();
+        history.set_limit(0);
+        record(&mut history, "a");
+        record(&mut history, "b");
+        assert_eq!(inserted(history.undo()), None);
+        assert_eq!(history.nodes.len(), 1);
+    }
+
+    #[test]
+    fn redo_goes_down_a_branch_that_survives_the_trim() {
+        let mut history = History::default();
+        history.set_limit(10);
+        record(&mut history, "a");
+        record(&mut history, "b");
+        history.undo();
+        // c is a branch beside b, then b is come up out of again, so redo points at it
+        record(&mut history, "c");
+        history.travel(2);
+        history.undo();
+        assert_eq!(history.current, 1);
+        assert_eq!(history.nodes[&1].redo, Some(2));
+        // a becomes the root and b, the oldest tip, goes
+        history.set_limit(1);
+        assert!(!history.nodes.contains_key(&2));
+        assert_eq!(inserted(history.redo()), Some("c".to_string()));
+        assert_eq!(inserted(history.undo()), Some("c".to_string()));
+        assert_eq!(inserted(history.undo()), None);
+    }
+}
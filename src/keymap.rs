@@ -0,0 +1,345 @@
+use termion::event::Key;
+
+// Everything a key (or a sequence of keys) can be bound to. Typing text and
+// moving the cursor aren't in here, those are handled before the keymap is asked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    ForceQuit,
+    Save,
+    SaveSelection,
+    SaveSnapshot,
+    Reload,
+    ToggleReadOnly,
+    OpenFile,
+    OpenRecent,
+    PickSyntax,
+    PickLineEnding,
+    RepeatEdit,
+    Undo,
+    Redo,
+    UndoOlder,
+    UndoNewer,
+    UndoTree,
+    Find,
+    PeekLine,
+    GotoPercent,
+    ScrollDown,
+    ScrollUp,
+    NextSibling,
+    PreviousSibling,
+    Parent,
+    CycleLineNumbers,
+    InsertLiteral,
+    Reflow,
+    Increment,
+    Decrement,
+    Transpose,
+    KillLine,
+    OpenRowBelow,
+    OpenRowAbove,
+    Yank,
+    AddCursors,
+    SelectText,
+    SelectBlock,
+    Surround,
+    InsertOutput,
+    InsertOutputBelow,
+    InsertOutputAbove,
+    LoadQuickfix,
+    NextQuickfix,
+    PreviousQuickfix,
+    RecordMacro,
+    PlayMacro,
+    PickMacro,
+    ToggleOverwrite,
+    ToggleMinimap,
+    FullFeatures,
+    ToggleLongLines,
+    ToggleWordHighlight,
+    ToggleCursorColumn,
+    NewScratch,
+    RetabToSpaces,
+    RetabToTabs,
+    ReverseLines,
+    StripAnsi,
+    Prettify,
+    Calculate,
+    CommandPalette,
+    Cancel,
+}
+
+impl Action {
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::ForceQuit => "quit now, dropping unsaved changes",
+            Action::Save => "save",
+            Action::SaveSelection => "save the selected rows to another file",
+            Action::SaveSnapshot => "save what's on screen to a file",
+            Action::Reload => "reload the file, dropping changes",
+            Action::ToggleReadOnly => "toggle read-only",
+            Action::OpenFile => "open a file",
+            Action::OpenRecent => "open a recent file",
+            Action::PickSyntax => "pick the syntax",
+            Action::PickLineEnding => "pick the line ending",
+            Action::RepeatEdit => "repeat the last edit",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::UndoOlder => "back to the edit before, on any branch",
+            Action::UndoNewer => "on to the edit after, on any branch",
+            Action::UndoTree => "go anywhere in the undo tree",
+            Action::Find => "find",
+            Action::PeekLine => "show the whole row in the message bar, again to copy it",
+            Action::GotoPercent => "go to a percentage through the file",
+            Action::ScrollDown => "scroll the view down",
+            Action::ScrollUp => "scroll the view up",
+            Action::NextSibling => "next row indented the same",
+            Action::PreviousSibling => "previous row indented the same",
+            Action::Parent => "up to the row this one's indented under",
+            Action::CycleLineNumbers => "cycle line numbers",
+            Action::InsertLiteral => "insert a key literally",
+            Action::Reflow => "rewrap the paragraph",
+            Action::Increment => "add one to the number under the cursor",
+            Action::Decrement => "take one from the number under the cursor",
+            Action::Transpose => "swap the characters either side of the cursor",
+            Action::KillLine => "cut to the end of the row",
+            Action::OpenRowBelow => "a new row below this one",
+            Action::OpenRowAbove => "a new row above this one",
+            Action::Yank => "paste what was last cut",
+            Action::AddCursors => "a cursor on every match",
+            Action::SelectText => "select text",
+            Action::SelectBlock => "select a block",
+            Action::Surround => "surround the selection or word",
+            Action::InsertOutput => "insert a command's output",
+            Action::InsertOutputBelow => "a command's output below this row",
+            Action::InsertOutputAbove => "a command's output above this row",
+            Action::LoadQuickfix => "load a quickfix list",
+            Action::NextQuickfix => "next quickfix entry",
+            Action::PreviousQuickfix => "previous quickfix entry",
+            Action::RecordMacro => "record a macro",
+            Action::PlayMacro => "play the last macro",
+            Action::PickMacro => "play a saved macro",
+            Action::ToggleOverwrite => "toggle overwrite",
+            Action::ToggleMinimap => "toggle the minimap",
+            Action::FullFeatures => "highlight a large file anyway",
+            Action::ToggleLongLines => "toggle flagging long lines",
+            Action::ToggleWordHighlight => "toggle highlighting the word under the cursor",
+            Action::ToggleCursorColumn => "toggle shading the cursor's column",
+            Action::NewScratch => "a new scratch buffer",
+            Action::RetabToSpaces => "indent with spaces throughout",
+            Action::RetabToTabs => "indent with tabs throughout",
+            Action::ReverseLines => "reverse the selected rows",
+            Action::StripAnsi => "strip terminal colour codes",
+            Action::Prettify => "lay the buffer out with prettify_command",
+            Action::Calculate => "work out a sum and insert the answer",
+            Action::CommandPalette => "run any of these by name",
+            Action::Cancel => "cancel",
+        }
+    }
+
+    // the ones a read-only buffer refuses
+    pub fn edits(self) -> bool {
+        matches!(
+            self,
+            Action::Save
+                | Action::RepeatEdit
+                | Action::Undo
+                | Action::Redo
+                | Action::UndoOlder
+                | Action::UndoNewer
+                | Action::UndoTree
+                | Action::InsertLiteral
+                | Action::Reflow
+                | Action::Increment
+                | Action::Decrement
+                | Action::Transpose
+                | Action::KillLine
+                | Action::OpenRowBelow
+                | Action::OpenRowAbove
+                | Action::Yank
+                | Action::Surround
+                | Action::InsertOutput
+                | Action::InsertOutputBelow
+                | Action::InsertOutputAbove
+                | Action::RetabToSpaces
+                | Action::RetabToTabs
+                | Action::ReverseLines
+                | Action::StripAnsi
+                | Action::Prettify
+                | Action::Calculate
+        )
+    }
+}
+
+pub enum Lookup {
+    Action(Action),
+    // the keys so far start one or more longer bindings,
+    // and might also be a binding of their own if no more keys come
+    Prefix(Option<Action>),
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let single = [
+            (Key::Ctrl('q'), Action::Quit),
+            (Key::Ctrl('s'), Action::Save),
+            (Key::Ctrl('o'), Action::OpenFile),
+            (Key::Alt('o'), Action::OpenRecent),
+            (Key::Alt('t'), Action::PickSyntax),
+            (Key::Alt('l'), Action::PickLineEnding),
+            (Key::Ctrl('r'), Action::RepeatEdit),
+            (Key::Ctrl('z'), Action::Undo),
+            (Key::Ctrl('y'), Action::Redo),
+            (Key::Ctrl('f'), Action::Find),
+            (Key::Ctrl('l'), Action::PeekLine),
+            (Key::Alt('g'), Action::GotoPercent),
+            // vim's Ctrl-Y is redo here
+            (Key::Ctrl('e'), Action::ScrollDown),
+            (Key::Ctrl('w'), Action::ScrollUp),
+            (Key::Alt('}'), Action::NextSibling),
+            (Key::Alt('{'), Action::PreviousSibling),
+            (Key::Alt('u'), Action::Parent),
+            (Key::Ctrl('n'), Action::CycleLineNumbers),
+            (Key::Ctrl('v'), Action::InsertLiteral),
+            (Key::Alt('q'), Action::Reflow),
+            // vim's Ctrl-X is taken by the emacs keys below
+            (Key::Ctrl('a'), Action::Increment),
+            (Key::Alt('x'), Action::Decrement),
+            (Key::Ctrl('t'), Action::Transpose),
+            // Ctrl-Y is already redo
+            (Key::Ctrl('k'), Action::KillLine),
+            (Key::Alt('y'), Action::Yank),
+            (Key::Alt('d'), Action::AddCursors),
+            (Key::Alt('v'), Action::SelectText),
+            (Key::Alt('b'), Action::SelectBlock),
+            (Key::Alt('s'), Action::Surround),
+            (Key::Alt('e'), Action::LoadQuickfix),
+            (Key::Alt('n'), Action::NextQuickfix),
+            (Key::Alt('p'), Action::PreviousQuickfix),
+            (Key::Alt('m'), Action::RecordMacro),
+            (Key::Alt('r'), Action::PlayMacro),
+            (Key::Alt('k'), Action::PickMacro),
+            (Key::Insert, Action::ToggleOverwrite),
+            (Key::F(1), Action::CommandPalette),
+            (Key::Esc, Action::Cancel),
+        ];
+        // emacs-ish ones behind Ctrl-X, for those with the habit
+        let ctrl_x = [
+            (Key::Ctrl('s'), Action::Save),
+            (Key::Char('w'), Action::SaveSelection),
+            (Key::Char('p'), Action::SaveSnapshot),
+            (Key::Ctrl('r'), Action::Reload),
+            (Key::Ctrl('q'), Action::ToggleReadOnly),
+            (Key::Char('!'), Action::InsertOutput),
+            (Key::Char('r'), Action::InsertOutputBelow),
+            (Key::Char('R'), Action::InsertOutputAbove),
+            (Key::Char('o'), Action::OpenRowBelow),
+            (Key::Char('O'), Action::OpenRowAbove),
+            (Key::Ctrl('c'), Action::Quit),
+            // no warnings and no second chances, so it's kept well away from Ctrl-Q
+            (Key::Char('q'), Action::ForceQuit),
+            (Key::Ctrl('f'), Action::OpenFile),
+            (Key::Char('u'), Action::Undo),
+            // vim's g- and g+
+            (Key::Char('-'), Action::UndoOlder),
+            (Key::Char('+'), Action::UndoNewer),
+            (Key::Char('U'), Action::UndoTree),
+            (Key::Char('('), Action::RecordMacro),
+            (Key::Char(')'), Action::RecordMacro),
+            (Key::Char('e'), Action::PlayMacro),
+            (Key::Char('m'), Action::ToggleMinimap),
+            (Key::Char('f'), Action::FullFeatures),
+            (Key::Char('l'), Action::ToggleLongLines),
+            (Key::Char('h'), Action::ToggleWordHighlight),
+            (Key::Char('c'), Action::ToggleCursorColumn),
+            (Key::Char('s'), Action::NewScratch),
+            (Key::Char('t'), Action::RetabToSpaces),
+            (Key::Char('T'), Action::RetabToTabs),
+            (Key::Char('v'), Action::ReverseLines),
+            (Key::Char('a'), Action::StripAnsi),
+            (Key::Char('b'), Action::Prettify),
+            (Key::Char('='), Action::Calculate),
+            // emacs' M-x
+            (Key::Char('x'), Action::CommandPalette),
+        ];
+        let mut bindings: Vec<(Vec<Key>, Action)> =
+            single.iter().map(|(key, action)| (vec![*key], *action)).collect();
+        bindings.extend(ctrl_x.iter().map(|(key, action)| (vec![Key::Ctrl('x'), *key], *action)));
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        let mut exact = None;
+        let mut prefix = false;
+        for (bound, action) in &self.bindings {
+            if bound[..] == *keys {
+                exact = Some(*action);
+            } else if bound.starts_with(keys) {
+                prefix = true;
+            }
+        }
+        match (exact, prefix) {
+            (_, true) => Lookup::Prefix(exact),
+            (Some(action), false) => Lookup::Action(action),
+            (None, false) => Lookup::Unbound,
+        }
+    }
+
+    // every bound action once, with the first keys it's bound to
+    pub fn commands(&self) -> Vec<(Action, &[Key])> {
+        let mut commands: Vec<(Action, &[Key])> = Vec::new();
+        for (keys, action) in &self.bindings {
+            if !commands.iter().any(|(seen, _)| seen == action) {
+                commands.push((*action, keys));
+            }
+        }
+        commands
+    }
+
+    // the keys that can come after prefix, each with the action it finishes,
+    // or None if it only leads on to more keys
+    pub fn continuations(&self, prefix: &[Key]) -> Vec<(Key, Option<Action>)> {
+        let mut next: Vec<(Key, Option<Action>)> = Vec::new();
+        for (bound, action) in &self.bindings {
+            if bound.len() <= prefix.len() || !bound.starts_with(prefix) {
+                continue;
+            }
+            let key = bound[prefix.len()];
+            let action = if bound.len() == prefix.len() + 1 { Some(*action) } else { None };
+            if !next.iter().any(|(seen, _)| *seen == key) {
+                next.push((key, action));
+            }
+        }
+        next
+    }
+}
+
+// how a key is written in hints, e.g. Ctrl-S or Alt-X
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c.to_ascii_uppercase()),
+        Key::Alt(c) => format!("Alt-{}", c.to_ascii_uppercase()),
+        Key::F(n) => format!("F{}", n),
+        Key::Esc => "Esc".to_string(),
+        Key::Insert => "Insert".to_string(),
+        Key::Delete => "Delete".to_string(),
+        Key::Backspace => "Backspace".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter().map(|key| key_name(*key)).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,221 @@
+use crate::recent::state_dir;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use termion::event::Key;
+
+// Saved macros live in one file in the state dir, a `name = keys` line each.
+// The keys are written like vim's notation: plain characters as themselves,
+// everything else in angle brackets, e.g. `dd<Up><C-s>`.
+fn macros_file() -> Option<PathBuf> {
+    Some(state_dir()?.join("macros"))
+}
+
+fn read_all(file: &Path) -> Vec<(String, Vec<Key>)> {
+    let contents = fs::read_to_string(file).unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (name, keys) = line.split_once('=')?;
+            Some((name.trim().to_string(), decode(keys.trim())?))
+        })
+        .collect()
+}
+
+pub fn names() -> Vec<String> {
+    macros_file().map(|file| names_in(&file)).unwrap_or_default()
+}
+
+pub fn load(name: &str) -> Option<Vec<Key>> {
+    load_from(&macros_file()?, name)
+}
+
+pub fn save(name: &str, keys: &[Key]) -> Result<(), io::Error> {
+    let file = macros_file().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state dir"))?;
+    save_to(&file, name, keys)
+}
+
+// the same three for a file of macros anywhere
+fn names_in(file: &Path) -> Vec<String> {
+    read_all(file).into_iter().map(|(name, _)| name).collect()
+}
+
+fn load_from(file: &Path, name: &str) -> Option<Vec<Key>> {
+    read_all(file)
+        .into_iter()
+        .find(|(saved, _)| saved == name)
+        .map(|(_, keys)| keys)
+}
+
+// replaces any macro already saved under name
+fn save_to(file: &Path, name: &str, keys: &[Key]) -> Result<(), io::Error> {
+    let mut macros = read_all(file);
+    macros.retain(|(saved, _)| saved != name);
+    macros.push((name.to_string(), keys.to_vec()));
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lines: Vec<String> = macros
+        .iter()
+        .map(|(name, keys)| format!("{} = {}", name, encode(keys)))
+        .collect();
+    fs::write(file, lines.join("\n") + "\n")
+}
+
+// keys that can't be written down, like mouse events, are left out
+pub fn encode(keys: &[Key]) -> String {
+    keys.iter().filter_map(|key| encode_key(*key)).collect()
+}
+
+fn encode_key(key: Key) -> Option<String> {
+    let name = match key {
+        Key::Char(c) => match char_name(c) {
+            Some(name) => name.to_string(),
+            None => return Some(c.to_string()),
+        },
+        Key::Ctrl(c) => format!("C-{}", char_name(c).map_or(c.to_string(), String::from)),
+        Key::Alt(c) => format!("A-{}", char_name(c).map_or(c.to_string(), String::from)),
+        Key::F(n) => format!("F{}", n),
+        Key::Backspace => "BS".to_string(),
+        Key::Left => "Left".to_string(),
+        Key::Right => "Right".to_string(),
+        Key::Up => "Up".to_string(),
+        Key::Down => "Down".to_string(),
+        Key::Home => "Home".to_string(),
+        Key::End => "End".to_string(),
+        Key::PageUp => "PageUp".to_string(),
+        Key::PageDown => "PageDown".to_string(),
+        Key::BackTab => "S-Tab".to_string(),
+        Key::Delete => "Del".to_string(),
+        Key::Insert => "Insert".to_string(),
+        Key::Esc => "Esc".to_string(),
+        _ => return None,
+    };
+    Some(format!("<{}>", name))
+}
+
+// characters that would get lost or misread written as themselves
+fn char_name(c: char) -> Option<&'static str> {
+    match c {
+        '<' => Some("lt"),
+        '>' => Some("gt"),
+        ' ' => Some("Space"),
+        '\n' => Some("CR"),
+        '\t' => Some("Tab"),
+        _ => None,
+    }
+}
+
+// None if there's anything in there that isn't a key
+pub fn decode(text: &str) -> Option<Vec<Key>> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = rest.find('>')?;
+            keys.push(decode_name(&rest[1..end])?);
+            rest = &rest[end + 1..];
+        } else {
+            keys.push(Key::Char(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Some(keys)
+}
+
+fn decode_name(name: &str) -> Option<Key> {
+    let key = match name {
+        "BS" => Key::Backspace,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "S-Tab" => Key::BackTab,
+        "Del" => Key::Delete,
+        "Insert" => Key::Insert,
+        "Esc" => Key::Esc,
+        _ => {
+            if let Some(c) = name.strip_prefix("C-") {
+                Key::Ctrl(decode_char(c)?)
+            } else if let Some(c) = name.strip_prefix("A-") {
+                Key::Alt(decode_char(c)?)
+            } else if let Some(n) = name.strip_prefix('F') {
+                Key::F(n.parse().ok()?)
+            } else {
+                Key::Char(decode_char(name)?)
+            }
+        }
+    };
+    Some(key)
+}
+
+fn decode_char(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "Space" => Some(' '),
+        "CR" => Some('\n'),
+        "Tab" => Some('\t'),
+        _ => {
+            let mut chars = name.chars();
+            let c = chars.next()?;
+            chars.next().is_none().then_some(c)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn keys_survive_being_written_down() {
+        let keys = vec![
+            Key::Home,
+            Key::Char('-'),
+            Key::Char(' '),
+            Key::Char('<'),
+            Key::Char('>'),
+            Key::Char('\t'),
+            Key::Char('\n'),
+            Key::Char('é'),
+            Key::Ctrl('s'),
+            Key::Alt(' '),
+            Key::F(12),
+            Key::BackTab,
+            Key::Down,
+        ];
+        let text = encode(&keys);
+        assert_eq!(text, "<Home>-<Space><lt><gt><Tab><CR>é<C-s><A-Space><F12><S-Tab><Down>");
+        assert_eq!(decode(&text), Some(keys));
+    }
+
+    #[test]
+    fn anything_that_is_not_a_key_is_refused() {
+        assert_eq!(decode("<Nope>"), None);
+        assert_eq!(decode("a<Up"), None);
+        assert_eq!(decode("<C-ab>"), None);
+    }
+
+    #[test]
+    fn a_saved_macro_loads_back_by_name() {
+        let dir = env::temp_dir().join(format!("milli-macros-{}", process::id()));
+        let file = dir.join("macros");
+        let prefix = [Key::Home, Key::Char('-'), Key::Char(' '), Key::Down];
+        save_to(&file, "prefix", &prefix).unwrap();
+        save_to(&file, "other", &[Key::Ctrl('k')]).unwrap();
+        // saving under a name that's taken replaces it
+        save_to(&file, "other", &[Key::End]).unwrap();
+        assert_eq!(names_in(&file), ["prefix", "other"]);
+        assert_eq!(load_from(&file, "prefix").as_deref(), Some(&prefix[..]));
+        assert_eq!(load_from(&file, "other"), Some(vec![Key::End]));
+        assert_eq!(load_from(&file, "missing"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
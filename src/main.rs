@@ -1,20 +1,66 @@
+mod buffer;
+mod calc;
+mod config;
 mod editor;
 mod terminal;
 mod navigation;
+mod paths;
+mod picker;
+mod quickfix;
+mod recent;
 mod row; 
+mod shell;
+mod signs;
+mod syntax;
 mod document;
+mod diff;
+mod edit;
+mod git;
+mod highlighting;
+mod history;
+mod keymap;
+mod macros;
 
-pub use document::Document;
+pub use buffer::TextBuffer;
+pub use config::{Config, ControlCharStyle, LineNumberMode, LongLineStyle, SearchCase};
+pub use document::{Document, IndentStyle, LineEnding};
+pub use edit::Edit;
 use editor::Editor;
-pub use editor::Position;
-pub use row::Row;
-pub use terminal::Terminal;
+pub use editor::{Position, SearchDirection};
+pub use highlighting::{HighlightState, Highlighting};
+pub use row::{highlight_line, Row};
+pub use syntax::SyntaxDefinition;
+pub use terminal::{CursorShape, Event, Size, Terminal};
 pub use navigation::Navigable;
-use simple_logging;
-use log::LevelFilter;
+use log::{error, LevelFilter};
+use std::io::{self, Write};
+use std::panic;
+use std::process;
+use std::sync::{Arc, Mutex};
 
 fn main() {
-    simple_logging::log_to_file("logs/log.txt", LevelFilter::Debug );
-    let mut editor = Editor::default();
-    editor.run();
+    let _ = simple_logging::log_to_file("logs/log.txt", LevelFilter::Debug);
+    // a panic is reported before anything is dropped, which would put the message
+    // on the alternate screen in raw mode and lose it when the terminal is put back,
+    // so it's kept until the editor (and its terminal) have been dropped
+    let report = Arc::new(Mutex::new(None));
+    let hook_report = Arc::clone(&report);
+    panic::set_hook(Box::new(move |info| {
+        error!("{}", info);
+        if let Ok(mut report) = hook_report.lock() {
+            *report = Some(info.to_string());
+        }
+    }));
+    let result = panic::catch_unwind(|| {
+        let mut editor = Editor::default();
+        editor.run();
+    });
+    if result.is_err() {
+        // leaving the alternate screen is still sitting in stdout's buffer
+        let _ = io::stdout().flush();
+        if let Some(report) = report.lock().ok().and_then(|mut report| report.take()) {
+            eprintln!("{}", report);
+        }
+        process::exit(101);
+    }
 }
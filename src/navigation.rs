@@ -1,10 +1,11 @@
 use log::{debug, info};
 use termion::event::Key;
 // these have been linked already in main.rs, so we are simply saying to use them in this file
-use crate::Editor;
+use crate::Config;
+use crate::Document;
 use crate::Position;
 
-pub type Navigation = dyn Fn(&Editor, &Position) -> Position;
+pub type Navigation = dyn Fn(&Document, &Config, &Position) -> Position;
 
 // If something is defined as something to be "Navigable"
 // then it has access to the navigation_func function
@@ -30,32 +31,32 @@ impl Navigable for Key {
     }
 }
 
-fn calc_line_width(editor: &Editor, y: usize) -> usize {
-    if let Some(row) = editor.document().row(y) {
+fn calc_line_width(document: &Document, y: usize) -> usize {
+    if let Some(row) = document.row(y) {
         row.len()
     } else {
         0
     }
 }
 
-fn navigate_line_end(editor: &Editor, position: &Position) -> Position {
+fn navigate_line_end(document: &Document, _: &Config, position: &Position) -> Position {
     let (_, y) = position.as_tuple();
-    let last_col = calc_line_width(editor, y);
+    let last_col = calc_line_width(document, y);
     debug!("line end to: {} {}", last_col, y);
     Position { x: last_col, y }
 }
 
-fn navigate_line_start(_: &Editor, position: &Position) -> Position {
+fn navigate_line_start(_: &Document, _: &Config, position: &Position) -> Position {
     let (_, y) = position.as_tuple();
 
     Position { x: 0, y }
 }
 
-fn navigate_document_end(editor: &Editor, position: &Position) -> Position {
+fn navigate_document_end(document: &Document, _: &Config, position: &Position) -> Position {
     let (mut x, y) = position.as_tuple();
-    let size = editor.document().len();
-    let height = size.saturating_sub(1) as usize;
-    let width = calc_line_width(editor, y);
+    let size = document.len();
+    let height = size.saturating_sub(1);
+    let width = calc_line_width(document, y);
 
     if x > width {
         x = width;
@@ -64,9 +65,9 @@ fn navigate_document_end(editor: &Editor, position: &Position) -> Position {
     Position { x, y: height }
 }
 
-fn navigate_document_start(editor: &Editor, position: &Position) -> Position {
+fn navigate_document_start(document: &Document, _: &Config, position: &Position) -> Position {
     let (mut x, _) = position.as_tuple();
-    let width = calc_line_width(editor, 0);
+    let width = calc_line_width(document, 0);
 
     if x > width {
         x = width;
@@ -74,13 +75,13 @@ fn navigate_document_start(editor: &Editor, position: &Position) -> Position {
     Position { x, y: 0 }
 }
 
-fn navigate_up(editor: &Editor, position: &Position) -> Position {
+fn navigate_up(document: &Document, config: &Config, position: &Position) -> Position {
     let (mut x, y) = position.as_tuple();
 
     if y > 0 {
         info!("Navigating up    to ({} {})", x, y.saturating_sub(1));
-        let prev_line_width = calc_line_width(editor, y.saturating_sub(1));
-        if x > prev_line_width {
+        let prev_line_width = calc_line_width(document, y.saturating_sub(1));
+        if x > prev_line_width && !config.virtual_edit {
             x = prev_line_width;
         }
         Position {
@@ -92,14 +93,16 @@ fn navigate_up(editor: &Editor, position: &Position) -> Position {
     }
 }
 
-fn navigate_down(editor: &Editor, position: &Position) -> Position {
+fn navigate_down(document: &Document, config: &Config, position: &Position) -> Position {
     let (mut x, y) = position.as_tuple();
-    let height = editor.document().len();
+    let height = document.len();
 
-    if y < height {
+    // the last row is as far as the cursor goes, a trailing newline
+    // shows up as an empty last row
+    if y.saturating_add(1) < height {
         info!("Navigating down  to ({} {})", x, y.saturating_add(1));
-        let next_line_width = calc_line_width(editor, y.saturating_add(1));
-        if x > next_line_width {
+        let next_line_width = calc_line_width(document, y.saturating_add(1));
+        if x > next_line_width && !config.virtual_edit {
             x = next_line_width;
         }
 
@@ -112,13 +115,13 @@ fn navigate_down(editor: &Editor, position: &Position) -> Position {
     }
 }
 
-fn navigate_left(editor: &Editor, position: &Position) -> Position {
+fn navigate_left(document: &Document, _: &Config, position: &Position) -> Position {
     let (x, mut y) = position.as_tuple();
     if x > 0 {
         Position { x: x - 1, y }
     } else if y > 0 {
         y -= 1;
-        if let Some(row) = editor.document().row(y) {
+        if let Some(row) = document.row(y) {
             Position { x: row.len(), y }
         } else {
             Position { x: 0, y }
@@ -128,18 +131,84 @@ fn navigate_left(editor: &Editor, position: &Position) -> Position {
     }
 }
 
-fn navigate_right(editor: &Editor, position: &Position) -> Position {
+fn navigate_right(document: &Document, config: &Config, position: &Position) -> Position {
     let (x, y) = position.as_tuple();
-    let width = calc_line_width(editor, y);
-    // is this suppsoed to be len - 1?
-    let height = editor.document().len();
+    let width = calc_line_width(document, y);
+    let height = document.len();
 
-    if x < width {
+    // with virtual_edit the cursor carries on past the end of the row
+    if x < width || config.virtual_edit {
         info!("Navigating right to ({} {})", x + 1, y);
         Position { x: x + 1, y }
-    } else if y < height {
+    } else if y + 1 < height {
         Position { x: 0, y: y + 1 }
     } else {
         Position { x, y }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    const TAB_STOP: usize = 4;
+
+    // "\tone", "  \ttwo\tthree", "    four\t\tfive", "\t \t  mixed"
+    fn tabs() -> Document {
+        Document::open(&format!("{}/test/tabs.txt", env!("CARGO_MANIFEST_DIR"))).expect("fixture opens")
+    }
+
+    fn press(document: &Document, key: Key, at: Position) -> Position {
+        let navigation = key.navigation_func().expect("a movement key");
+        navigation(document, &Config::default(), &at)
+    }
+
+    // where the cursor is drawn
+    fn column(document: &Document, at: &Position) -> usize {
+        document.row(at.y).map_or(0, |row| row.char_to_display_col(at.x, TAB_STOP))
+    }
+
+    #[test]
+    fn right_and_left_step_over_a_tab_in_one_go() {
+        let document = tabs();
+        let mut at = Position { x: 0, y: 3 };
+        let mut columns = vec![column(&document, &at)];
+        for _ in 0..4 {
+            at = press(&document, Key::Right, at);
+            columns.push(column(&document, &at));
+        }
+        assert_eq!(at, Position { x: 4, y: 3 });
+        assert_eq!(columns, [0, 4, 5, 8, 9]);
+        for x in (0..4).rev() {
+            at = press(&document, Key::Left, at);
+            assert_eq!(at, Position { x, y: 3 });
+        }
+    }
+
+    #[test]
+    fn home_and_end_land_on_the_ends_of_a_row_with_tabs() {
+        let document = tabs();
+        let row = document.row(1).expect("the fixture has a second row");
+        let end = press(&document, Key::End, Position { x: 3, y: 1 });
+        assert_eq!(end, Position { x: row.len(), y: 1 });
+        assert_eq!(column(&document, &end), row.display_len(TAB_STOP));
+        assert_eq!(press(&document, Key::Home, end), Position { x: 0, y: 1 });
+    }
+
+    #[test]
+    fn the_cursor_is_drawn_where_its_text_is() {
+        let document = tabs();
+        for y in 0..document.len() {
+            let row = document.row(y).expect("in range");
+            let expanded: Vec<char> = row.expanded(TAB_STOP).chars().collect();
+            for (x, grapheme) in row.as_str().graphemes(true).enumerate() {
+                let col = row.char_to_display_col(x, TAB_STOP);
+                if grapheme != "\t" {
+                    assert_eq!(expanded[col].to_string(), grapheme, "row {} grapheme {}", y, x);
+                }
+                assert_eq!(row.display_col_to_char(col, TAB_STOP), x);
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::env;
+use std::fs;
+
+// Expands a leading ~ or ~user and any $NAME or ${NAME} in a path typed by
+// hand, the way a shell would have. Whatever can't be resolved, like an
+// unknown user or an unset variable, is left as it was written, so opening it
+// fails (or makes a new file) with the name the user actually typed.
+pub fn expand(path: &str) -> String {
+    expanded(path, |name| env::var(name).ok())
+}
+
+// the same, with the variables looked up by var
+fn expanded<F: Fn(&str) -> Option<String>>(path: &str, var: F) -> String {
+    expand_vars(&expand_home(path, &var), &var)
+}
+
+fn expand_home<F: Fn(&str) -> Option<String>>(path: &str, var: F) -> String {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) => rest,
+        None => return path.to_string(),
+    };
+    let (user, tail) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, ""),
+    };
+    let home = if user.is_empty() {
+        var("HOME")
+    } else {
+        home_of(user)
+    };
+    match home {
+        Some(home) => format!("{}{}", home, tail),
+        None => path.to_string(),
+    }
+}
+
+// there's no getpwnam without libc, so this reads /etc/passwd itself
+fn home_of(user: &str) -> Option<String> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        (fields.len() > 5 && fields[0] == user).then(|| fields[5].to_string())
+    })
+}
+
+fn expand_vars<F: Fn(&str) -> Option<String>>(path: &str, var: F) -> String {
+    let mut result = String::new();
+    let mut rest = path;
+    while let Some(dollar) = rest.find('$') {
+        result.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+        let (name, written_len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(close) => (&braced[..close], close + 2),
+                None => ("", 0),
+            },
+            None => {
+                let len = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..len], len)
+            }
+        };
+        match var(name) {
+            Some(value) if !name.is_empty() => result.push_str(&value),
+            _ => result.push_str(&rest[dollar..dollar + 1 + written_len]),
+        }
+        rest = &after[written_len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a made up environment, so the real one doesn't matter
+    fn expand_with(path: &str) -> String {
+        expanded(path, |name| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "DIR" => Some("notes".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn home_and_variables_are_filled_in() {
+        assert_eq!(expand_with("~/x"), "/home/me/x");
+        assert_eq!(expand_with("~"), "/home/me");
+        assert_eq!(expand_with("$HOME/$DIR/${DIR}.txt"), "/home/me/notes/notes.txt");
+        // only at the very start
+        assert_eq!(expand_with("a/~/x"), "a/~/x");
+    }
+
+    #[test]
+    fn what_can_not_be_filled_in_stays_as_typed() {
+        assert_eq!(expand_with("~nosuchuser/x"), "~nosuchuser/x");
+        assert_eq!(expand_with("$NOPE/x"), "$NOPE/x");
+        assert_eq!(expand_with("${NOPE}/x"), "${NOPE}/x");
+        assert_eq!(expand_with("${NOPE/x"), "${NOPE/x");
+        assert_eq!(expand_with("cost$"), "cost$");
+        assert_eq!(expand_with("$/x"), "$/x");
+    }
+}
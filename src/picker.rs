@@ -0,0 +1,123 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// stop walking after this many files, big trees would take forever
+const MAX_FILES: usize = 10_000;
+
+// every file under root, as paths relative to it
+// hidden files and anything in the root .gitignore are skipped
+pub fn list_files(root: &Path) -> Vec<String> {
+    let ignored = read_gitignore(root);
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || is_ignored(&name, &ignored) {
+                continue;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                files.push(relative.to_string_lossy().to_string());
+                if files.len() >= MAX_FILES {
+                    return files;
+                }
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// what's directly in dir, for browsing it: ../ first, then the directories
+// (with a / on the end) and then the files, each sorted by name
+pub fn dir_entries(dir: &Path) -> io::Result<Vec<String>> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_dir() {
+            dirs.push(format!("{}/", name));
+        } else {
+            files.push(name);
+        }
+    }
+    dirs.sort();
+    files.sort();
+    let mut entries = vec!["../".to_string()];
+    entries.extend(dirs);
+    entries.extend(files);
+    Ok(entries)
+}
+
+// only the simple cases, plain names and *.extension
+fn read_gitignore(root: &Path) -> Vec<String> {
+    let contents = fs::read_to_string(root.join(".gitignore")).unwrap_or_default();
+    contents
+        .lines()
+        .map(|line| line.trim().trim_matches('/').to_string())
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .collect()
+}
+
+fn is_ignored(name: &str, ignored: &[String]) -> bool {
+    ignored.iter().any(|pattern| match pattern.strip_prefix('*') {
+        Some(suffix) => name.ends_with(suffix),
+        None => name == pattern,
+    })
+}
+
+// None if query isn't a subsequence of candidate, otherwise higher is better
+// runs of matching characters and matches at the start of a path component
+// score extra, and shorter candidates win ties
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut query_chars = query.chars().map(|c| c.to_ascii_lowercase()).peekable();
+    let mut previous_matched = false;
+    let mut previous: Option<char> = None;
+    for c in candidate.chars() {
+        let wanted = match query_chars.peek() {
+            Some(wanted) => *wanted,
+            None => break,
+        };
+        if c.to_ascii_lowercase() == wanted {
+            score += 1;
+            if previous_matched {
+                score += 5;
+            }
+            if previous.is_none_or(|p| ['/', '_', '-', '.'].contains(&p)) {
+                score += 10;
+            }
+            query_chars.next();
+            previous_matched = true;
+        } else {
+            previous_matched = false;
+        }
+        previous = Some(c);
+    }
+    if query_chars.peek().is_some() {
+        return None;
+    }
+    Some(score * 100 - candidate.len() as i64)
+}
+
+// the candidates matching query, best first
+// an empty query keeps the original order
+pub fn fuzzy_filter<'a>(query: &str, candidates: &'a [String]) -> Vec<&'a String> {
+    if query.is_empty() {
+        return candidates.iter().collect();
+    }
+    let mut scored: Vec<(i64, &String)> = candidates
+        .iter()
+        .filter_map(|candidate| fuzzy_score(query, candidate).map(|score| (score, candidate)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.into_iter().map(|(_, candidate)| candidate).collect()
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::Path;
+
+// one place the compiler (or grep, or anything else) pointed at
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub file: String,
+    // both start at 1, like the tools print them
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+pub fn load(path: &Path) -> Result<Vec<Entry>, std::io::Error> {
+    Ok(parse(&fs::read_to_string(path)?))
+}
+
+// picks out every `file:line:col: message` (or `file:line: message`) line
+// cargo's ` --> file:line:col` lines are understood too, and take their message
+// from the `error: ...` or `warning: ...` line above them
+pub fn parse(text: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut heading = "";
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("error") || trimmed.starts_with("warning") {
+            heading = trimmed;
+        }
+        let (location, from_arrow) = match trimmed.strip_prefix("-->") {
+            Some(location) => (location.trim_start(), true),
+            None => (trimmed, false),
+        };
+        if let Some(mut entry) = parse_location(location) {
+            if from_arrow && entry.message.is_empty() {
+                entry.message = heading.to_string();
+            }
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+fn parse_location(text: &str) -> Option<Entry> {
+    let mut parts = text.splitn(4, ':');
+    let file = parts.next()?;
+    if file.is_empty() || file.contains(char::is_whitespace) {
+        return None;
+    }
+    let line = parts.next()?.parse().ok()?;
+    let (col, message) = match parts.next() {
+        Some(part) => match part.parse() {
+            Ok(col) => (col, parts.next().unwrap_or("")),
+            // no column, so this is already the message
+            Err(_) => (1, text.splitn(3, ':').nth(2).unwrap_or("")),
+        },
+        None => (1, ""),
+    };
+    Some(Entry {
+        file: file.to_string(),
+        line,
+        col,
+        message: message.trim().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, line: usize, col: usize, message: &str) -> Entry {
+        Entry {
+            file: file.to_string(),
+            line,
+            col,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn every_kind_of_location_in_the_fixture_is_picked_out() {
+        let path = format!("{}/test/errors.txt", env!("CARGO_MANIFEST_DIR"));
+        let entries = parse(&fs::read_to_string(path).unwrap());
+        assert_eq!(
+            entries,
+            [
+                // cargo's arrow, with the heading above it for a message
+                entry("test/literals.rs", 3, 5, "error[E0425]: cannot find value `y` in this scope"),
+                entry("test/tabs.txt", 2, 5, "warning: mixed tabs and spaces"),
+                // no column, so it's the first
+                entry("test/unicode.txt", 1, 1, "first line"),
+            ]
+        );
+    }
+}
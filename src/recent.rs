@@ -0,0 +1,64 @@
+use log::info;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// how many files are remembered
+const MAX_RECENT: usize = 20;
+
+// $XDG_STATE_HOME/milli, or ~/.local/state/milli
+pub fn state_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local").join("state"),
+    };
+    Some(dir.join("milli"))
+}
+
+fn recent_file() -> Option<PathBuf> {
+    Some(state_dir()?.join("recent"))
+}
+
+fn read_all() -> Vec<String> {
+    recent_file()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+// the recently used files, most recent first
+// files that have since gone away are left out
+pub fn load() -> Vec<String> {
+    read_all()
+        .into_iter()
+        .filter(|file| Path::new(file).exists())
+        .collect()
+}
+
+// moves file_name to the front of the list
+// failing to remember a file isn't worth bothering the user about
+pub fn add(file_name: &str) {
+    let path = match fs::canonicalize(file_name) {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => return,
+    };
+    let mut files = read_all();
+    files.retain(|file| *file != path);
+    files.insert(0, path);
+    files.truncate(MAX_RECENT);
+
+    let recent = match recent_file() {
+        Some(recent) => recent,
+        None => return,
+    };
+    if let Some(dir) = recent.parent() {
+        if fs::create_dir_all(dir).is_err() {
+            return;
+        }
+    }
+    if let Err(error) = fs::write(&recent, files.join("\n") + "\n") {
+        info!("couldn't save recent files: {}", error);
+    }
+}
@@ -1,9 +1,19 @@
-use std::cmp;
+use crate::{HighlightState, Highlighting};
+use crate::SearchDirection;
+use crate::SyntaxDefinition;
 
+use std::ops::Range;
 use unicode_segmentation::UnicodeSegmentation;
 
+#[derive(Default)]
 pub struct Row {
     string: String,
+    // one entry per grapheme, filled in by highlight
+    highlighting: Vec<Highlighting>,
+    // the state the cached highlighting was worked out from
+    // None once the row has changed and needs highlighting again
+    highlighted_from: Option<HighlightState>,
+    ends_in: HighlightState,
     len: usize,
 }
 
@@ -11,6 +21,9 @@ impl From<&str> for Row {
     fn from(slice: &str) -> Self{ 
         let mut row = Self {
             string: String::from(slice),
+            highlighting: Vec::new(),
+            highlighted_from: None,
+            ends_in: HighlightState::Normal,
             len: 0,
         };
         row.update_len();
@@ -19,21 +32,43 @@ impl From<&str> for Row {
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
+    // start and end are screen columns, with tabs expanded out to the next tab stop
+    // a tab or control character cut off by either edge only draws the part of it
+    // that's on screen
+    pub fn render(&self, start: usize, end: usize, tab_stop: usize) -> String {
         let mut result = String::new();
-        for grapheme in self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            {
+        let mut current_highlighting = Highlighting::None;
+        let mut col = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if col >= end {
+                break;
+            }
+            let next_col = next_col(grapheme, col, tab_stop);
+            if next_col > start {
+                let caret = caret_notation(grapheme);
+                let highlighting = match caret {
+                    Some(_) => Highlighting::ControlChar,
+                    None => self.highlighting.get(index).copied().unwrap_or(Highlighting::None),
+                };
+                if highlighting != current_highlighting {
+                    result.push_str(&highlighting.switch_from(current_highlighting));
+                    current_highlighting = highlighting;
+                }
+                let visible = col.max(start) - col..next_col.min(end) - col;
                 if grapheme == "\t" {
-                    result.push_str(" ");
+                    result.push_str(&" ".repeat(visible.len()));
+                } else if let Some(caret) = caret {
+                    // printing the control character itself would mess up the terminal
+                    result.push_str(&caret[visible]);
                 } else {
                     result.push_str(grapheme)
                 }
             }
+            col = next_col;
+        }
+        if current_highlighting != Highlighting::None {
+            result.push_str(&Highlighting::None.switch_from(current_highlighting));
+        }
         result
     }
 
@@ -46,7 +81,658 @@ impl Row {
         self.len == 0
     }
 
+    // at is a grapheme index, not a byte index
+    pub fn insert(&mut self, at: usize, c: char) {
+        if at >= self.len() {
+            self.string.push(c);
+        } else {
+            let mut result: String = self.string[..].graphemes(true).take(at).collect();
+            let remainder: String = self.string[..].graphemes(true).skip(at).collect();
+            result.push(c);
+            result.push_str(&remainder);
+            self.string = result;
+        }
+        self.update_len();
+    }
+
+    pub fn delete(&mut self, at: usize) {
+        if at >= self.len() {
+            return;
+        }
+        let mut result: String = self.string[..].graphemes(true).take(at).collect();
+        let remainder: String = self.string[..].graphemes(true).skip(at + 1).collect();
+        result.push_str(&remainder);
+        self.string = result;
+        self.update_len();
+    }
+
+    // swaps the graphemes at and at + 1, if there are both
+    pub fn transpose(&mut self, at: usize) {
+        if at + 1 >= self.len() {
+            return;
+        }
+        let mut graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        graphemes.swap(at, at + 1);
+        self.string = graphemes.concat();
+        self.update_len();
+    }
+
+    // takes out terminal escape sequences, like the colours in pasted command
+    // output: CSI ones (ESC [ ... final byte), OSC ones up to BEL or ESC \,
+    // and any other ESC with the character after it
+    // returns false if there weren't any
+    pub fn strip_ansi(&mut self) -> bool {
+        if !self.string.contains('\x1b') {
+            return false;
+        }
+        let mut stripped = String::with_capacity(self.string.len());
+        let mut chars = self.string.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                stripped.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.string = stripped;
+        self.update_len();
+        true
+    }
+
+    pub fn append(&mut self, new: &Self) {
+        self.string = format!("{}{}", self.string, new.string);
+        self.update_len();
+    }
+
+    // everything from at onwards is moved into the returned row
+    pub fn split(&mut self, at: usize) -> Self {
+        let beginning: String = self.string[..].graphemes(true).take(at).collect();
+        let remainder: String = self.string[..].graphemes(true).skip(at).collect();
+        self.string = beginning;
+        self.update_len();
+        Self::from(&remainder[..])
+    }
+
+    // forwards looks from at to the end of the row, backwards from the start up to at
+    // returns the grapheme index of the match
+    pub fn find(
+        &self,
+        query: &str,
+        at: usize,
+        direction: SearchDirection,
+        ignore_case: bool,
+    ) -> Option<usize> {
+        if at > self.len || query.is_empty() {
+            return None;
+        }
+        let query: Vec<&str> = query.graphemes(true).collect();
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let matches_at = |index: &usize| {
+            graphemes[*index..*index + query.len()]
+                .iter()
+                .zip(&query)
+                .all(|(a, b)| graphemes_match(a, b, ignore_case))
+        };
+        match direction {
+            SearchDirection::Forward => {
+                let last_start = self.len.checked_sub(query.len())?;
+                (at..=last_start).find(matches_at)
+            }
+            SearchDirection::Backward => {
+                let last_start = at.checked_sub(query.len())?;
+                (0..=last_start).rev().find(matches_at)
+            }
+        }
+    }
+
+    // the whole row with tabs expanded out to the next tab stop,
+    // and control characters in caret notation
+    pub fn expanded(&self, tab_stop: usize) -> String {
+        let mut result = String::new();
+        let mut col = 0;
+        for grapheme in self.string[..].graphemes(true) {
+            let next = next_col(grapheme, col, tab_stop);
+            if grapheme == "\t" {
+                result.push_str(&" ".repeat(next - col));
+            } else if let Some(caret) = caret_notation(grapheme) {
+                result.push_str(&caret);
+            } else {
+                result.push_str(grapheme);
+            }
+            col = next;
+        }
+        result
+    }
+
+    // the graphemes start..end of the word that x is in (or just after)
+    pub fn word_at(&self, x: usize, word_chars: &str) -> Option<(usize, usize)> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let is_word = |g: &&str| is_word(g, word_chars);
+        let x = if graphemes.get(x).is_some_and(is_word) {
+            x
+        } else if x > 0 && graphemes.get(x - 1).is_some_and(is_word) {
+            x - 1
+        } else {
+            return None;
+        };
+        let start = graphemes[..x].iter().rposition(|g| !is_word(g)).map_or(0, |i| i + 1);
+        let end = graphemes[x..].iter().position(|g| !is_word(g)).map_or(graphemes.len(), |i| x + i);
+        Some((start, end))
+    }
+
+    // start..end of the words that are wholly inside a comment
+    pub fn comment_words(&self, word_chars: &str) -> Vec<(usize, usize)> {
+        let mut words = self.words(word_chars);
+        words.retain(|(start, end)| {
+            self.highlighting
+                .get(*start..*end)
+                .is_some_and(|word| word.iter().all(|h| *h == Highlighting::Comment))
+        });
+        words
+    }
+
+    // start..end of every word in the row, in order
+    pub fn words(&self, word_chars: &str) -> Vec<(usize, usize)> {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (x, grapheme) in self.string[..].graphemes(true).enumerate() {
+            match (is_word(grapheme, word_chars), start) {
+                (true, None) => start = Some(x),
+                (false, Some(from)) => {
+                    words.push((from, x));
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(from) = start {
+            words.push((from, self.len));
+        }
+        words
+    }
+
+    // The number under x or the first one after it, as the graphemes it covers
+    // and its value. A - in front counts as its sign unless it's stuck to a word,
+    // as in x-1, and 0x starts a hex number.
+    pub fn number_at(&self, x: usize) -> Option<(Range<usize>, i64)> {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let digit = |i: usize, radix: u32| graphemes.get(i).is_some_and(|g| g.len() == 1 && g.chars().all(|c| c.is_digit(radix)));
+        let mut i = 0;
+        while i < graphemes.len() {
+            if !digit(i, 10) {
+                i += 1;
+                continue;
+            }
+            let hex = graphemes[i] == "0" && matches!(graphemes.get(i + 1), Some(&"x") | Some(&"X")) && digit(i + 2, 16);
+            let (digits_start, radix) = if hex { (i + 2, 16) } else { (i, 10) };
+            let mut end = digits_start;
+            while digit(end, radix) {
+                end += 1;
+            }
+            let signed = !hex
+                && i > 0
+                && graphemes[i - 1] == "-"
+                && (i < 2 || !graphemes[i - 2].chars().all(char::is_alphanumeric));
+            let start = if signed { i - 1 } else { i };
+            if end > x {
+                let digits: String = graphemes[digits_start..end].concat();
+                let value = if hex {
+                    u64::from_str_radix(&digits, 16).ok()? as i64
+                } else {
+                    let value: i64 = digits.parse().ok()?;
+                    if signed { -value } else { value }
+                };
+                return Some((start..end, value));
+            }
+            i = end;
+        }
+        None
+    }
+
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        self.string[..].graphemes(true).skip(start).take(end.saturating_sub(start)).collect()
+    }
+
+    // how many screen columns the row takes up
+    pub fn display_len(&self, tab_stop: usize) -> usize {
+        self.char_to_display_col(self.len, tab_stop)
+    }
+
+    // the column grapheme x ends up in once tabs are expanded
+    pub fn char_to_display_col(&self, x: usize, tab_stop: usize) -> usize {
+        let mut col = 0;
+        for grapheme in self.string[..].graphemes(true).take(x) {
+            col = next_col(grapheme, col, tab_stop);
+        }
+        // past the end of the row, where virtual_edit lets the cursor go
+        col + x.saturating_sub(self.len)
+    }
+
+    // the first grapheme starting at or after the screen column col,
+    // or the end of the row if it doesn't reach that far
+    pub fn display_col_to_char(&self, col: usize, tab_stop: usize) -> usize {
+        let mut at = 0;
+        for (x, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if at >= col {
+                return x;
+            }
+            at = next_col(grapheme, at, tab_stop);
+        }
+        self.len
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.string.as_bytes()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    // the leading run of spaces and tabs
+    pub fn indentation(&self) -> &str {
+        let trimmed = self.string.trim_start_matches([' ', '\t']);
+        &self.string[..self.string.len() - trimmed.len()]
+    }
+
+    // how many columns the indentation takes up
+    pub fn indent_width(&self, tab_stop: usize) -> usize {
+        self.char_to_display_col(self.indentation().len(), tab_stop)
+    }
+
+    // whether the cached highlighting is still good for a row starting in state
+    pub fn is_highlighted(&self, state: HighlightState) -> bool {
+        self.highlighted_from == Some(state)
+    }
+
+    pub fn ends_in(&self) -> HighlightState {
+        self.ends_in
+    }
+
+    // forgets the cached highlighting, e.g. when the file type changes
+    pub fn clear_highlighting(&mut self) {
+        self.highlighted_from = None;
+    }
+
+    // too long to be worth highlighting, so it's drawn plain, and whatever
+    // it started in is taken to carry on past it
+    pub fn skip_highlighting(&mut self, starts_in: HighlightState) {
+        self.highlighting.clear();
+        self.highlighted_from = Some(starts_in);
+        self.ends_in = starts_in;
+    }
+
+    // highlights the row as if the row above it ended in starts_in
+    // returns what the next row starts in
+    pub fn highlight(
+        &mut self,
+        syntax: &SyntaxDefinition,
+        starts_in: HighlightState,
+    ) -> HighlightState {
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        // line by line languages like diffs don't carry anything on to the next row
+        if let Some(style) = syntax.line_style(&self.string) {
+            self.highlighting = vec![style; graphemes.len()];
+            self.highlighted_from = Some(starts_in);
+            self.ends_in = HighlightState::Normal;
+            return self.ends_in;
+        }
+        let mut highlighting = Vec::with_capacity(graphemes.len());
+        let mut index = 0;
+        let mut in_string = starts_in == HighlightState::String;
+        let mut in_comment = starts_in == HighlightState::BlockComment;
+
+        while index < graphemes.len() {
+            let grapheme = graphemes[index];
+
+            if in_comment {
+                if let Some((_, end)) = syntax.block_comment {
+                    if starts_with(&graphemes[index..], end) {
+                        let len = end.graphemes(true).count();
+                        highlighting.resize(highlighting.len() + len, Highlighting::Comment);
+                        index += len;
+                        in_comment = false;
+                        continue;
+                    }
+                }
+                highlighting.push(Highlighting::Comment);
+                index += 1;
+                continue;
+            }
+
+            if in_string {
+                // a backslash takes the next grapheme with it, so \" doesn't end the string
+                let len = if grapheme == "\\" { 2 } else { 1 };
+                let len = len.min(graphemes.len() - index);
+                highlighting.resize(highlighting.len() + len, Highlighting::String);
+                index += len;
+                if grapheme == "\"" {
+                    in_string = false;
+                }
+                continue;
+            }
+
+            let previous_is_separator = index == 0 || is_separator(graphemes[index - 1]);
+
+            if let Some((start, _)) = syntax.block_comment {
+                if starts_with(&graphemes[index..], start) {
+                    let len = start.graphemes(true).count();
+                    highlighting.resize(highlighting.len() + len, Highlighting::Comment);
+                    index += len;
+                    in_comment = true;
+                    continue;
+                }
+            }
+
+            if let Some(prefix) = syntax.line_comment {
+                if starts_with(&graphemes[index..], prefix) {
+                    highlighting.resize(graphemes.len(), Highlighting::Comment);
+                    break;
+                }
+            }
+
+            if syntax.strings && grapheme == "\"" {
+                highlighting.push(Highlighting::String);
+                index += 1;
+                in_string = true;
+                continue;
+            }
+
+            if syntax.characters && grapheme == "'" {
+                if let Some(len) = character_len(&graphemes[index..]) {
+                    highlighting.resize(highlighting.len() + len, Highlighting::Character);
+                    index += len;
+                    continue;
+                }
+            }
+
+            if syntax.numbers && previous_is_separator && is_digit(grapheme) {
+                let len = number_len(&graphemes[index..]);
+                highlighting.resize(highlighting.len() + len, Highlighting::Number);
+                index += len;
+                continue;
+            }
+
+            if previous_is_separator {
+                if let Some((len, keyword)) = find_keyword(&graphemes[index..], syntax) {
+                    highlighting.resize(highlighting.len() + len, keyword);
+                    index += len;
+                    continue;
+                }
+            }
+
+            highlighting.push(Highlighting::None);
+            index += 1;
+        }
+        self.highlighting = highlighting;
+        self.highlighted_from = Some(starts_in);
+        self.ends_in = if in_comment {
+            HighlightState::BlockComment
+        } else if in_string && syntax.multiline_strings {
+            HighlightState::String
+        } else {
+            HighlightState::Normal
+        };
+        self.ends_in
+    }
+
+    // every change to the row comes through here
     fn update_len(&mut self) {
-        self.len = self.string[..].graphemes(true).count()
+        self.len = self.string[..].graphemes(true).count();
+        self.highlighted_from = None;
+    }
+}
+
+fn is_separator(grapheme: &str) -> bool {
+    grapheme
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_punctuation() || c.is_whitespace())
+}
+
+fn is_digit(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(|c| c.is_ascii_digit())
+}
+
+// the length of a character literal like 'a' or '\n' at the start of graphemes
+// None if there's no closing quote, e.g. a rust lifetime
+fn character_len(graphemes: &[&str]) -> Option<usize> {
+    if graphemes.get(1) == Some(&"\\") {
+        // escapes like '\x41' and '\u{1F600}' are longer, but not by much
+        let closing = graphemes.iter().skip(3).take(10).position(|g| *g == "'")?;
+        Some(closing + 4)
+    } else if graphemes.get(2) == Some(&"'") {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+// the length of the number at the start of graphemes, which starts with a digit
+// handles 0x, 0o and 0b prefixes, _ separators, decimals, exponents
+// and type suffixes like u8 or f32
+fn number_len(graphemes: &[&str]) -> usize {
+    let is = |index: usize, test: &dyn Fn(char) -> bool| {
+        graphemes
+            .get(index)
+            .and_then(|grapheme| grapheme.chars().next())
+            .is_some_and(test)
+    };
+    let digits_from = |mut index: usize, test: &dyn Fn(char) -> bool| {
+        while is(index, &|c| test(c) || c == '_') {
+            index += 1;
+        }
+        index
+    };
+
+    let radix = match graphemes.get(1) {
+        Some(&"x") | Some(&"X") if graphemes[0] == "0" => Some(16),
+        Some(&"o") | Some(&"O") if graphemes[0] == "0" => Some(8),
+        Some(&"b") | Some(&"B") if graphemes[0] == "0" => Some(2),
+        _ => None,
+    };
+    let mut index = match radix {
+        Some(radix) => digits_from(2, &|c| c.is_digit(radix)),
+        None => {
+            let mut index = digits_from(0, &|c| c.is_ascii_digit());
+            // 1.5 but not the range in 1..2 or the method call in 1.max(2)
+            if is(index, &|c| c == '.') && is(index + 1, &|c| c.is_ascii_digit()) {
+                index = digits_from(index + 1, &|c| c.is_ascii_digit());
+            }
+            if is(index, &|c| c == 'e' || c == 'E') {
+                let sign = if is(index + 1, &|c| c == '+' || c == '-') { 1 } else { 0 };
+                if is(index + 1 + sign, &|c| c.is_ascii_digit()) {
+                    index = digits_from(index + 1 + sign, &|c| c.is_ascii_digit());
+                }
+            }
+            index
+        }
+    };
+    // anything alphanumeric stuck on the end is a suffix
+    while is(index, &|c| c.is_alphanumeric() || c == '_') {
+        index += 1;
+    }
+    index
+}
+
+fn starts_with(graphemes: &[&str], prefix: &str) -> bool {
+    let prefix: Vec<&str> = prefix.graphemes(true).collect();
+    graphemes.len() >= prefix.len() && graphemes[..prefix.len()] == prefix[..]
+}
+
+// The highlighting of line on its own, away from any document or terminal, for
+// checking what the highlighter makes of something. starts_in is what the
+// line above it left open, Normal for a line by itself, and what this line
+// leaves open comes back with it.
+pub fn highlight_line(
+    line: &str,
+    syntax: &SyntaxDefinition,
+    starts_in: HighlightState,
+) -> (Vec<Highlighting>, HighlightState) {
+    let mut row = Row::from(line);
+    let ends_in = row.highlight(syntax, starts_in);
+    (row.highlighting, ends_in)
+}
+
+// what counts as part of a word: letters, digits and whatever's in word_chars,
+// so everything that works by words agrees on where they end
+fn is_word(grapheme: &str, word_chars: &str) -> bool {
+    grapheme.chars().all(|c| c.is_alphanumeric() || word_chars.contains(c))
+}
+
+// the keyword at the start of graphemes, if it's a whole word
+fn find_keyword(graphemes: &[&str], syntax: &SyntaxDefinition) -> Option<(usize, Highlighting)> {
+    // keywords like #include start with a separator, so the first grapheme is
+    // always part of the word
+    let len = 1 + graphemes
+        .iter()
+        .skip(1)
+        .take_while(|grapheme| !is_separator(grapheme))
+        .count();
+    let word = graphemes[..len].concat();
+    if syntax.primary_keywords.contains(&&word[..]) {
+        Some((len, Highlighting::PrimaryKeyword))
+    } else if syntax.secondary_keywords.contains(&&word[..]) {
+        Some((len, Highlighting::SecondaryKeyword))
+    } else {
+        None
+    }
+}
+
+// the column just after grapheme when it starts at col: tabs go on to the
+// next tab stop and control characters take as many columns as caret_notation
+// has characters
+fn next_col(grapheme: &str, col: usize, tab_stop: usize) -> usize {
+    let tab_stop = tab_stop.max(1);
+    if grapheme == "\t" {
+        col + tab_stop - col % tab_stop
+    } else if let Some(caret) = caret_notation(grapheme) {
+        col + caret.len()
+    } else {
+        col + 1
+    }
+}
+
+// how a lone control character other than tab is shown, e.g. ^[ for Esc and ^? for Del
+// the C1 ones have no caret form, and some terminals act on them, so they're
+// shown as their code in hex, e.g. <85>
+fn caret_notation(grapheme: &str) -> Option<String> {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next()) {
+        (Some('\t'), None) => None,
+        (Some(c @ '\0'..='\x1f'), None) => Some(format!("^{}", (c as u8 + b'@') as char)),
+        (Some('\x7f'), None) => Some("^?".to_string()),
+        (Some(c @ '\u{80}'..='\u{9f}'), None) => Some(format!("<{:x}>", c as u32)),
+        _ => None,
+    }
+}
+
+fn graphemes_match(a: &str, b: &str, ignore_case: bool) -> bool {
+    a == b
+        || (ignore_case
+            && a.chars()
+                .flat_map(char::to_lowercase)
+                .eq(b.chars().flat_map(char::to_lowercase)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the runs of line that aren't plain text, as rust, with their text
+    fn runs(line: &str) -> Vec<(Highlighting, String)> {
+        let syntax = SyntaxDefinition::by_name("rust").expect("rust is built in");
+        let (highlighting, _) = highlight_line(line, &syntax, HighlightState::Normal);
+        let graphemes: Vec<&str> = line.graphemes(true).collect();
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for x in 1..=highlighting.len() {
+            if x == highlighting.len() || highlighting[x] != highlighting[start] {
+                if highlighting[start] != Highlighting::None {
+                    runs.push((highlighting[start], graphemes[start..x].concat()));
+                }
+                start = x;
+            }
+        }
+        runs
+    }
+
+    fn run(highlighting: Highlighting, text: &str) -> (Highlighting, String) {
+        (highlighting, text.to_string())
+    }
+
+    #[test]
+    fn a_let_binding_has_its_keyword_and_number_in_place() {
+        let syntax = SyntaxDefinition::by_name("rust").expect("rust is built in");
+        let (highlighting, ends_in) = highlight_line("let x = 42;", &syntax, HighlightState::Normal);
+        assert_eq!(highlighting.len(), 11);
+        for (x, highlighting) in highlighting.iter().enumerate() {
+            let expected = match x {
+                0..=2 => Highlighting::PrimaryKeyword,
+                8..=9 => Highlighting::Number,
+                _ => Highlighting::None,
+            };
+            assert_eq!(*highlighting, expected, "grapheme {}", x);
+        }
+        assert_eq!(ends_in, HighlightState::Normal);
+    }
+
+    #[test]
+    fn an_escaped_quote_does_not_end_the_string() {
+        assert_eq!(
+            runs(r#"f("a\"b", 1)"#),
+            [run(Highlighting::String, r#""a\"b""#), run(Highlighting::Number, "1")]
+        );
+        assert_eq!(runs(r#"f("\\", 2)"#), [run(Highlighting::String, r#""\\""#), run(Highlighting::Number, "2")]);
+        assert_eq!(runs(r"f('\'', '\n')"), [run(Highlighting::Character, r"'\''"), run(Highlighting::Character, r"'\n'")]);
+    }
+
+    #[test]
+    fn a_string_left_open_carries_on_to_the_next_row() {
+        let syntax = SyntaxDefinition::by_name("rust").expect("rust is built in");
+        let (_, ends_in) = highlight_line(r#"f("a\""#, &syntax, HighlightState::Normal);
+        assert_eq!(ends_in, HighlightState::String);
+    }
+
+    #[test]
+    fn number_literals() {
+        for number in ["0xFF_u8", "0b1010_0101", "0o777", "1_000_000", "1.5e-3_f64", "1_000.5e3"] {
+            assert_eq!(runs(&format!("f({})", number)), [run(Highlighting::Number, number)], "{}", number);
+        }
+    }
+
+    #[test]
+    fn dots_and_digits_that_are_not_part_of_a_number() {
+        assert_eq!(runs("f(x1)"), []);
+        assert_eq!(runs("f(1..2)"), [run(Highlighting::Number, "1"), run(Highlighting::Number, "2")]);
+        assert_eq!(runs("f(1.max(2))"), [run(Highlighting::Number, "1"), run(Highlighting::Number, "2")]);
+        // a tuple index is a number of its own, the dot before it isn't a decimal point
+        assert_eq!(runs("f(pair.0)"), [run(Highlighting::Number, "0")]);
+    }
+
+    #[test]
+    fn c1_controls_are_drawn_as_their_code() {
+        let row = Row::from("a\u{85}b");
+        assert_eq!(row.display_len(4), 6);
+        assert_eq!(row.char_to_display_col(2, 4), 5);
+        let mut drawn = Row::from(&row.render(0, 10, 4)[..]);
+        drawn.strip_ansi();
+        assert_eq!(drawn.as_str(), "a<85>b");
     }
 }
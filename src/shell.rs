@@ -0,0 +1,94 @@
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+
+// A command run with sh, fed input, with everything it prints being taken as
+// it goes. Both ends are on threads of their own so a command that prints a
+// lot can't fill a pipe and stall, and whoever started it can get on with
+// other things, asking is_done now and then.
+pub struct Running {
+    child: Child,
+    stdout: thread::JoinHandle<Vec<u8>>,
+    stderr: thread::JoinHandle<Vec<u8>>,
+}
+
+pub fn spawn(command: &str, input: &str) -> io::Result<Running> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // a command that doesn't read all of it just gets a broken pipe here
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = input.to_string();
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    Ok(Running { child, stdout, stderr })
+}
+
+impl Running {
+    pub fn is_done(&mut self) -> io::Result<bool> {
+        Ok(self.child.try_wait()?.is_some())
+    }
+
+    // waits for it first if it hasn't finished
+    pub fn output(mut self) -> io::Result<Output> {
+        let status = self.child.wait()?;
+        Ok(Output {
+            status,
+            stdout: self.stdout.join().unwrap_or_default(),
+            stderr: self.stderr.join().unwrap_or_default(),
+        })
+    }
+
+    pub fn kill(mut self) -> io::Result<()> {
+        self.child.kill()?;
+        self.child.wait()?;
+        Ok(())
+    }
+}
+
+fn read_all<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn a_command_gets_its_input_and_both_outputs_come_back() {
+        let running = spawn("tr a-z A-Z; echo oops >&2; exit 3", "shout\n").unwrap();
+        let output = running.output().unwrap();
+        assert_eq!(output.stdout, b"SHOUT\n");
+        assert_eq!(output.stderr, b"oops\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn a_command_runs_on_until_it_is_done_or_killed() {
+        let mut finishes = spawn("true", "").unwrap();
+        let started = Instant::now();
+        while !finishes.is_done().unwrap() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut sleeps = spawn("sleep 60", "").unwrap();
+        assert!(!sleeps.is_done().unwrap());
+        sleeps.kill().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
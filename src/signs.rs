@@ -0,0 +1,45 @@
+use termion::color;
+use unicode_segmentation::UnicodeSegmentation;
+
+// a mark drawn in the column between the line numbers and the text
+#[derive(Clone)]
+pub struct Sign {
+    // the row it sits next to, from 0
+    pub line: usize,
+    pub glyph: String,
+    pub color: color::Rgb,
+}
+
+// Signs are kept by the feature that placed them, so each one can replace
+// its own without touching anyone else's. Where two land on the same row,
+// the group that was set first wins.
+#[derive(Default)]
+pub struct Signs {
+    groups: Vec<(&'static str, Vec<Sign>)>,
+}
+
+impl Signs {
+    pub fn set(&mut self, group: &'static str, signs: Vec<Sign>) {
+        match self.groups.iter_mut().find(|(name, _)| *name == group) {
+            Some((_, existing)) => *existing = signs,
+            None => self.groups.push((group, signs)),
+        }
+    }
+
+    pub fn at(&self, line: usize) -> Option<&Sign> {
+        self.groups
+            .iter()
+            .flat_map(|(_, signs)| signs.iter())
+            .find(|sign| sign.line == line)
+    }
+
+    // as wide as the widest glyph, and not there at all without any signs
+    pub fn width(&self) -> usize {
+        self.groups
+            .iter()
+            .flat_map(|(_, signs)| signs.iter())
+            .map(|sign| sign.glyph.graphemes(true).count())
+            .max()
+            .unwrap_or(0)
+    }
+}
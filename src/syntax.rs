@@ -0,0 +1,186 @@
+use crate::Highlighting;
+use std::path::Path;
+
+// how to highlight one language
+// the registry below is searched by file extension, anything unknown is plain
+#[derive(Clone, Debug)]
+pub struct SyntaxDefinition {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    // a file starting with one of these is in this language whatever it's called
+    pub first_lines: &'static [&'static str],
+    pub numbers: bool,
+    pub strings: bool,
+    // whether a string left open carries on to the next row
+    pub multiline_strings: bool,
+    pub characters: bool,
+    pub line_comment: Option<&'static str>,
+    // the start and end delimiters, these can span rows
+    pub block_comment: Option<(&'static str, &'static str)>,
+    // openers and their closers, Enter after an opener indents the next row
+    pub blocks: &'static [(char, char)],
+    pub primary_keywords: &'static [&'static str],
+    pub secondary_keywords: &'static [&'static str],
+    // rows starting with one of these are coloured whole, instead of word by word,
+    // the first prefix that matches wins
+    pub line_styles: &'static [(&'static str, Highlighting)],
+}
+
+impl Default for SyntaxDefinition {
+    fn default() -> Self {
+        PLAIN.clone()
+    }
+}
+
+const PLAIN: SyntaxDefinition = SyntaxDefinition {
+    name: "plain",
+    extensions: &[],
+    first_lines: &[],
+    numbers: false,
+    strings: false,
+    multiline_strings: false,
+    characters: false,
+    line_comment: None,
+    block_comment: None,
+    blocks: &[],
+    primary_keywords: &[],
+    secondary_keywords: &[],
+    line_styles: &[],
+};
+
+const DEFINITIONS: &[SyntaxDefinition] = &[
+    SyntaxDefinition {
+        name: "rust",
+        extensions: &["rs"],
+        first_lines: &[],
+        numbers: true,
+        strings: true,
+        multiline_strings: true,
+        characters: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        blocks: &[('{', '}'), ('(', ')'), ('[', ']')],
+        primary_keywords: &[
+            "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn",
+            "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+            "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+            "unsafe", "use", "where", "while", "dyn", "async", "await",
+        ],
+        secondary_keywords: &[
+            "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64",
+            "u128", "usize", "f32", "f64", "str", "String", "Vec", "Option", "Result", "Some",
+            "None", "Ok", "Err", "Box",
+        ],
+        line_styles: &[],
+    },
+    SyntaxDefinition {
+        name: "c",
+        extensions: &["c", "h", "cpp", "hpp", "cc"],
+        first_lines: &[],
+        numbers: true,
+        strings: true,
+        multiline_strings: false,
+        characters: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        blocks: &[('{', '}'), ('(', ')'), ('[', ']')],
+        primary_keywords: &[
+            "break", "case", "const", "continue", "default", "do", "else", "enum", "extern",
+            "for", "goto", "if", "return", "sizeof", "static", "struct", "switch", "typedef",
+            "union", "volatile", "while", "#include", "#define", "#ifdef", "#ifndef", "#endif",
+        ],
+        secondary_keywords: &[
+            "char", "double", "float", "int", "long", "short", "signed", "unsigned", "void",
+            "size_t", "bool",
+        ],
+        line_styles: &[],
+    },
+    SyntaxDefinition {
+        name: "python",
+        extensions: &["py"],
+        first_lines: &[],
+        numbers: true,
+        strings: true,
+        multiline_strings: false,
+        characters: false,
+        line_comment: Some("#"),
+        block_comment: None,
+        blocks: &[('(', ')'), ('[', ']'), ('{', '}')],
+        primary_keywords: &[
+            "and", "as", "assert", "break", "class", "continue", "def", "del", "elif", "else",
+            "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+            "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+        ],
+        secondary_keywords: &["True", "False", "None", "self", "int", "str", "list", "dict"],
+        line_styles: &[],
+    },
+    SyntaxDefinition {
+        name: "diff",
+        extensions: &["diff", "patch"],
+        first_lines: &["diff ", "--- ", "Index: "],
+        numbers: false,
+        strings: false,
+        multiline_strings: false,
+        characters: false,
+        line_comment: None,
+        block_comment: None,
+        blocks: &[],
+        primary_keywords: &[],
+        secondary_keywords: &[],
+        line_styles: &[
+            ("diff ", Highlighting::FileHeader),
+            ("index ", Highlighting::FileHeader),
+            ("--- ", Highlighting::FileHeader),
+            ("+++ ", Highlighting::FileHeader),
+            ("@@", Highlighting::Hunk),
+            ("+", Highlighting::Added),
+            ("-", Highlighting::Removed),
+        ],
+    },
+];
+
+impl SyntaxDefinition {
+    pub fn for_file(file_name: &str) -> Self {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        DEFINITIONS
+            .iter()
+            .find(|definition| definition.extensions.contains(&extension))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // by the file's name, or failing that by its first line
+    pub fn detect(file_name: &str, first_line: &str) -> Self {
+        let by_name = Self::for_file(file_name);
+        if by_name.name != PLAIN.name {
+            return by_name;
+        }
+        DEFINITIONS
+            .iter()
+            .find(|definition| definition.first_lines.iter().any(|start| first_line.starts_with(start)))
+            .cloned()
+            .unwrap_or(by_name)
+    }
+
+    // the whole row's colour, if it gets one
+    pub fn line_style(&self, line: &str) -> Option<Highlighting> {
+        self.line_styles
+            .iter()
+            .find(|(prefix, _)| line.starts_with(prefix))
+            .map(|(_, highlighting)| *highlighting)
+    }
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|definition| definition.name == name)
+    }
+
+    // every definition, plain included
+    pub fn all() -> Vec<Self> {
+        let mut all = vec![PLAIN.clone()];
+        all.extend(DEFINITIONS.iter().cloned());
+        all
+    }
+}
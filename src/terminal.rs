@@ -1,91 +1,337 @@
 use crate::Position;
 use log::info;
-use std::io::{self, stdout, Write};
+use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::io::{self, stdout, Read, Stdout, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
 use termion::{
-    color,
-    event::Key,
+    color, cursor,
+    event::{Key, MouseEvent},
     input::TermRead,
     raw::{IntoRawMode, RawTerminal},
+    screen::AlternateScreen,
+    style,
 };
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CursorShape {
+    // whatever the terminal was using before we started
+    Default,
+    Block,
+    Underline,
+    Bar,
+}
+
+impl std::str::FromStr for CursorShape {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "default" => Ok(CursorShape::Default),
+            "block" => Ok(CursorShape::Block),
+            "underline" => Ok(CursorShape::Underline),
+            "bar" => Ok(CursorShape::Bar),
+            _ => Err(()),
+        }
+    }
+}
+
+// DECSCUSR, terminals that don't know it just ignore it
+impl fmt::Display for CursorShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match self {
+            CursorShape::Default => 0,
+            CursorShape::Block => 2,
+            CursorShape::Underline => 4,
+            CursorShape::Bar => 6,
+        };
+        write!(f, "\x1b[{} q", code)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
-pub struct Terminal {
-    size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+// how often the size is checked for the terminal being resized, there's no
+// catching SIGWINCH without a crate for signals
+const RESIZE_POLL: Duration = Duration::from_millis(250);
+
+// Everything the main loop waits on. The terminal's own events each come in
+// on a thread of their own, so anything else that wants waking up for only
+// has to send on the same channel.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Key(Key),
+    Mouse(MouseEvent),
+    // the terminal is this big now, size() already says so by the time this is read
+    Resize(Size),
+    // the deadline read_event_until was given came before anything else did
+    Tick,
 }
 
-const FOOTER_SIZE: u16 = 2;
+pub struct Terminal {
+    size: Cell<Size>,
+    out: RefCell<AlternateScreen<RawTerminal<Stdout>>>,
+    // events come in on their own threads so we can stop waiting for one
+    events: Receiver<Result<Event, std::io::Error>>,
+}
 
 impl Terminal {
+    // can't be the Default trait since setting up the terminal can fail
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Result<Self, std::io::Error> {
         let size = termion::terminal_size()?;
 
+        // with stdin taken up by a pipe, keys come from the terminal itself
+        let input: Box<dyn Read + Send> = if termion::is_tty(&io::stdin()) {
+            Box::new(io::stdin())
+        } else {
+            Box::new(termion::get_tty()?)
+        };
         // size is a tuple
         // size.0 is width, size.1 is height
+        let size = Size {
+            width: size.0,
+            height: size.1,
+        };
+        let (sender, events) = mpsc::channel();
+        let key_sender = sender.clone();
+        thread::spawn(move || {
+            for event in input.events() {
+                let event = match event {
+                    Ok(termion::event::Event::Key(key)) => Ok(Event::Key(key)),
+                    Ok(termion::event::Event::Mouse(mouse)) => Ok(Event::Mouse(mouse)),
+                    Ok(termion::event::Event::Unsupported(_)) => continue,
+                    Err(error) => Err(error),
+                };
+                if key_sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        thread::spawn(move || {
+            let mut last = size;
+            loop {
+                thread::sleep(RESIZE_POLL);
+                let now = match termion::terminal_size() {
+                    Ok((width, height)) => Size { width, height },
+                    Err(_) => continue,
+                };
+                if now != last {
+                    last = now;
+                    if sender.send(Ok(Event::Resize(now))).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
         Ok(Self {
-            size: Size {
-                width: size.0,
-                height: size.1.saturating_sub(FOOTER_SIZE),
-            },
-            _stdout: stdout().into_raw_mode().unwrap(),
+            size: Cell::new(size),
+            out: RefCell::new(AlternateScreen::from(stdout().into_raw_mode()?)),
+            events,
         })
     }
 
-    pub fn size(&self) -> &Size {
-        &self.size
+    pub fn size(&self) -> Size {
+        self.size.get()
+    }
+
+    // nothing gets printed straight to stdout, so all output is buffered the
+    // same way and only reaches the terminal when flush is called
+    pub fn write(&self, text: &str) -> Result<(), std::io::Error> {
+        self.out.borrow_mut().write_all(text.as_bytes())
     }
 
-    pub fn clear_screen() {
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        self.out.borrow_mut().flush()
+    }
+
+    pub fn clear_screen(&self) -> Result<(), std::io::Error> {
         info!("clearing");
-        print!("{}", termion::clear::All);
+        self.write(termion::clear::All.as_ref())
     }
 
-    pub fn cursor_position(position: &Position) {
+    pub fn cursor_position(&self, position: &Position) -> Result<(), std::io::Error> {
         let Position { mut x, mut y } = position;
         x = x.saturating_add(1);
         y = y.saturating_add(1);
         let x = x as u16;
         let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+        self.write(&termion::cursor::Goto(x, y).to_string())
+    }
+
+    pub fn set_cursor_shape(&self, shape: CursorShape) -> Result<(), std::io::Error> {
+        self.write(&shape.to_string())
+    }
+
+    pub fn bell(&self) -> Result<(), std::io::Error> {
+        self.write("\x07")?;
+        self.flush()
+    }
+
+    // the whole screen in reverse video (DECSCNM), until it's turned off again
+    pub fn set_inverted(&self, inverted: bool) -> Result<(), std::io::Error> {
+        self.write(if inverted { "\x1b[?5h" } else { "\x1b[?5l" })?;
+        self.flush()
+    }
+
+    // button presses and the wheel come in as Event::Mouse, in the SGR
+    // encoding so columns past 223 still work
+    pub fn set_mouse_reporting(&self, on: bool) -> Result<(), std::io::Error> {
+        self.write(if on { "\x1b[?1000h\x1b[?1006h" } else { "\x1b[?1006l\x1b[?1000l" })?;
+        self.flush()
     }
 
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
+    // OSC 52, asks the terminal to put text on the system clipboard
+    // terminals that don't allow it just ignore it
+    pub fn set_clipboard(&self, text: &str) -> Result<(), std::io::Error> {
+        self.write(&format!("\x1b]52;c;{}\x07", base64(text.as_bytes())))?;
+        self.flush()
     }
 
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn clear_current_line(&self) -> Result<(), std::io::Error> {
+        self.write(termion::clear::CurrentLine.as_ref())
     }
 
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    pub fn read_event(&self) -> Result<Event, std::io::Error> {
+        match self.events.recv() {
+            Ok(event) => self.received(event),
+            Err(_) => Err(closed_input()),
+        }
+    }
+
+    // an event that has already arrived, without waiting for one
+    pub fn try_read_event(&self) -> Result<Option<Event>, std::io::Error> {
+        match self.events.try_recv() {
+            Ok(event) => self.received(event).map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(closed_input()),
+        }
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    // like read_event, but a Tick once deadline passes if nothing came in first
+    pub fn read_event_until(&self, deadline: Option<Instant>) -> Result<Event, std::io::Error> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return self.read_event(),
+        };
+        match self.events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(event) => self.received(event),
+            Err(RecvTimeoutError::Timeout) => Ok(Event::Tick),
+            Err(RecvTimeoutError::Disconnected) => Err(closed_input()),
+        }
     }
 
-    pub fn read_key() -> Result<Key, std::io::Error> {
+    // The key-only reads are for the places that only ever want a key, like
+    // prompts. Anything else that comes in meanwhile is passed over, though a
+    // resize still takes effect.
+    pub fn read_key(&self) -> Result<Key, std::io::Error> {
         loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+            if let Event::Key(key) = self.read_event()? {
+                return Ok(key);
             }
         }
     }
 
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    pub fn try_read_key(&self) -> Result<Option<Key>, std::io::Error> {
+        while let Some(event) = self.try_read_event()? {
+            if let Event::Key(key) = event {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
     }
 
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset))
+    fn received(&self, event: Result<Event, std::io::Error>) -> Result<Event, std::io::Error> {
+        if let Ok(Event::Resize(size)) = event {
+            info!("resized to {}x{}", size.width, size.height);
+            self.size.set(size);
+        }
+        event
     }
+}
+
+// out's own drops leave the alternate screen and raw mode after this,
+// what's left to put back is whatever we changed that the terminal keeps
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = restore(&mut *self.out.borrow_mut());
+    }
+}
 
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset))
+// inverse video, mouse reporting, colours, the cursor's shape and visibility
+fn restore<W: Write>(out: &mut W) -> Result<(), std::io::Error> {
+    write!(
+        out,
+        "\x1b[?5l\x1b[?1006l\x1b[?1000l{}{}{}{}{}",
+        color::Fg(color::Reset),
+        color::Bg(color::Reset),
+        style::Reset,
+        CursorShape::Default,
+        cursor::Show
+    )?;
+    out.flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn closed_input() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "input closed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    // somewhere to write that can still be read once the screen's dropped
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn the_terminal_is_put_back_before_the_alternate_screen_is_left() {
+        let written = Shared::default();
+        {
+            // the same order Terminal's Drop and then its out field's run in
+            let mut screen = AlternateScreen::from(written.clone());
+            restore(&mut screen).unwrap();
+        }
+        let written = String::from_utf8(written.0.borrow().clone()).unwrap();
+        let entered = written.find("\x1b[?1049h").unwrap();
+        let restored = written.find("\x1b[?1000l").unwrap();
+        let shown = written.find(&cursor::Show.to_string()).unwrap();
+        let left = written.rfind("\x1b[?1049l").unwrap();
+        assert!(entered < restored && restored < shown && shown < left, "{:?}", written);
+        assert!(written.ends_with("\x1b[?1049l"), "{:?}", written);
     }
 }
@@ -0,0 +1,7 @@
+// open in milli to check the highlighting of tricky literals
+let escaped = "a\"b"; let after = 1;
+let newline = "line\n"; let quote = '\''; let tab = '\t';
+let hex = 0xFF_u8; let binary = 0b1010_1010; let octal = 0o777;
+let float = 1.5e-3_f64; let million = 1_000_000; let tuple = pair.0;
+let range = 1..2; let method = 1.max(2); let lifetime: &'a str = "x";
+let unicode = '\u{1F600}'; let byte = '\x41';